@@ -12,14 +12,14 @@ INSERT INTO sample_type (id,name,variant ) VALUES
 	 (1,'Trace 1',"100mL"),
 	 (2,'Trace 2',"200mL");
 
-INSERT INTO people (id,name) VALUES
-	 (1,'USER 1'),
-	 (2,'USER 2'),
-	 (3,'USER 3');
-
-INSERT INTO task (id,done,serial,well_id,"depth") VALUES
-	 (1,true,'GW-1',1,'10'),
-	 (2,false,'SW-1',2,'R');
+INSERT INTO people (id,name,"role") VALUES
+	 (1,'USER 1','data_manager'),
+	 (2,'USER 2','field_tech'),
+	 (3,'USER 3','viewer');
+
+INSERT INTO task (id,done,serial,well_id,"depth",status) VALUES
+	 (1,true,'GW-1',1,'10','sampled'),
+	 (2,false,'SW-1',2,'R','planned');
 
 INSERT INTO sample_set (task_id,sample_type_id,qty) VALUES
 	 (1,1,1),
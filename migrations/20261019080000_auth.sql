@@ -0,0 +1,23 @@
+-- Add migration script here
+ALTER TABLE people ADD COLUMN role TEXT NOT NULL DEFAULT 'field_tech'
+CHECK (role IN ('viewer', 'field_tech', 'data_manager'));
+
+ALTER TABLE people ADD COLUMN secret_hash TEXT;
+
+CREATE TABLE session (
+    token_hash TEXT PRIMARY KEY,
+    people_id INTEGER NOT NULL,
+    created_at DATETIME NOT NULL,
+    expires_at DATETIME NOT NULL,
+    FOREIGN KEY (people_id) REFERENCES people (id) ON DELETE CASCADE
+);
+
+CREATE TABLE api_token (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    people_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    token_hash TEXT UNIQUE NOT NULL,
+    created_at DATETIME NOT NULL,
+    last_used_at DATETIME,
+    FOREIGN KEY (people_id) REFERENCES people (id) ON DELETE CASCADE
+);
@@ -0,0 +1,15 @@
+-- Add migration script here
+ALTER TABLE task ADD COLUMN finalized_at DATETIME;
+
+ALTER TABLE task ADD COLUMN finalized_by INTEGER REFERENCES people (id);
+
+CREATE TABLE task_finalize_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL,
+    action TEXT NOT NULL CHECK (action IN ('finalize', 'reopen')),
+    people_id INTEGER NOT NULL,
+    "timestamp" DATETIME NOT NULL,
+    reason TEXT,
+    FOREIGN KEY (task_id) REFERENCES task (id) ON DELETE CASCADE,
+    FOREIGN KEY (people_id) REFERENCES people (id)
+);
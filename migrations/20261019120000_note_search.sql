@@ -0,0 +1,136 @@
+-- Add migration script here
+CREATE VIRTUAL TABLE note_search USING fts5 (
+    body,
+    source UNINDEXED,
+    field UNINDEXED,
+    source_id UNINDEXED,
+    task_id UNINDEXED,
+    tokenize = 'porter unicode61'
+);
+
+-- task_info
+
+CREATE TRIGGER note_search_task_info_insert AFTER INSERT ON task_info
+BEGIN
+    INSERT INTO note_search (body, source, field, source_id, task_id)
+    SELECT body, 'task_info', field, new.id, new.task_id
+    FROM (
+        SELECT new.comment AS body, 'comment' AS field
+        UNION ALL
+        SELECT new.calibration, 'calibration'
+        UNION ALL
+        SELECT new.hose_setup, 'hose_setup'
+    )
+    WHERE body IS NOT NULL AND body != '';
+END;
+
+CREATE TRIGGER note_search_task_info_update AFTER UPDATE OF
+comment, calibration, hose_setup, task_id ON task_info
+BEGIN
+    DELETE FROM note_search
+    WHERE source = 'task_info' AND source_id = old.id;
+
+    INSERT INTO note_search (body, source, field, source_id, task_id)
+    SELECT body, 'task_info', field, new.id, new.task_id
+    FROM (
+        SELECT new.comment AS body, 'comment' AS field
+        UNION ALL
+        SELECT new.calibration, 'calibration'
+        UNION ALL
+        SELECT new.hose_setup, 'hose_setup'
+    )
+    WHERE body IS NOT NULL AND body != '';
+END;
+
+CREATE TRIGGER note_search_task_info_delete AFTER DELETE ON task_info
+BEGIN
+    DELETE FROM note_search
+    WHERE source = 'task_info' AND source_id = old.id;
+END;
+
+-- well, pump and sample_type comments
+
+CREATE TRIGGER note_search_well_insert AFTER INSERT ON well
+WHEN new.comment IS NOT NULL AND new.comment != ''
+BEGIN
+    INSERT INTO note_search (body, source, field, source_id)
+    VALUES (new.comment, 'well', 'comment', new.id);
+END;
+
+CREATE TRIGGER note_search_well_update AFTER UPDATE OF comment ON well
+BEGIN
+    DELETE FROM note_search WHERE source = 'well' AND source_id = old.id;
+    INSERT INTO note_search (body, source, field, source_id)
+    SELECT new.comment, 'well', 'comment', new.id
+    WHERE new.comment IS NOT NULL AND new.comment != '';
+END;
+
+CREATE TRIGGER note_search_well_delete AFTER DELETE ON well
+BEGIN
+    DELETE FROM note_search WHERE source = 'well' AND source_id = old.id;
+END;
+
+CREATE TRIGGER note_search_pump_insert AFTER INSERT ON pump
+WHEN new.comment IS NOT NULL AND new.comment != ''
+BEGIN
+    INSERT INTO note_search (body, source, field, source_id)
+    VALUES (new.comment, 'pump', 'comment', new.id);
+END;
+
+CREATE TRIGGER note_search_pump_update AFTER UPDATE OF comment ON pump
+BEGIN
+    DELETE FROM note_search WHERE source = 'pump' AND source_id = old.id;
+    INSERT INTO note_search (body, source, field, source_id)
+    SELECT new.comment, 'pump', 'comment', new.id
+    WHERE new.comment IS NOT NULL AND new.comment != '';
+END;
+
+CREATE TRIGGER note_search_pump_delete AFTER DELETE ON pump
+BEGIN
+    DELETE FROM note_search WHERE source = 'pump' AND source_id = old.id;
+END;
+
+CREATE TRIGGER note_search_sample_type_insert AFTER INSERT ON sample_type
+WHEN new.comment IS NOT NULL AND new.comment != ''
+BEGIN
+    INSERT INTO note_search (body, source, field, source_id)
+    VALUES (new.comment, 'sample_type', 'comment', new.id);
+END;
+
+CREATE TRIGGER note_search_sample_type_update AFTER UPDATE OF comment ON sample_type
+BEGIN
+    DELETE FROM note_search
+    WHERE source = 'sample_type' AND source_id = old.id;
+    INSERT INTO note_search (body, source, field, source_id)
+    SELECT new.comment, 'sample_type', 'comment', new.id
+    WHERE new.comment IS NOT NULL AND new.comment != '';
+END;
+
+CREATE TRIGGER note_search_sample_type_delete AFTER DELETE ON sample_type
+BEGIN
+    DELETE FROM note_search
+    WHERE source = 'sample_type' AND source_id = old.id;
+END;
+
+-- Index existing notes
+
+INSERT INTO note_search (body, source, field, source_id, task_id)
+SELECT body, 'task_info', field, id, task_id
+FROM (
+    SELECT id, task_id, comment AS body, 'comment' AS field FROM task_info
+    UNION ALL
+    SELECT id, task_id, calibration, 'calibration' FROM task_info
+    UNION ALL
+    SELECT id, task_id, hose_setup, 'hose_setup' FROM task_info
+)
+WHERE body IS NOT NULL AND body != '';
+
+INSERT INTO note_search (body, source, field, source_id)
+SELECT comment, 'well', 'comment', id FROM well
+WHERE comment IS NOT NULL AND comment != ''
+UNION ALL
+SELECT comment, 'pump', 'comment', id FROM pump
+WHERE comment IS NOT NULL AND comment != ''
+UNION ALL
+SELECT comment, 'sample_type', 'comment', id FROM sample_type
+WHERE comment IS NOT NULL AND comment != '';
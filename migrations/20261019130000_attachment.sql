@@ -0,0 +1,19 @@
+-- Add migration script here
+CREATE TABLE attachment (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_info_id INTEGER NOT NULL,
+    file_name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    thumbnail BLOB,
+    taken_at DATETIME,
+    latitude REAL,
+    longitude REAL,
+    uploaded_by INTEGER NOT NULL,
+    uploaded_at DATETIME NOT NULL,
+    FOREIGN KEY (task_info_id) REFERENCES task_info (id) ON DELETE CASCADE,
+    FOREIGN KEY (uploaded_by) REFERENCES people (id)
+);
+
+CREATE INDEX attachment_task_info_id ON attachment (task_info_id);
@@ -0,0 +1,21 @@
+-- Add migration script here
+-- Lengths are in metres, depths are measured below top of casing.
+ALTER TABLE well ADD COLUMN x REAL;
+
+ALTER TABLE well ADD COLUMN y REAL;
+
+ALTER TABLE well ADD COLUMN crs TEXT NOT NULL DEFAULT 'EPSG:4326';
+
+ALTER TABLE well ADD COLUMN ground_elevation REAL;
+
+ALTER TABLE well ADD COLUMN casing_top_elevation REAL;
+
+ALTER TABLE well ADD COLUMN casing_diameter REAL;
+
+ALTER TABLE well ADD COLUMN total_depth REAL;
+
+ALTER TABLE well ADD COLUMN screen_top REAL;
+
+ALTER TABLE well ADD COLUMN screen_bottom REAL;
+
+ALTER TABLE well ADD COLUMN aquifer TEXT;
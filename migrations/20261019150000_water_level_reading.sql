@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Water level readings taken while purging, depths in metres below the reference point.
+CREATE TABLE water_level_reading (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL,
+    measured_at DATETIME NOT NULL,
+    depth_to_water REAL NOT NULL,
+    reference_point TEXT NOT NULL DEFAULT 'TOC',
+    people_id INTEGER,
+    FOREIGN KEY (task_id) REFERENCES task (id) ON DELETE CASCADE,
+    FOREIGN KEY (people_id) REFERENCES people (id)
+);
+
+CREATE INDEX water_level_reading_task_id ON water_level_reading (task_id, measured_at);
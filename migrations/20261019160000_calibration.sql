@@ -0,0 +1,26 @@
+-- Add migration script here
+ALTER TABLE task ADD COLUMN instrument_serial TEXT;
+
+-- One row per calibrated parameter. A calibration passes when the post-calibration
+-- reading is within tolerance of the standard, unless passed is set explicitly.
+CREATE TABLE calibration (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    instrument_serial TEXT NOT NULL,
+    probe TEXT,
+    parameter TEXT NOT NULL,
+    standard_value REAL,
+    standard_unit TEXT,
+    standard_lot TEXT,
+    standard_expiry DATE,
+    pre_reading REAL,
+    post_reading REAL,
+    tolerance REAL,
+    passed BOOLEAN,
+    calibrated_at DATETIME NOT NULL,
+    valid_until DATETIME,
+    calibrated_by INTEGER,
+    comment TEXT,
+    FOREIGN KEY (calibrated_by) REFERENCES people (id)
+);
+
+CREATE INDEX calibration_instrument_serial ON calibration (instrument_serial, calibrated_at);
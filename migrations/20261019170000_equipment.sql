@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE equipment (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    equipment_type TEXT NOT NULL,
+    name TEXT NOT NULL,
+    serial TEXT UNIQUE,
+    status TEXT NOT NULL DEFAULT 'in_service',
+    service_due DATE,
+    calibration_due DATE,
+    comment TEXT
+);
+
+CREATE TABLE task_equipment (
+    task_id INTEGER NOT NULL,
+    equipment_id INTEGER NOT NULL,
+    FOREIGN KEY (task_id) REFERENCES task (id) ON DELETE CASCADE,
+    FOREIGN KEY (equipment_id) REFERENCES equipment (id) ON DELETE CASCADE,
+    PRIMARY KEY (task_id, equipment_id)
+);
+
+CREATE INDEX task_equipment_equipment_id ON task_equipment (equipment_id);
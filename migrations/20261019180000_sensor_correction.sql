@@ -0,0 +1,23 @@
+-- Add migration script here
+-- Corrections are applied when sensor data is read, sensor_data keeps the raw values.
+-- The correction changes linearly from start_offset at start_time to end_offset at end_time,
+-- a constant offset has both equal.
+CREATE TABLE sensor_correction (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL,
+    parameter TEXT NOT NULL,
+    start_offset REAL NOT NULL,
+    end_offset REAL NOT NULL,
+    start_time DATETIME NOT NULL,
+    end_time DATETIME NOT NULL,
+    comment TEXT,
+    created_by INTEGER NOT NULL,
+    created_at DATETIME NOT NULL,
+    reverted_by INTEGER,
+    reverted_at DATETIME,
+    FOREIGN KEY (task_id) REFERENCES task (id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES people (id),
+    FOREIGN KEY (reverted_by) REFERENCES people (id)
+);
+
+CREATE INDEX sensor_correction_task_id ON sensor_correction (task_id);
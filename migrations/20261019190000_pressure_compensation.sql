@@ -0,0 +1,42 @@
+-- Add migration script here
+-- Results of the barometric compensation, in metres. The logged depth is kept as is.
+ALTER TABLE sensor_data ADD COLUMN water_column REAL;
+
+ALTER TABLE sensor_data ADD COLUMN depth_to_water REAL;
+
+ALTER TABLE sensor_data ADD COLUMN water_elevation REAL;
+
+-- Barometric pressure logged by a separate logger, pressure in PSI.
+CREATE TABLE baro_series (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    comment TEXT,
+    uploaded_by INTEGER NOT NULL,
+    uploaded_at DATETIME NOT NULL,
+    FOREIGN KEY (uploaded_by) REFERENCES people (id)
+);
+
+CREATE TABLE baro_reading (
+    series_id INTEGER NOT NULL,
+    "datetime" DATETIME NOT NULL,
+    pressure REAL NOT NULL,
+    FOREIGN KEY (series_id) REFERENCES baro_series (id) ON DELETE CASCADE,
+    PRIMARY KEY (series_id, "datetime")
+);
+
+-- Compensation runs of a task, the latest one produced the values in sensor_data.
+CREATE TABLE pressure_compensation (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL,
+    vented BOOLEAN NOT NULL,
+    baro_series_id INTEGER,
+    reference_depth_to_water REAL,
+    reference_time DATETIME,
+    compensated INTEGER NOT NULL,
+    skipped INTEGER NOT NULL,
+    created_by INTEGER NOT NULL,
+    created_at DATETIME NOT NULL,
+    FOREIGN KEY (task_id) REFERENCES task (id) ON DELETE CASCADE,
+    FOREIGN KEY (baro_series_id) REFERENCES baro_series (id) ON DELETE SET NULL,
+    FOREIGN KEY (created_by) REFERENCES people (id)
+);
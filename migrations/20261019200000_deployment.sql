@@ -0,0 +1,40 @@
+-- Add migration script here
+-- Loggers left in a well over a longer period, hang depth in metres below top of casing.
+CREATE TABLE deployment (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    well_id INTEGER NOT NULL,
+    instrument_serial TEXT,
+    equipment_id INTEGER,
+    installed_at DATETIME NOT NULL,
+    removed_at DATETIME,
+    hang_depth REAL,
+    -- Seconds between records, used for gap detection. Inferred from the data when unset.
+    logging_interval INTEGER,
+    comment TEXT,
+    FOREIGN KEY (well_id) REFERENCES well (id),
+    FOREIGN KEY (equipment_id) REFERENCES equipment (id) ON DELETE SET NULL
+);
+
+CREATE TABLE deployment_download (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    deployment_id INTEGER NOT NULL,
+    file_name TEXT NOT NULL,
+    first_record DATETIME,
+    last_record DATETIME,
+    records INTEGER NOT NULL,
+    added INTEGER NOT NULL,
+    downloaded_by INTEGER NOT NULL,
+    downloaded_at DATETIME NOT NULL,
+    FOREIGN KEY (deployment_id) REFERENCES deployment (id) ON DELETE CASCADE,
+    FOREIGN KEY (downloaded_by) REFERENCES people (id)
+);
+
+-- One row per logged value, overlapping downloads are merged on the primary key.
+CREATE TABLE deployment_reading (
+    deployment_id INTEGER NOT NULL,
+    parameter TEXT NOT NULL,
+    "datetime" DATETIME NOT NULL,
+    value REAL NOT NULL,
+    FOREIGN KEY (deployment_id) REFERENCES deployment (id) ON DELETE CASCADE,
+    PRIMARY KEY (deployment_id, parameter, "datetime")
+);
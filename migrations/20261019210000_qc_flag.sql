@@ -0,0 +1,18 @@
+-- Add migration script here
+-- Quality control flags on a time range of sensor data, applied when sensor data is read.
+-- A flag without a parameter applies to the whole record. Later flags take precedence.
+CREATE TABLE sensor_qc_flag (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL,
+    parameter TEXT,
+    start_time DATETIME NOT NULL,
+    end_time DATETIME NOT NULL,
+    flag TEXT NOT NULL CHECK (flag IN ('good', 'suspect', 'bad')),
+    reason TEXT,
+    created_by INTEGER NOT NULL,
+    created_at DATETIME NOT NULL,
+    FOREIGN KEY (task_id) REFERENCES task (id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES people (id)
+);
+
+CREATE INDEX sensor_qc_flag_task_id ON sensor_qc_flag (task_id);
@@ -0,0 +1,24 @@
+-- Add migration script here
+-- Flags suggested by the automatic checks on ingest, until they are accepted.
+ALTER TABLE sensor_qc_flag ADD COLUMN suggested BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Thresholds of the automatic checks per parameter, NULL disables a check.
+-- max_rate is in units per minute, spike is the jump from both neighbours and
+-- flat_line the number of identical consecutive values of a stuck sensor.
+CREATE TABLE qc_threshold (
+    parameter TEXT PRIMARY KEY,
+    max_rate REAL,
+    spike REAL,
+    flat_line INTEGER
+);
+
+INSERT INTO qc_threshold (parameter, max_rate, spike, flat_line) VALUES
+    ('temp_internal', 1.0, 0.5, NULL),
+    ('cndct', 200.0, 100.0, 30),
+    ('spcndct', 200.0, 100.0, 30),
+    ('ph', 0.5, 0.3, 30),
+    ('orp', 50.0, 30.0, 30),
+    ('do_con', 1.0, 0.5, 30),
+    ('do_sat', 10.0, 5.0, 30),
+    ('turbidity', 50.0, 20.0, NULL),
+    ('pres', 1.0, 0.5, NULL);
@@ -0,0 +1,31 @@
+-- Add migration script here
+-- Timestamps used to be stored in the local time of the client and are stored in UTC from now on.
+-- The stored local times are converted once on the next start, from the project time zone.
+CREATE TABLE utc_conversion (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    time_zone TEXT,
+    converted_at DATETIME
+);
+
+INSERT INTO utc_conversion (id) VALUES (1);
+
+-- Time zone of the logger clock, for logs without an offset. Defaults to the project time zone.
+ALTER TABLE deployment ADD COLUMN time_zone TEXT;
+
+-- Shifts of the sensor data of a task, for a logger clock which was off.
+-- start_time and end_time are the shifted range before the shift.
+CREATE TABLE clock_offset (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL,
+    seconds INTEGER NOT NULL,
+    start_time DATETIME NOT NULL,
+    end_time DATETIME NOT NULL,
+    records INTEGER NOT NULL,
+    comment TEXT,
+    created_by INTEGER NOT NULL,
+    created_at DATETIME NOT NULL,
+    FOREIGN KEY (task_id) REFERENCES task (id) ON DELETE CASCADE,
+    FOREIGN KEY (created_by) REFERENCES people (id)
+);
+
+CREATE INDEX clock_offset_task_id ON clock_offset (task_id);
@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Events during a task, such as a changed pump rate, drawn on the sensor data chart.
+CREATE TABLE annotation (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL,
+    "timestamp" DATETIME NOT NULL,
+    category TEXT NOT NULL CHECK (category IN ('pump', 'flow_cell', 'water_level', 'purging', 'sampling', 'other')),
+    text TEXT NOT NULL,
+    people_id INTEGER,
+    FOREIGN KEY (task_id) REFERENCES task (id) ON DELETE CASCADE,
+    FOREIGN KEY (people_id) REFERENCES people (id)
+);
+
+CREATE INDEX annotation_task_id ON annotation (task_id);
@@ -0,0 +1,47 @@
+-- Add migration script here
+-- Report layouts rendered with MiniJinja, edited by data managers.
+CREATE TABLE report_template (
+    name TEXT PRIMARY KEY NOT NULL,
+    format TEXT NOT NULL CHECK (format IN ('html', 'markdown', 'csv')),
+    description TEXT,
+    body TEXT NOT NULL,
+    updated_by INTEGER,
+    updated_at DATETIME NOT NULL,
+    FOREIGN KEY (updated_by) REFERENCES people (id)
+);
+
+INSERT INTO report_template (name, format, description, body, updated_at) VALUES
+('summary', 'html', 'Task summary with the final field parameters',
+'<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{{ task.serial }}</title>
+</head>
+<body>
+<h1>{{ task.serial }}</h1>
+<p>Well {{ well.name }}, depth {{ task.depth }}, status {{ task.status }}</p>
+<h2>Field records</h2>
+{% for info in task.task_info %}
+<p>
+Purging {{ info.purging_time | localtime }}, sampling {{ info.sampling_time | localtime }},
+sampled by {{ info.sampled_by | join(", ") }}
+</p>
+{% if info.comment %}{{ info.comment | safe }}{% endif %}
+{% endfor %}
+<h2>Samples</h2>
+<ul>
+{% for sample in samples %}<li>{{ sample.name }}{% if sample.variant %} ({{ sample.variant }}){% endif %}: {{ sample.qty }}</li>
+{% endfor %}</ul>
+<h2>Final field parameters</h2>
+<table>
+<tr><th>Parameter</th><th>Value</th><th>Method</th><th>Window</th></tr>
+{% for parameter in field_parameters.parameters %}<tr><td>{{ parameter.parameter }}</td><td>{{ parameter.value | round(3) }}</td><td>{{ parameter.method }}</td><td>{{ parameter.window_start | localtime("%H:%M") }} - {{ parameter.window_end | localtime("%H:%M") }}</td></tr>
+{% endfor %}</table>
+</body>
+</html>
+', CURRENT_TIMESTAMP),
+('field_parameters', 'csv', 'Final field parameters, one row per parameter',
+'serial,well,parameter,value,method,min,max,mean,records,window_start,window_end
+{% for parameter in field_parameters.parameters %}{{ task.serial | csv }},{{ well.name | csv }},{{ parameter.parameter }},{{ parameter.value }},{{ parameter.method }},{{ parameter.min }},{{ parameter.max }},{{ parameter.mean }},{{ parameter.records }},{{ parameter.window_start }},{{ parameter.window_end }}
+{% endfor %}', CURRENT_TIMESTAMP);
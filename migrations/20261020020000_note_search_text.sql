@@ -0,0 +1,146 @@
+-- Add migration script here
+-- Notes are stored as HTML. The triggers queue changed notes, which are indexed
+-- by their text rendering before the next search.
+CREATE TABLE note_search_pending (
+    body TEXT NOT NULL,
+    source TEXT NOT NULL,
+    field TEXT NOT NULL,
+    source_id INTEGER NOT NULL,
+    task_id INTEGER
+);
+
+DROP TRIGGER note_search_task_info_insert;
+DROP TRIGGER note_search_task_info_update;
+DROP TRIGGER note_search_task_info_delete;
+DROP TRIGGER note_search_well_insert;
+DROP TRIGGER note_search_well_update;
+DROP TRIGGER note_search_well_delete;
+DROP TRIGGER note_search_pump_insert;
+DROP TRIGGER note_search_pump_update;
+DROP TRIGGER note_search_pump_delete;
+DROP TRIGGER note_search_sample_type_insert;
+DROP TRIGGER note_search_sample_type_update;
+DROP TRIGGER note_search_sample_type_delete;
+
+-- task_info
+
+CREATE TRIGGER note_search_task_info_insert AFTER INSERT ON task_info
+BEGIN
+    INSERT INTO note_search_pending (body, source, field, source_id, task_id)
+    SELECT body, 'task_info', field, new.id, new.task_id
+    FROM (
+        SELECT new.comment AS body, 'comment' AS field
+        UNION ALL
+        SELECT new.calibration, 'calibration'
+        UNION ALL
+        SELECT new.hose_setup, 'hose_setup'
+    )
+    WHERE body IS NOT NULL AND body != '';
+END;
+
+CREATE TRIGGER note_search_task_info_update AFTER UPDATE OF
+comment, calibration, hose_setup, task_id ON task_info
+BEGIN
+    DELETE FROM note_search
+    WHERE source = 'task_info' AND source_id = old.id;
+    DELETE FROM note_search_pending
+    WHERE source = 'task_info' AND source_id = old.id;
+
+    INSERT INTO note_search_pending (body, source, field, source_id, task_id)
+    SELECT body, 'task_info', field, new.id, new.task_id
+    FROM (
+        SELECT new.comment AS body, 'comment' AS field
+        UNION ALL
+        SELECT new.calibration, 'calibration'
+        UNION ALL
+        SELECT new.hose_setup, 'hose_setup'
+    )
+    WHERE body IS NOT NULL AND body != '';
+END;
+
+CREATE TRIGGER note_search_task_info_delete AFTER DELETE ON task_info
+BEGIN
+    DELETE FROM note_search
+    WHERE source = 'task_info' AND source_id = old.id;
+    DELETE FROM note_search_pending
+    WHERE source = 'task_info' AND source_id = old.id;
+END;
+
+-- well, pump and sample_type comments
+
+CREATE TRIGGER note_search_well_insert AFTER INSERT ON well
+WHEN new.comment IS NOT NULL AND new.comment != ''
+BEGIN
+    INSERT INTO note_search_pending (body, source, field, source_id)
+    VALUES (new.comment, 'well', 'comment', new.id);
+END;
+
+CREATE TRIGGER note_search_well_update AFTER UPDATE OF comment ON well
+BEGIN
+    DELETE FROM note_search WHERE source = 'well' AND source_id = old.id;
+    DELETE FROM note_search_pending WHERE source = 'well' AND source_id = old.id;
+    INSERT INTO note_search_pending (body, source, field, source_id)
+    SELECT new.comment, 'well', 'comment', new.id
+    WHERE new.comment IS NOT NULL AND new.comment != '';
+END;
+
+CREATE TRIGGER note_search_well_delete AFTER DELETE ON well
+BEGIN
+    DELETE FROM note_search WHERE source = 'well' AND source_id = old.id;
+    DELETE FROM note_search_pending WHERE source = 'well' AND source_id = old.id;
+END;
+
+CREATE TRIGGER note_search_pump_insert AFTER INSERT ON pump
+WHEN new.comment IS NOT NULL AND new.comment != ''
+BEGIN
+    INSERT INTO note_search_pending (body, source, field, source_id)
+    VALUES (new.comment, 'pump', 'comment', new.id);
+END;
+
+CREATE TRIGGER note_search_pump_update AFTER UPDATE OF comment ON pump
+BEGIN
+    DELETE FROM note_search WHERE source = 'pump' AND source_id = old.id;
+    DELETE FROM note_search_pending WHERE source = 'pump' AND source_id = old.id;
+    INSERT INTO note_search_pending (body, source, field, source_id)
+    SELECT new.comment, 'pump', 'comment', new.id
+    WHERE new.comment IS NOT NULL AND new.comment != '';
+END;
+
+CREATE TRIGGER note_search_pump_delete AFTER DELETE ON pump
+BEGIN
+    DELETE FROM note_search WHERE source = 'pump' AND source_id = old.id;
+    DELETE FROM note_search_pending WHERE source = 'pump' AND source_id = old.id;
+END;
+
+CREATE TRIGGER note_search_sample_type_insert AFTER INSERT ON sample_type
+WHEN new.comment IS NOT NULL AND new.comment != ''
+BEGIN
+    INSERT INTO note_search_pending (body, source, field, source_id)
+    VALUES (new.comment, 'sample_type', 'comment', new.id);
+END;
+
+CREATE TRIGGER note_search_sample_type_update AFTER UPDATE OF comment ON sample_type
+BEGIN
+    DELETE FROM note_search
+    WHERE source = 'sample_type' AND source_id = old.id;
+    DELETE FROM note_search_pending
+    WHERE source = 'sample_type' AND source_id = old.id;
+    INSERT INTO note_search_pending (body, source, field, source_id)
+    SELECT new.comment, 'sample_type', 'comment', new.id
+    WHERE new.comment IS NOT NULL AND new.comment != '';
+END;
+
+CREATE TRIGGER note_search_sample_type_delete AFTER DELETE ON sample_type
+BEGIN
+    DELETE FROM note_search
+    WHERE source = 'sample_type' AND source_id = old.id;
+    DELETE FROM note_search_pending
+    WHERE source = 'sample_type' AND source_id = old.id;
+END;
+
+-- Reindex the notes indexed as HTML
+
+INSERT INTO note_search_pending (body, source, field, source_id, task_id)
+SELECT body, source, field, source_id, task_id FROM note_search;
+
+DELETE FROM note_search;
@@ -0,0 +1,37 @@
+-- no-transaction
+-- Rebuild equipment with CHECK constraints on its enums. Foreign keys are off
+-- during the rebuild, dropping the table would otherwise delete its
+-- task_equipment rows and unlink its deployments.
+PRAGMA foreign_keys = OFF;
+
+BEGIN;
+
+CREATE TABLE equipment_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    equipment_type TEXT NOT NULL CHECK (
+        equipment_type IN (
+            'sonde', 'probe', 'flow_cell', 'water_level_meter', 'tubing', 'pump', 'other'
+        )
+    ),
+    name TEXT NOT NULL,
+    serial TEXT UNIQUE,
+    status TEXT NOT NULL DEFAULT 'in_service'
+    CHECK (status IN ('in_service', 'repair', 'retired')),
+    service_due DATE,
+    calibration_due DATE,
+    comment TEXT
+);
+
+INSERT INTO equipment_new (
+    id, equipment_type, name, serial, status, service_due, calibration_due, comment
+)
+SELECT id, equipment_type, name, serial, status, service_due, calibration_due, comment
+FROM equipment;
+
+DROP TABLE equipment;
+
+ALTER TABLE equipment_new RENAME TO equipment;
+
+COMMIT;
+
+PRAGMA foreign_keys = ON;
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- Reference point of the reference depth to water, TOC or GS. Earlier runs always used the top of casing.
+ALTER TABLE pressure_compensation ADD COLUMN reference_point TEXT;
+
+UPDATE pressure_compensation SET reference_point = 'TOC'
+WHERE reference_depth_to_water IS NOT NULL;
@@ -0,0 +1,41 @@
+-- Add migration script here
+-- Tasks from before status tracking have no status change time and no log entry.
+-- Their status is dated to the last sampling time of the task, or to now, and logged
+-- without a person, so people_id becomes optional. Sampling times still in local
+-- time are left for the conversion to UTC, which backfills them afterwards.
+CREATE TABLE task_status_log_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    people_id INTEGER,
+    "timestamp" DATETIME NOT NULL,
+    comment TEXT,
+    FOREIGN KEY (task_id) REFERENCES task (id) ON DELETE CASCADE,
+    FOREIGN KEY (people_id) REFERENCES people (id)
+);
+
+INSERT INTO task_status_log_new (id, task_id, status, people_id, "timestamp", comment)
+SELECT id, task_id, status, people_id, "timestamp", comment FROM task_status_log;
+
+DROP TABLE task_status_log;
+
+ALTER TABLE task_status_log_new RENAME TO task_status_log;
+
+UPDATE task SET status_changed_at = COALESCE(
+    (
+        SELECT MAX(task_info.sampling_time)
+        FROM task_info
+        WHERE task_info.task_id = task.id
+    ),
+    datetime('now')
+)
+WHERE status_changed_at IS NULL
+AND NOT EXISTS (SELECT 1 FROM utc_conversion WHERE converted_at IS NULL);
+
+INSERT INTO task_status_log (task_id, status, people_id, "timestamp", comment)
+SELECT id, status, NULL, status_changed_at, 'Status from before status tracking'
+FROM task
+WHERE status_changed_at IS NOT NULL
+AND NOT EXISTS (
+    SELECT 1 FROM task_status_log WHERE task_status_log.task_id = task.id
+);
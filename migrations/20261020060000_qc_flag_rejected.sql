@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Suggested flags which were rejected are kept, so that the automatic checks
+-- don't suggest them again. They aren't applied to the sensor data.
+ALTER TABLE sensor_qc_flag ADD COLUMN rejected BOOLEAN NOT NULL DEFAULT FALSE;
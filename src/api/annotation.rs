@@ -0,0 +1,241 @@
+//! Events during a task, such as a lowered pump rate or a cleared flow cell,
+//! for the sensor data chart. The purging and sampling times of the task info
+//! are included as built-in annotations.
+
+use std::collections::HashMap;
+
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::auth::{Role, Session};
+use super::finalize::ensure_unlocked;
+use super::task_info::fetch_task_info;
+use super::{ApiContext, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum AnnotationCategory {
+    Pump,
+    FlowCell,
+    WaterLevel,
+    Purging,
+    Sampling,
+    Other,
+}
+
+#[derive(Serialize)]
+pub struct Annotation {
+    /// None for built-in annotations
+    id: Option<i64>,
+    task_id: i64,
+    #[serde(with = "super::serde::iso8601")]
+    timestamp: NaiveDateTime,
+    category: AnnotationCategory,
+    text: String,
+    people_id: Option<i64>,
+    /// Taken from the task info, edited there
+    builtin: bool,
+}
+
+async fn fetch_annotations(db: &SqlitePool, task_id: i64) -> Result<Vec<Annotation>, Error> {
+    let mut annotations = sqlx::query_as!(
+        Annotation,
+        r#"
+        SELECT
+            id,
+            task_id,
+            "timestamp",
+            category AS "category: AnnotationCategory",
+            text,
+            people_id,
+            FALSE AS "builtin!: bool"
+        FROM
+            annotation
+        WHERE
+            task_id = $1
+        "#,
+        task_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    for info in fetch_task_info(db, task_id).await? {
+        let builtin = [
+            (
+                info.purging_time,
+                AnnotationCategory::Purging,
+                "Purging started",
+            ),
+            (
+                info.sampling_time,
+                AnnotationCategory::Sampling,
+                "Sample collected",
+            ),
+        ];
+        for (timestamp, category, text) in builtin {
+            if let Some(timestamp) = timestamp {
+                annotations.push(Annotation {
+                    id: None,
+                    task_id,
+                    timestamp,
+                    category,
+                    text: text.to_string(),
+                    people_id: None,
+                    builtin: true,
+                });
+            }
+        }
+    }
+
+    annotations.sort_by_key(|annotation| annotation.timestamp);
+
+    Ok(annotations)
+}
+
+/// Annotations of a task in time order, to draw along the sensor data.
+pub async fn list_annotations(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<Vec<Annotation>>, Error> {
+    Ok(Json(fetch_annotations(&ctx.db, task_id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct NewAnnotation {
+    #[serde(with = "super::serde::iso8601")]
+    timestamp: NaiveDateTime,
+    category: AnnotationCategory,
+    text: String,
+    /// Defaults to the person logged in
+    #[serde(default)]
+    people_id: Option<i64>,
+}
+
+pub async fn insert_annotation(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(task_id): Path<i64>,
+    Json(annotation): Json<NewAnnotation>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    let people_id = annotation.people_id.unwrap_or(session.people_id);
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO
+            annotation (task_id, "timestamp", category, text, people_id)
+        VALUES
+            ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        task_id,
+        annotation.timestamp,
+        annotation.category,
+        annotation.text,
+        people_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok(Json(id.id.ok_or_else(|| {
+        anyhow::anyhow!("Failed to insert annotation")
+    })?))
+}
+
+pub async fn update_annotation(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path((task_id, annotation_id)): Path<(i64, i64)>,
+    Json(update): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    for (key, value) in update {
+        match key.as_str() {
+            "timestamp" => {
+                let value = super::serde::iso8601::deserialize(value)?;
+                sqlx::query!(
+                    r#"UPDATE annotation SET "timestamp" = $1 WHERE id = $2 AND task_id = $3"#,
+                    value,
+                    annotation_id,
+                    task_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "category" => {
+                let value: AnnotationCategory = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE annotation SET category = $1 WHERE id = $2 AND task_id = $3",
+                    value,
+                    annotation_id,
+                    task_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "text" => {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid value for text: {:?}", value))?;
+                sqlx::query!(
+                    "UPDATE annotation SET text = $1 WHERE id = $2 AND task_id = $3",
+                    value,
+                    annotation_id,
+                    task_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "people_id" => {
+                let value: Option<i64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE annotation SET people_id = $1 WHERE id = $2 AND task_id = $3",
+                    value,
+                    annotation_id,
+                    task_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn delete_annotation(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path((task_id, annotation_id)): Path<(i64, i64)>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    sqlx::query!(
+        "DELETE FROM annotation WHERE id = $1 AND task_id = $2",
+        annotation_id,
+        task_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(())
+}
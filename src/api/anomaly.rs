@@ -0,0 +1,542 @@
+//! Automatic QC checks on sensor data, run when it's inserted. Findings are
+//! stored as suggested QC flags, which only count once they're accepted.
+
+use std::collections::HashMap;
+
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use super::auth::{Role, Session};
+use super::deployment::{detect_gaps, Gap};
+use super::derived::DeriveMode;
+use super::qc::{insert_suggested_qc_flag, NewSensorQcFlag, QcFlag, SensorQcFlag};
+use super::sensor_data::{fetch_sensor_data, SensorRecord, PARAMETERS};
+use super::{ApiContext, Error};
+
+/// Physically possible range of the parameters.
+const RANGES: &[(&str, Option<f64>, Option<f64>)] = &[
+    ("ph", Some(0.0), Some(14.0)),
+    ("cndct", Some(0.0), None),
+    ("spcndct", Some(0.0), None),
+    ("sa", Some(0.0), None),
+    ("tds", Some(0.0), None),
+    ("resis", Some(0.0), None),
+    ("turbidity", Some(0.0), None),
+    ("do_con", Some(0.0), None),
+    ("do_sat", Some(0.0), Some(500.0)),
+];
+
+#[derive(Serialize)]
+pub struct QcThreshold {
+    parameter: String,
+    /// Largest change per minute
+    max_rate: Option<f64>,
+    /// Largest jump of a single value from both its neighbours
+    spike: Option<f64>,
+    /// Number of identical consecutive values of a stuck sensor
+    flat_line: Option<i64>,
+}
+
+async fn fetch_qc_thresholds(db: &SqlitePool) -> Result<Vec<QcThreshold>, Error> {
+    let thresholds = sqlx::query_as!(
+        QcThreshold,
+        r#"
+        SELECT
+            parameter AS "parameter!",
+            max_rate,
+            spike,
+            flat_line
+        FROM
+            qc_threshold
+        ORDER BY
+            parameter
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(thresholds)
+}
+
+pub async fn list_qc_thresholds(
+    ctx: Extension<ApiContext>,
+) -> Result<Json<Vec<QcThreshold>>, Error> {
+    Ok(Json(fetch_qc_thresholds(&ctx.db).await?))
+}
+
+pub async fn update_qc_threshold(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(parameter): Path<String>,
+    Json(update): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    if !PARAMETERS.contains(&parameter.as_str()) {
+        return Err(anyhow::anyhow!("Invalid parameter: {:?}", parameter).into());
+    }
+
+    let mut tx = ctx.db.begin().await?;
+
+    sqlx::query!(
+        "INSERT INTO qc_threshold (parameter) VALUES ($1) ON CONFLICT DO NOTHING",
+        parameter
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for (key, value) in update {
+        match key.as_str() {
+            "max_rate" => {
+                let value: Option<f64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE qc_threshold SET max_rate = $1 WHERE parameter = $2",
+                    value,
+                    parameter
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "spike" => {
+                let value: Option<f64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE qc_threshold SET spike = $1 WHERE parameter = $2",
+                    value,
+                    parameter
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "flat_line" => {
+                let value: Option<i64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE qc_threshold SET flat_line = $1 WHERE parameter = $2",
+                    value,
+                    parameter
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Records a check found, by index.
+struct Finding {
+    parameter: Option<&'static str>,
+    flag: QcFlag,
+    reason: String,
+    indices: Vec<usize>,
+}
+
+fn values(records: &[SensorRecord], parameter: &str) -> Vec<Option<f64>> {
+    records
+        .iter()
+        .map(|record| record.parameter(parameter))
+        .collect()
+}
+
+fn check_range(records: &[SensorRecord]) -> Vec<Finding> {
+    RANGES
+        .iter()
+        .map(|(parameter, min, max)| Finding {
+            parameter: Some(*parameter),
+            flag: QcFlag::Bad,
+            reason: "Physically impossible value".to_string(),
+            indices: values(records, parameter)
+                .into_iter()
+                .enumerate()
+                .filter(|(_, value)| {
+                    value.is_some_and(|value| {
+                        min.is_some_and(|min| value < min) || max.is_some_and(|max| value > max)
+                    })
+                })
+                .map(|(idx, _)| idx)
+                .collect(),
+        })
+        .collect()
+}
+
+fn check_thresholds(
+    records: &[SensorRecord],
+    parameter: &'static str,
+    threshold: &QcThreshold,
+) -> Vec<Finding> {
+    let values = values(records, parameter);
+    let mut findings = Vec::new();
+
+    if let Some(max_rate) = threshold.max_rate {
+        let indices = (1..records.len())
+            .filter(|&idx| {
+                let minutes =
+                    (records[idx].datetime - records[idx - 1].datetime).num_seconds() as f64 / 60.0;
+                match (values[idx - 1], values[idx]) {
+                    (Some(previous), Some(value)) if minutes > 0.0 => {
+                        (value - previous).abs() / minutes > max_rate
+                    }
+                    _ => false,
+                }
+            })
+            .collect();
+        findings.push(Finding {
+            parameter: Some(parameter),
+            flag: QcFlag::Suspect,
+            reason: format!("Rate of change above {} per minute", max_rate),
+            indices,
+        });
+    }
+
+    if let Some(spike) = threshold.spike {
+        let indices = (1..records.len().saturating_sub(1))
+            .filter(
+                |&idx| match (values[idx - 1], values[idx], values[idx + 1]) {
+                    (Some(previous), Some(value), Some(next)) => {
+                        let (rise, fall) = (value - previous, value - next);
+                        rise.signum() == fall.signum() && rise.abs().min(fall.abs()) > spike
+                    }
+                    _ => false,
+                },
+            )
+            .collect();
+        findings.push(Finding {
+            parameter: Some(parameter),
+            flag: QcFlag::Suspect,
+            reason: format!("Spike above {}", spike),
+            indices,
+        });
+    }
+
+    if let Some(flat_line) = threshold.flat_line.filter(|flat_line| *flat_line > 1) {
+        let mut indices = Vec::new();
+        let mut start = 0;
+        for idx in 1..=values.len() {
+            if idx < values.len() && values[idx].is_some() && values[idx] == values[start] {
+                continue;
+            }
+            if values[start].is_some() && (idx - start) as i64 >= flat_line {
+                indices.extend(start..idx);
+            }
+            start = idx;
+        }
+        findings.push(Finding {
+            parameter: Some(parameter),
+            flag: QcFlag::Suspect,
+            reason: format!("Flat line of at least {} identical values", flat_line),
+            indices,
+        });
+    }
+
+    findings
+}
+
+/// Indices of records sharing their timestamp with another record.
+fn duplicate_indices(records: &[SensorRecord]) -> Vec<usize> {
+    (0..records.len())
+        .filter(|&idx| {
+            (idx > 0 && records[idx - 1].datetime == records[idx].datetime)
+                || records
+                    .get(idx + 1)
+                    .is_some_and(|next| next.datetime == records[idx].datetime)
+        })
+        .collect()
+}
+
+/// Consecutive indices as ranges of records.
+fn runs(indices: &[usize]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for &idx in indices {
+        match runs.last_mut() {
+            Some((_, end)) if *end + 1 == idx => *end = idx,
+            _ => runs.push((idx, idx)),
+        }
+    }
+    runs
+}
+
+#[derive(Serialize)]
+pub struct DuplicateTimestamp {
+    #[serde(with = "super::serde::iso8601")]
+    datetime: NaiveDateTime,
+    records: usize,
+}
+
+#[derive(Serialize)]
+pub struct IngestReport {
+    inserted: usize,
+    /// QC flags suggested for the inserted records
+    suggested: Vec<SensorQcFlag>,
+    gaps: Vec<Gap>,
+    duplicates: Vec<DuplicateTimestamp>,
+}
+
+/// Check the sensor data of a task after records from `first` to `last` were
+/// inserted. The whole series is checked, so the first inserted records are
+/// compared with the ones before them, but only findings touching the inserted
+/// records are reported.
+pub(crate) async fn check_ingest(
+    db: &SqlitePool,
+    task_id: i64,
+    people_id: i64,
+    inserted: usize,
+    first: NaiveDateTime,
+    last: NaiveDateTime,
+) -> Result<IngestReport, Error> {
+    let records = fetch_sensor_data(db, task_id, DeriveMode::Logged).await?;
+    let thresholds = fetch_qc_thresholds(db).await?;
+
+    let mut findings = check_range(&records);
+    for threshold in &thresholds {
+        if let Some(parameter) = PARAMETERS.iter().find(|p| **p == threshold.parameter) {
+            findings.extend(check_thresholds(&records, parameter, threshold));
+        }
+    }
+
+    let duplicates = duplicate_indices(&records);
+    findings.push(Finding {
+        parameter: None,
+        flag: QcFlag::Suspect,
+        reason: "Duplicate timestamp".to_string(),
+        indices: duplicates.clone(),
+    });
+
+    let mut suggested = Vec::new();
+    for finding in findings {
+        for (start, end) in runs(&finding.indices) {
+            let (start_time, end_time) = (records[start].datetime, records[end].datetime);
+            if end_time < first || start_time > last {
+                continue;
+            }
+
+            let qc_flag = NewSensorQcFlag {
+                parameter: finding.parameter.map(str::to_string),
+                start_time: Some(start_time),
+                end_time: Some(end_time),
+                flag: finding.flag,
+                reason: Some(finding.reason.clone()),
+            };
+            if let Some(flag) = insert_suggested_qc_flag(db, task_id, people_id, &qc_flag).await? {
+                suggested.push(flag);
+            }
+        }
+    }
+
+    let mut datetimes: Vec<NaiveDateTime> = records.iter().map(|record| record.datetime).collect();
+    datetimes.dedup();
+    let gaps = detect_gaps(&datetimes, None)
+        .into_iter()
+        .filter(|gap| gap.to >= first && gap.from <= last)
+        .collect();
+
+    let mut duplicate_timestamps: Vec<DuplicateTimestamp> = Vec::new();
+    for datetime in duplicates.into_iter().map(|idx| records[idx].datetime) {
+        if datetime < first || datetime > last {
+            continue;
+        }
+        match duplicate_timestamps.last_mut() {
+            Some(duplicate) if duplicate.datetime == datetime => duplicate.records += 1,
+            _ => duplicate_timestamps.push(DuplicateTimestamp {
+                datetime,
+                records: 1,
+            }),
+        }
+    }
+
+    if !suggested.is_empty() {
+        tracing::info!(
+            "{} QC flags suggested for task {}",
+            suggested.len(),
+            task_id
+        );
+    }
+
+    Ok(IngestReport {
+        inserted,
+        suggested,
+        gaps,
+        duplicates: duplicate_timestamps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sensor_data::time;
+
+    /// One record a minute with the given salinity.
+    fn records(sa: &[Option<f64>]) -> Vec<SensorRecord> {
+        sa.iter()
+            .enumerate()
+            .map(|(minute, sa)| SensorRecord {
+                sa: *sa,
+                ..SensorRecord::at(time(minute as i64))
+            })
+            .collect()
+    }
+
+    fn threshold(max_rate: Option<f64>, spike: Option<f64>, flat_line: Option<i64>) -> QcThreshold {
+        QcThreshold {
+            parameter: "sa".to_string(),
+            max_rate,
+            spike,
+            flat_line,
+        }
+    }
+
+    fn indices(sa: &[Option<f64>], threshold: &QcThreshold) -> Vec<usize> {
+        let findings = check_thresholds(&records(sa), "sa", threshold);
+        assert_eq!(findings.len(), 1);
+        findings.into_iter().next().unwrap().indices
+    }
+
+    #[test]
+    fn range() {
+        let records: Vec<SensorRecord> = [7.0, -1.0, 15.0, 14.0, 0.0]
+            .into_iter()
+            .enumerate()
+            .map(|(minute, ph)| SensorRecord {
+                ph,
+                ..SensorRecord::at(time(minute as i64))
+            })
+            .collect();
+        for finding in check_range(&records) {
+            let expected: &[usize] = match finding.parameter {
+                Some("ph") => &[1, 2],
+                _ => &[],
+            };
+            assert_eq!(finding.indices, expected, "{:?}", finding.parameter);
+            assert_eq!(finding.flag, QcFlag::Bad);
+        }
+    }
+
+    #[test]
+    fn rate_of_change() {
+        let cases: &[(&[Option<f64>], &[usize])] = &[
+            (&[Some(0.0), Some(1.0), Some(5.0)], &[2]),
+            (&[Some(5.0), Some(2.0), Some(2.5)], &[1]),
+            (&[Some(0.0), None, Some(5.0)], &[]),
+            (&[Some(0.0), Some(2.0), Some(4.0)], &[]),
+        ];
+        for (sa, expected) in cases {
+            assert_eq!(
+                indices(sa, &threshold(Some(2.0), None, None)),
+                *expected,
+                "{:?}",
+                sa
+            );
+        }
+    }
+
+    #[test]
+    fn spike_sign() {
+        let cases: &[(&[Option<f64>], &[usize])] = &[
+            // Up and down
+            (&[Some(1.0), Some(5.0), Some(1.0)], &[1]),
+            (&[Some(5.0), Some(1.0), Some(5.0)], &[1]),
+            // A ramp rises on one side and falls on the other
+            (&[Some(1.0), Some(5.0), Some(9.0)], &[]),
+            (&[Some(9.0), Some(5.0), Some(1.0)], &[]),
+            // Both sides must jump by more than the threshold
+            (&[Some(1.0), Some(5.0), Some(4.0)], &[]),
+            (&[Some(1.0), Some(1.0), Some(1.0)], &[]),
+            // The first and last values have one neighbour only
+            (&[Some(5.0), Some(1.0), Some(1.0), Some(5.0)], &[]),
+            (&[Some(1.0), Some(5.0), None], &[]),
+            // Alternating spikes, the value between them sits on a slope
+            (
+                &[Some(1.0), Some(5.0), Some(1.0), Some(-3.0), Some(1.0)],
+                &[1, 3],
+            ),
+        ];
+        for (sa, expected) in cases {
+            assert_eq!(
+                indices(sa, &threshold(None, Some(2.0), None)),
+                *expected,
+                "{:?}",
+                sa
+            );
+        }
+    }
+
+    #[test]
+    fn flat_line_runs() {
+        let cases: &[(&[Option<f64>], &[usize])] = &[
+            (&[Some(1.0), Some(1.0), Some(1.0), Some(2.0)], &[0, 1, 2]),
+            // A run at the end
+            (
+                &[Some(1.0), Some(1.0), Some(2.0), Some(2.0), Some(2.0)],
+                &[2, 3, 4],
+            ),
+            (&[Some(1.0), Some(1.0), Some(2.0), Some(2.0)], &[]),
+            (
+                &[
+                    Some(2.0),
+                    Some(2.0),
+                    Some(2.0),
+                    Some(2.0),
+                    Some(3.0),
+                    Some(3.0),
+                    Some(3.0),
+                ],
+                &[0, 1, 2, 3, 4, 5, 6],
+            ),
+            // Missing values break a run and are never a run themselves
+            (&[Some(1.0), Some(1.0), None, Some(1.0)], &[]),
+            (&[None, None, None, None], &[]),
+            (&[], &[]),
+        ];
+        for (sa, expected) in cases {
+            assert_eq!(
+                indices(sa, &threshold(None, None, Some(3))),
+                *expected,
+                "{:?}",
+                sa
+            );
+        }
+    }
+
+    #[test]
+    fn flat_line_disabled() {
+        let sa = [Some(1.0), Some(1.0), Some(1.0)];
+        assert!(check_thresholds(&records(&sa), "sa", &threshold(None, None, Some(1))).is_empty());
+    }
+
+    #[test]
+    fn duplicates() {
+        let cases: &[(&[i64], &[usize])] = &[
+            (&[0, 1, 1, 2, 3, 3, 3], &[1, 2, 4, 5, 6]),
+            (&[0, 0], &[0, 1]),
+            (&[0, 1, 2], &[]),
+            (&[], &[]),
+        ];
+        for (minutes, expected) in cases {
+            let records: Vec<SensorRecord> = minutes
+                .iter()
+                .map(|minute| SensorRecord::at(time(*minute)))
+                .collect();
+            assert_eq!(duplicate_indices(&records), *expected, "{:?}", minutes);
+        }
+    }
+
+    #[test]
+    fn merge_runs() {
+        let cases = [
+            (vec![], vec![]),
+            (vec![4], vec![(4, 4)]),
+            (vec![1, 2, 3, 5, 7, 8], vec![(1, 3), (5, 5), (7, 8)]),
+            (vec![0, 2, 4], vec![(0, 0), (2, 2), (4, 4)]),
+        ];
+        for (indices, expected) in cases {
+            assert_eq!(runs(&indices), expected, "{:?}", indices);
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use std::io::Cursor;
+
+use axum::extract::{Extension, Json, Multipart, Path};
+use axum::http::header;
+use axum::response::IntoResponse;
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use super::auth::{Role, Session};
+use super::finalize::ensure_task_info_unlocked;
+use super::time_zone::to_utc;
+use super::{ApiContext, Error};
+
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// Content types served inline, with the leading bytes of such files.
+/// Anything else is stored and served as a download.
+const INLINE_CONTENT_TYPES: &[(&str, &[u8])] = &[
+    ("image/jpeg", b"\xFF\xD8\xFF"),
+    ("image/png", b"\x89PNG\r\n\x1A\n"),
+    ("application/pdf", b"%PDF-"),
+];
+
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Content type of an upload by its leading bytes, whatever the client claims.
+fn sniff_content_type(data: &[u8]) -> &'static str {
+    INLINE_CONTENT_TYPES
+        .iter()
+        .find(|(_, magic)| data.starts_with(magic))
+        .map_or(OCTET_STREAM, |(content_type, _)| content_type)
+}
+
+#[derive(Serialize)]
+pub struct Attachment {
+    id: i64,
+    task_info_id: i64,
+    file_name: String,
+    content_type: String,
+    size: i64,
+    has_thumbnail: bool,
+    #[serde(with = "super::serde::iso8601_option")]
+    taken_at: Option<NaiveDateTime>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    uploaded_by: i64,
+    #[serde(with = "super::serde::iso8601")]
+    uploaded_at: NaiveDateTime,
+}
+
+pub(crate) async fn fetch_attachments(
+    db: &SqlitePool,
+    task_info_id: i64,
+) -> Result<Vec<Attachment>, Error> {
+    let attachments = sqlx::query_as!(
+        Attachment,
+        r#"
+        SELECT
+            id AS "id!",
+            task_info_id,
+            file_name,
+            content_type,
+            size,
+            thumbnail IS NOT NULL AS "has_thumbnail!: bool",
+            taken_at,
+            latitude,
+            longitude,
+            uploaded_by,
+            uploaded_at
+        FROM
+            attachment
+        WHERE
+            task_info_id = $1
+        ORDER BY
+            id
+        "#,
+        task_info_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(attachments)
+}
+
+/// Attachments are reached through the task, the task info must belong to it.
+async fn ensure_task_info_of_task(
+    db: &SqlitePool,
+    task_id: i64,
+    task_info_id: i64,
+) -> Result<(), Error> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM task_info WHERE id = $1 AND task_id = $2) AS "exists!: bool""#,
+        task_info_id,
+        task_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    if !exists {
+        return Err(Error::TaskInfoNotFound(task_id, task_info_id));
+    }
+
+    Ok(())
+}
+
+pub async fn list_attachments(
+    ctx: Extension<ApiContext>,
+    Path((task_id, task_info_id)): Path<(i64, i64)>,
+) -> Result<Json<Vec<Attachment>>, Error> {
+    ensure_task_info_of_task(&ctx.db, task_id, task_info_id).await?;
+
+    Ok(Json(fetch_attachments(&ctx.db, task_info_id).await?))
+}
+
+/// Metadata extracted from an uploaded image.
+#[derive(Default)]
+struct ImageInfo {
+    thumbnail: Option<Vec<u8>>,
+    taken_at: Option<NaiveDateTime>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+impl ImageInfo {
+    fn from_bytes(data: &[u8]) -> Self {
+        let mut info = ImageInfo {
+            thumbnail: thumbnail(data),
+            ..Default::default()
+        };
+
+        if let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(data)) {
+            info.taken_at = exif
+                .get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
+                .or_else(|| exif.get_field(exif::Tag::DateTime, exif::In::PRIMARY))
+                .and_then(|field| match &field.value {
+                    exif::Value::Ascii(values) => values.first().and_then(|value| {
+                        NaiveDateTime::parse_from_str(
+                            &String::from_utf8_lossy(value),
+                            "%Y:%m:%d %H:%M:%S",
+                        )
+                        .ok()
+                    }),
+                    _ => None,
+                });
+            info.latitude =
+                gps_coordinate(&exif, exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef);
+            info.longitude =
+                gps_coordinate(&exif, exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef);
+        }
+
+        info
+    }
+}
+
+fn thumbnail(data: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(data).ok()?;
+    let mut buf = Cursor::new(Vec::new());
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .to_rgb8()
+        .write_to(&mut buf, image::ImageFormat::Jpeg)
+        .ok()?;
+    Some(buf.into_inner())
+}
+
+/// Convert an EXIF GPS degrees, minutes, seconds triplet into decimal degrees.
+fn gps_coordinate(exif: &exif::Exif, tag: exif::Tag, ref_tag: exif::Tag) -> Option<f64> {
+    let dms = match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Rational(dms) if dms.len() == 3 => {
+            dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0
+        }
+        _ => return None,
+    };
+
+    let sign = match exif.get_field(ref_tag, exif::In::PRIMARY) {
+        Some(field) => match field.display_value().to_string().as_str() {
+            "S" | "W" => -1.0,
+            _ => 1.0,
+        },
+        None => 1.0,
+    };
+
+    Some(sign * dms)
+}
+
+pub async fn insert_attachments(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path((task_id, task_info_id)): Path<(i64, i64)>,
+    mut multipart: Multipart,
+) -> Result<Json<Vec<i64>>, Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_task_info_of_task(&ctx.db, task_id, task_info_id).await?;
+    ensure_task_info_unlocked(&ctx.db, task_info_id).await?;
+
+    let mut ids = Vec::new();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| anyhow::anyhow!("Invalid multipart data: {:?}", e))?
+    {
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            continue;
+        };
+        let data = field
+            .bytes()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read attachment: {:?}", e))?
+            .to_vec();
+        let content_type = sniff_content_type(&data);
+
+        let mut info = if content_type.starts_with("image/") {
+            let data = data.clone();
+            tokio::task::spawn_blocking(move || ImageInfo::from_bytes(&data))
+                .await
+                .map_err(|e| anyhow::anyhow!("{:?}", e))?
+        } else {
+            ImageInfo::default()
+        };
+        // Cameras record the time of day without an offset
+        info.taken_at = info
+            .taken_at
+            .map(|taken_at| to_utc(ctx.settings.time_zone, taken_at));
+
+        let size = data.len() as i64;
+        let now = Utc::now().naive_utc();
+
+        let id = sqlx::query!(
+            r#"
+            INSERT INTO attachment (
+                task_info_id,
+                file_name,
+                content_type,
+                size,
+                data,
+                thumbnail,
+                taken_at,
+                latitude,
+                longitude,
+                uploaded_by,
+                uploaded_at
+            )
+            VALUES
+                ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            RETURNING id
+            "#,
+            task_info_id,
+            file_name,
+            content_type,
+            size,
+            data,
+            info.thumbnail,
+            info.taken_at,
+            info.latitude,
+            info.longitude,
+            session.people_id,
+            now
+        )
+        .fetch_one(&ctx.db)
+        .await?;
+
+        ids.push(
+            id.id
+                .ok_or_else(|| anyhow::anyhow!("Failed to insert attachment"))?,
+        );
+    }
+
+    Ok(Json(ids))
+}
+
+pub async fn get_attachment(
+    ctx: Extension<ApiContext>,
+    Path((task_id, task_info_id, attachment_id)): Path<(i64, i64, i64)>,
+) -> Result<impl IntoResponse, Error> {
+    ensure_task_info_of_task(&ctx.db, task_id, task_info_id).await?;
+
+    let attachment = sqlx::query!(
+        r#"
+        SELECT
+            file_name,
+            content_type,
+            data
+        FROM
+            attachment
+        WHERE
+            id = $1 AND task_info_id = $2
+        "#,
+        attachment_id,
+        task_info_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    // Attachments stored before uploads were sniffed may claim any type
+    let content_type = sniff_content_type(&attachment.data);
+    let inline = content_type != OCTET_STREAM && content_type == attachment.content_type;
+    let (content_type, disposition) = if inline {
+        (content_type, "inline")
+    } else {
+        (OCTET_STREAM, "attachment")
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "{}; filename=\"{}\"",
+                    disposition,
+                    attachment.file_name.replace(['"', '\\', '\r', '\n'], "")
+                ),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (header::CONTENT_SECURITY_POLICY, "sandbox".to_string()),
+        ],
+        attachment.data,
+    ))
+}
+
+pub async fn get_attachment_thumbnail(
+    ctx: Extension<ApiContext>,
+    Path((task_id, task_info_id, attachment_id)): Path<(i64, i64, i64)>,
+) -> Result<impl IntoResponse, Error> {
+    ensure_task_info_of_task(&ctx.db, task_id, task_info_id).await?;
+
+    let thumbnail = sqlx::query_scalar!(
+        "SELECT thumbnail FROM attachment WHERE id = $1 AND task_info_id = $2",
+        attachment_id,
+        task_info_id
+    )
+    .fetch_one(&ctx.db)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("Attachment has no thumbnail"))?;
+
+    Ok(([(header::CONTENT_TYPE, "image/jpeg")], thumbnail))
+}
+
+pub async fn delete_attachment(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path((task_id, task_info_id, attachment_id)): Path<(i64, i64, i64)>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_task_info_of_task(&ctx.db, task_id, task_info_id).await?;
+    ensure_task_info_unlocked(&ctx.db, task_info_id).await?;
+
+    sqlx::query!(
+        "DELETE FROM attachment WHERE id = $1 AND task_info_id = $2",
+        attachment_id,
+        task_info_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(())
+}
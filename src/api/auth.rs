@@ -0,0 +1,425 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::extract::{FromRequestParts, Json, Path};
+use axum::http::request::Parts;
+use axum::Extension;
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use axum_extra::headers::authorization::{Authorization, Bearer};
+use axum_extra::TypedHeader;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{ApiContext, Error};
+
+pub const SESSION_COOKIE: &str = "session";
+
+const SESSION_TTL_HOURS: i64 = 12;
+
+/// Roles are ordered by privilege, a higher role includes all lower ones.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    FieldTech,
+    DataManager,
+}
+
+/// The person acting on a request, resolved from the session cookie or an API token.
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    pub people_id: i64,
+    pub name: String,
+    pub role: Role,
+}
+
+impl Session {
+    pub fn require(&self, role: Role) -> Result<(), Error> {
+        if self.role >= role {
+            Ok(())
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for Session
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(ctx) = Extension::<ApiContext>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        let jar = CookieJar::from_headers(&parts.headers);
+        let now = Utc::now().naive_utc();
+
+        let mut session = None;
+
+        if let Some(cookie) = jar.get(SESSION_COOKIE) {
+            let token_hash = hash_token(cookie.value());
+            session = sqlx::query_as!(
+                Session,
+                r#"
+                SELECT
+                    people.id AS people_id,
+                    people.name,
+                    people.role AS "role: Role"
+                FROM
+                    session
+                JOIN people
+                    ON people.id = session.people_id
+                WHERE
+                    session.token_hash = $1 AND session.expires_at > $2
+                "#,
+                token_hash,
+                now
+            )
+            .fetch_optional(&ctx.db)
+            .await?;
+        }
+
+        // A stale session cookie of the browser must not shadow an API token
+        if session.is_none() {
+            if let Ok(TypedHeader(Authorization(bearer))) =
+                TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state).await
+            {
+                let token_hash = hash_token(bearer.token());
+                session = sqlx::query_as!(
+                    Session,
+                    r#"
+                    SELECT
+                        people.id AS people_id,
+                        people.name,
+                        people.role AS "role: Role"
+                    FROM
+                        api_token
+                    JOIN people
+                        ON people.id = api_token.people_id
+                    WHERE
+                        api_token.token_hash = $1
+                    "#,
+                    token_hash
+                )
+                .fetch_optional(&ctx.db)
+                .await?;
+
+                if session.is_some() {
+                    sqlx::query!(
+                        "UPDATE api_token SET last_used_at = $1 WHERE token_hash = $2",
+                        now,
+                        token_hash
+                    )
+                    .execute(&ctx.db)
+                    .await?;
+                }
+            }
+        }
+
+        let session = session.ok_or(Error::Unauthorized)?;
+
+        tracing::info!(
+            people_id = session.people_id,
+            "{} {} by {}",
+            parts.method,
+            parts.uri,
+            session.name
+        );
+
+        Ok(session)
+    }
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn hash_secret(secret: &str) -> Result<String, Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(secret.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash secret: {:?}", e))?
+        .to_string())
+}
+
+/// Everyone who may edit needs a PIN or password, only viewers may go without.
+pub fn ensure_secret_for_role(role: Role) -> Result<(), Error> {
+    if role > Role::Viewer {
+        Err(anyhow::anyhow!("Field techs and data managers need a PIN or password").into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Make a data manager of the person with this name, added if there is none,
+/// to bootstrap the first one from the command line.
+pub async fn create_admin(db: &sqlx::SqlitePool, name: &str, secret: &str) -> Result<i64, Error> {
+    if secret.is_empty() {
+        ensure_secret_for_role(Role::DataManager)?;
+    }
+    let secret_hash = hash_secret(secret)?;
+    let role = Role::DataManager;
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO
+            people (name, role, secret_hash)
+        VALUES
+            ($1, $2, $3)
+        ON CONFLICT (name) DO UPDATE SET
+            role = excluded.role,
+            secret_hash = excluded.secret_hash
+        RETURNING id AS "id!"
+        "#,
+        name,
+        role,
+        secret_hash
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(id)
+}
+
+fn verify_secret(secret: &str, secret_hash: &str) -> Result<bool, Error> {
+    let parsed_hash = PasswordHash::new(secret_hash)
+        .map_err(|e| anyhow::anyhow!("Invalid secret hash: {:?}", e))?;
+    Ok(Argon2::default()
+        .verify_password(secret.as_bytes(), &parsed_hash)
+        .is_ok())
+}
+
+#[derive(Deserialize)]
+pub struct Credentials {
+    people_id: i64,
+    #[serde(default)]
+    secret: Option<String>,
+}
+
+pub async fn login(
+    ctx: Extension<ApiContext>,
+    jar: CookieJar,
+    Json(credentials): Json<Credentials>,
+) -> Result<(CookieJar, Json<Session>), Error> {
+    let people = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            name,
+            role AS "role: Role",
+            secret_hash
+        FROM
+            people
+        WHERE
+            id = $1
+        "#,
+        credentials.people_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::Unauthorized)?;
+
+    // Only viewers may log in by name without a PIN or password.
+    match &people.secret_hash {
+        Some(secret_hash) => {
+            let secret = credentials.secret.as_deref().unwrap_or("");
+            if !verify_secret(secret, secret_hash)? {
+                return Err(Error::Unauthorized);
+            }
+        }
+        None if people.role > Role::Viewer => return Err(Error::Unauthorized),
+        None => {}
+    }
+
+    let token = generate_token();
+    let token_hash = hash_token(&token);
+    let now = Utc::now().naive_utc();
+    let expires_at = now + Duration::hours(SESSION_TTL_HOURS);
+
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            session (token_hash, people_id, created_at, expires_at)
+        VALUES
+            ($1, $2, $3, $4)
+        "#,
+        token_hash,
+        people.id,
+        now,
+        expires_at
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    // Drop expired sessions while we are here.
+    sqlx::query!("DELETE FROM session WHERE expires_at <= $1", now)
+        .execute(&ctx.db)
+        .await?;
+
+    let cookie = Cookie::build((SESSION_COOKIE, token))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict);
+
+    Ok((
+        jar.add(cookie),
+        Json(Session {
+            people_id: people.id,
+            name: people.name,
+            role: people.role,
+        }),
+    ))
+}
+
+pub async fn logout(ctx: Extension<ApiContext>, jar: CookieJar) -> Result<CookieJar, Error> {
+    if let Some(cookie) = jar.get(SESSION_COOKIE) {
+        let token_hash = hash_token(cookie.value());
+        sqlx::query!("DELETE FROM session WHERE token_hash = $1", token_hash)
+            .execute(&ctx.db)
+            .await?;
+    }
+
+    Ok(jar.remove(Cookie::build(SESSION_COOKIE).path("/")))
+}
+
+pub async fn get_session(session: Session) -> Json<Session> {
+    Json(session)
+}
+
+#[derive(Deserialize)]
+pub struct Secret {
+    secret: Option<String>,
+}
+
+/// Set or clear the PIN or password of the logged in person.
+pub async fn update_secret(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Json(secret): Json<Secret>,
+) -> Result<(), Error> {
+    if secret.secret.is_none() {
+        ensure_secret_for_role(session.role)?;
+    }
+
+    let secret_hash = secret.secret.as_deref().map(hash_secret).transpose()?;
+
+    sqlx::query!(
+        "UPDATE people SET secret_hash = $1 WHERE id = $2",
+        secret_hash,
+        session.people_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ApiToken {
+    id: i64,
+    name: String,
+    #[serde(with = "super::serde::iso8601")]
+    created_at: NaiveDateTime,
+    #[serde(with = "super::serde::iso8601_option")]
+    last_used_at: Option<NaiveDateTime>,
+}
+
+pub async fn list_api_tokens(
+    ctx: Extension<ApiContext>,
+    session: Session,
+) -> Result<Json<Vec<ApiToken>>, Error> {
+    let api_tokens = sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT
+            id,
+            name,
+            created_at,
+            last_used_at
+        FROM
+            api_token
+        WHERE
+            people_id = $1
+        ORDER BY
+            id
+        "#,
+        session.people_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(api_tokens))
+}
+
+#[derive(Deserialize)]
+pub struct NewApiToken {
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct CreatedApiToken {
+    id: i64,
+    token: String,
+}
+
+/// Create an API token for scripts. The token is only returned once.
+pub async fn insert_api_token(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Json(new_token): Json<NewApiToken>,
+) -> Result<Json<CreatedApiToken>, Error> {
+    let token = generate_token();
+    let token_hash = hash_token(&token);
+    let now = Utc::now().naive_utc();
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO
+            api_token (people_id, name, token_hash, created_at)
+        VALUES
+            ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+        session.people_id,
+        new_token.name,
+        token_hash,
+        now
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let id = id
+        .id
+        .ok_or_else(|| anyhow::anyhow!("Failed to insert API token"))?;
+
+    Ok(Json(CreatedApiToken { id, token }))
+}
+
+pub async fn delete_api_token(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(token_id): Path<i64>,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "DELETE FROM api_token WHERE id = $1 AND people_id = $2",
+        token_id,
+        session.people_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(())
+}
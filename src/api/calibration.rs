@@ -0,0 +1,524 @@
+use std::collections::HashMap;
+
+use axum::extract::{Json, Path, Query};
+use axum::Extension;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use super::auth::{Role, Session};
+use super::{ApiContext, Error};
+
+#[derive(Serialize)]
+pub struct Calibration {
+    id: i64,
+    instrument_serial: String,
+    probe: Option<String>,
+    /// Sensor data parameter, like `ph` or `do_con`
+    parameter: String,
+    standard_value: Option<f64>,
+    standard_unit: Option<String>,
+    standard_lot: Option<String>,
+    standard_expiry: Option<NaiveDate>,
+    pre_reading: Option<f64>,
+    post_reading: Option<f64>,
+    /// Largest accepted difference between the post-calibration reading and the standard
+    tolerance: Option<f64>,
+    passed: Option<bool>,
+    #[serde(with = "super::serde::iso8601")]
+    calibrated_at: NaiveDateTime,
+    #[serde(with = "super::serde::iso8601_option")]
+    valid_until: Option<NaiveDateTime>,
+    calibrated_by: Option<i64>,
+    comment: Option<String>,
+}
+
+impl Calibration {
+    /// Apply the acceptance criteria, unless pass/fail was recorded explicitly.
+    fn evaluate(mut self) -> Self {
+        if self.passed.is_none() {
+            self.passed = match (self.post_reading, self.standard_value, self.tolerance) {
+                (Some(reading), Some(standard), Some(tolerance)) => {
+                    Some((reading - standard).abs() <= tolerance)
+                }
+                _ => None,
+            };
+        }
+        self
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CalibrationFilter {
+    #[serde(default)]
+    instrument_serial: Option<String>,
+}
+
+async fn fetch_calibrations(
+    ctx: &ApiContext,
+    instrument_serial: Option<&str>,
+) -> Result<Vec<Calibration>, Error> {
+    let calibrations = sqlx::query_as!(
+        Calibration,
+        r#"
+        SELECT
+            id AS "id!",
+            instrument_serial,
+            probe,
+            parameter,
+            standard_value,
+            standard_unit,
+            standard_lot,
+            standard_expiry,
+            pre_reading,
+            post_reading,
+            tolerance,
+            passed,
+            calibrated_at,
+            valid_until,
+            calibrated_by,
+            comment
+        FROM
+            calibration
+        WHERE
+            $1 IS NULL OR instrument_serial = $1
+        ORDER BY
+            calibrated_at DESC, id DESC
+        "#,
+        instrument_serial
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(calibrations
+        .into_iter()
+        .map(Calibration::evaluate)
+        .collect())
+}
+
+pub async fn list_calibrations(
+    ctx: Extension<ApiContext>,
+    Query(filter): Query<CalibrationFilter>,
+) -> Result<Json<Vec<Calibration>>, Error> {
+    Ok(Json(
+        fetch_calibrations(&ctx, filter.instrument_serial.as_deref()).await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct NewCalibration {
+    instrument_serial: String,
+    #[serde(default)]
+    probe: Option<String>,
+    parameter: String,
+    #[serde(default)]
+    standard_value: Option<f64>,
+    #[serde(default)]
+    standard_unit: Option<String>,
+    #[serde(default)]
+    standard_lot: Option<String>,
+    #[serde(default)]
+    standard_expiry: Option<NaiveDate>,
+    #[serde(default)]
+    pre_reading: Option<f64>,
+    #[serde(default)]
+    post_reading: Option<f64>,
+    #[serde(default)]
+    tolerance: Option<f64>,
+    #[serde(default)]
+    passed: Option<bool>,
+    #[serde(with = "super::serde::iso8601")]
+    calibrated_at: NaiveDateTime,
+    #[serde(default, with = "super::serde::iso8601_option")]
+    valid_until: Option<NaiveDateTime>,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+pub async fn insert_calibration(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Json(calibration): Json<NewCalibration>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::FieldTech)?;
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO calibration (
+            instrument_serial,
+            probe,
+            parameter,
+            standard_value,
+            standard_unit,
+            standard_lot,
+            standard_expiry,
+            pre_reading,
+            post_reading,
+            tolerance,
+            passed,
+            calibrated_at,
+            valid_until,
+            calibrated_by,
+            comment
+        )
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+        RETURNING id
+        "#,
+        calibration.instrument_serial,
+        calibration.probe,
+        calibration.parameter,
+        calibration.standard_value,
+        calibration.standard_unit,
+        calibration.standard_lot,
+        calibration.standard_expiry,
+        calibration.pre_reading,
+        calibration.post_reading,
+        calibration.tolerance,
+        calibration.passed,
+        calibration.calibrated_at,
+        calibration.valid_until,
+        session.people_id,
+        calibration.comment
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok(Json(id.id))
+}
+
+pub async fn update_calibration(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(calibration_id): Path<i64>,
+    Json(update): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    for (key, value) in update {
+        match key.as_str() {
+            "instrument_serial" => {
+                let value = value.as_str().ok_or_else(|| {
+                    anyhow::anyhow!("Invalid value for instrument_serial: {:?}", value)
+                })?;
+                sqlx::query!(
+                    "UPDATE calibration SET instrument_serial = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "probe" => {
+                let value = value.as_str();
+                sqlx::query!(
+                    "UPDATE calibration SET probe = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "parameter" => {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid value for parameter: {:?}", value))?;
+                sqlx::query!(
+                    "UPDATE calibration SET parameter = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "standard_value" => {
+                let value: Option<f64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE calibration SET standard_value = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "standard_unit" => {
+                let value = value.as_str();
+                sqlx::query!(
+                    "UPDATE calibration SET standard_unit = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "standard_lot" => {
+                let value = value.as_str();
+                sqlx::query!(
+                    "UPDATE calibration SET standard_lot = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "standard_expiry" => {
+                let value: Option<NaiveDate> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE calibration SET standard_expiry = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "pre_reading" => {
+                let value: Option<f64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE calibration SET pre_reading = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "post_reading" => {
+                let value: Option<f64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE calibration SET post_reading = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "tolerance" => {
+                let value: Option<f64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE calibration SET tolerance = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "passed" => {
+                let value: Option<bool> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE calibration SET passed = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "calibrated_at" => {
+                let value = super::serde::iso8601::deserialize(value)?;
+                sqlx::query!(
+                    "UPDATE calibration SET calibrated_at = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "valid_until" => {
+                let value = super::serde::iso8601_option::deserialize(value)?;
+                sqlx::query!(
+                    "UPDATE calibration SET valid_until = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "comment" => {
+                let value = value.as_str();
+                sqlx::query!(
+                    "UPDATE calibration SET comment = $1 WHERE id = $2",
+                    value,
+                    calibration_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn delete_calibration(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(calibration_id): Path<i64>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    sqlx::query!("DELETE FROM calibration WHERE id = $1", calibration_id)
+        .execute(&ctx.db)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalibrationIssue {
+    /// The task has no instrument serial, calibrations can't be matched
+    NoInstrument,
+    /// A logged parameter without a calibration from before the data was logged
+    NotCalibrated,
+    Failed,
+    /// The calibration was no longer valid when the data was logged
+    Expired,
+    /// The standard solution was past its expiry date when used
+    StandardExpired,
+}
+
+/// Logged parameters which need a calibration, grouped by the parameters one
+/// calibration covers. Temperature and the parameters derived from
+/// conductivity are left out.
+const CALIBRATED_PARAMETERS: &[&[&str]] = &[
+    &["cndct", "spcndct"],
+    &["ph", "ph_mv"],
+    &["orp"],
+    &["do_con", "do_sat", "ppo2"],
+    &["turbidity"],
+    &["pres", "depth"],
+];
+
+#[derive(Serialize)]
+pub struct CalibrationWarning {
+    parameter: Option<String>,
+    calibration_id: Option<i64>,
+    issue: CalibrationIssue,
+}
+
+#[derive(Serialize)]
+pub struct TaskCalibration {
+    instrument_serial: Option<String>,
+    /// Latest calibration of each parameter before the sensor data was logged
+    calibrations: Vec<Calibration>,
+    warnings: Vec<CalibrationWarning>,
+}
+
+pub async fn get_task_calibration(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<TaskCalibration>, Error> {
+    let task = sqlx::query!(
+        r#"
+        SELECT
+            task.instrument_serial,
+            MIN(sensor_data.datetime) AS "logged_from: NaiveDateTime",
+            MAX(sensor_data.datetime) AS "logged_to: NaiveDateTime",
+            COUNT(sensor_data.turbidity) AS "turbidity!: i64",
+            COUNT(COALESCE(sensor_data.pres, sensor_data.depth)) AS "pressure!: i64"
+        FROM
+            task
+        LEFT JOIN sensor_data
+            ON sensor_data.task_id = task.id
+        WHERE
+            task.id = $1
+        GROUP BY
+            task.id
+        "#,
+        task_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let Some(instrument_serial) = task.instrument_serial else {
+        return Ok(Json(TaskCalibration {
+            instrument_serial: None,
+            calibrations: Vec::new(),
+            warnings: match task.logged_from {
+                Some(_) => vec![CalibrationWarning {
+                    parameter: None,
+                    calibration_id: None,
+                    issue: CalibrationIssue::NoInstrument,
+                }],
+                None => Vec::new(),
+            },
+        }));
+    };
+
+    // Calibrations are ordered newest first, keep the first one of each parameter
+    // which was done before the data was logged.
+    let mut calibrations: Vec<Calibration> = Vec::new();
+    for calibration in fetch_calibrations(&ctx, Some(&instrument_serial)).await? {
+        let before_logging = task
+            .logged_from
+            .is_none_or(|logged_from| calibration.calibrated_at <= logged_from);
+        if before_logging
+            && !calibrations
+                .iter()
+                .any(|applied| applied.parameter == calibration.parameter)
+        {
+            calibrations.push(calibration);
+        }
+    }
+
+    let mut warnings = Vec::new();
+    if let Some(logged_to) = task.logged_to {
+        for calibration in &calibrations {
+            let mut warn = |issue| {
+                warnings.push(CalibrationWarning {
+                    parameter: Some(calibration.parameter.clone()),
+                    calibration_id: Some(calibration.id),
+                    issue,
+                })
+            };
+
+            if calibration.passed == Some(false) {
+                warn(CalibrationIssue::Failed);
+            }
+            if calibration
+                .valid_until
+                .is_some_and(|valid_until| valid_until < logged_to)
+            {
+                warn(CalibrationIssue::Expired);
+            }
+            if calibration
+                .standard_expiry
+                .is_some_and(|expiry| expiry < calibration.calibrated_at.date())
+            {
+                warn(CalibrationIssue::StandardExpired);
+            }
+        }
+
+        // The other parameters are stored with every record
+        let logged = |parameter: &str| match parameter {
+            "turbidity" => task.turbidity > 0,
+            "pres" => task.pressure > 0,
+            _ => true,
+        };
+
+        for group in CALIBRATED_PARAMETERS {
+            let calibrated = calibrations.iter().any(|calibration| {
+                group
+                    .iter()
+                    .any(|parameter| calibration.parameter.eq_ignore_ascii_case(parameter))
+            });
+            if logged(group[0]) && !calibrated {
+                warnings.push(CalibrationWarning {
+                    parameter: Some(group[0].to_string()),
+                    calibration_id: None,
+                    issue: CalibrationIssue::NotCalibrated,
+                });
+            }
+        }
+    }
+
+    Ok(Json(TaskCalibration {
+        instrument_serial: Some(instrument_serial),
+        calibrations,
+        warnings,
+    }))
+}
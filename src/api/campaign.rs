@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use super::auth::{Role, Session};
+use super::task_status::{record_status, TaskStatus};
+use super::{ApiContext, Error};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Campaign {
+    id: i64,
+    name: String,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    description: Option<String>,
+}
+
+pub async fn list_campaigns(ctx: Extension<ApiContext>) -> Result<Json<Vec<Campaign>>, Error> {
+    let campaigns = sqlx::query_as!(
+        Campaign,
+        r#"
+        SELECT
+            id,
+            name,
+            start_date,
+            end_date,
+            description
+        FROM
+            campaign
+        ORDER BY
+            start_date DESC, id DESC
+        "#
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(Json(campaigns))
+}
+
+#[derive(Deserialize)]
+pub struct NewCampaign {
+    name: String,
+    #[serde(default)]
+    start_date: Option<NaiveDate>,
+    #[serde(default)]
+    end_date: Option<NaiveDate>,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+pub async fn insert_campaign(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Json(campaign): Json<NewCampaign>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::DataManager)?;
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO
+            campaign (name, start_date, end_date, description)
+        VALUES
+            ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+        campaign.name,
+        campaign.start_date,
+        campaign.end_date,
+        campaign.description
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok(Json(id.id))
+}
+
+pub async fn update_campaign(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(campaign_id): Path<i64>,
+    Json(update): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    for (key, value) in update {
+        match key.as_str() {
+            "name" => {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid value for name: {:?}", value))?;
+                sqlx::query!(
+                    "UPDATE campaign SET name = $1 WHERE id = $2",
+                    value,
+                    campaign_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "start_date" => {
+                let value: Option<NaiveDate> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE campaign SET start_date = $1 WHERE id = $2",
+                    value,
+                    campaign_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "end_date" => {
+                let value: Option<NaiveDate> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE campaign SET end_date = $1 WHERE id = $2",
+                    value,
+                    campaign_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "description" => {
+                let value = value.as_str();
+                sqlx::query!(
+                    "UPDATE campaign SET description = $1 WHERE id = $2",
+                    value,
+                    campaign_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Delete a campaign, its tasks are kept but no longer assigned to a campaign.
+pub async fn delete_campaign(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(campaign_id): Path<i64>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    sqlx::query!(
+        "UPDATE task SET campaign_id = NULL WHERE campaign_id = $1",
+        campaign_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!("DELETE FROM campaign WHERE id = $1", campaign_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Create the next round of a campaign, with a new planned task for each task of
+/// the source campaign using the same well, depth and sample set.
+pub async fn clone_campaign(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(campaign_id): Path<i64>,
+    Json(campaign): Json<NewCampaign>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::DataManager)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    sqlx::query!("SELECT id FROM campaign WHERE id = $1", campaign_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::CampaignNotFound(campaign_id))?;
+
+    let new_campaign_id = sqlx::query!(
+        r#"
+        INSERT INTO
+            campaign (name, start_date, end_date, description)
+        VALUES
+            ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+        campaign.name,
+        campaign.start_date,
+        campaign.end_date,
+        campaign.description
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .id;
+
+    let tasks = sqlx::query!(
+        "SELECT id, well_id, depth FROM task WHERE campaign_id = $1 ORDER BY id",
+        campaign_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for task in tasks {
+        let new_task_id = sqlx::query!(
+            r#"
+            INSERT INTO
+                task (well_id, depth, campaign_id)
+            VALUES
+                ($1, $2, $3)
+            RETURNING id
+            "#,
+            task.well_id,
+            task.depth,
+            new_campaign_id
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .id
+        .ok_or_else(|| anyhow::anyhow!("Failed to insert task"))?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO
+                sample_set (task_id, sample_type_id, qty)
+            SELECT
+                $1, sample_type_id, qty
+            FROM
+                sample_set
+            WHERE
+                task_id = $2
+            "#,
+            new_task_id,
+            task.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        record_status(
+            &mut tx,
+            new_task_id,
+            TaskStatus::Planned,
+            session.people_id,
+            None,
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(new_campaign_id))
+}
@@ -0,0 +1,526 @@
+//! Static charts of the sensor data of a task, as SVG for reports and emails
+//! and in the PDF field sheet.
+
+use std::collections::HashSet;
+
+use axum::extract::{Path, Query};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::Extension;
+use chrono::{DateTime, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde::Deserialize;
+
+use super::derived::DeriveMode;
+use super::field_parameters::{stable_runs, STABILITY_CRITERIA};
+use super::format::{escape_xml, label, local_seconds, local_time, number, seconds};
+use super::qc::QcFlag;
+use super::sensor_data::{fetch_sensor_data, SensorRecord, PARAMETERS};
+use super::task_info::fetch_task_info;
+use super::{ApiContext, Error};
+
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 40.0;
+const PANEL_GAP: f64 = 36.0;
+const LEGEND_HEIGHT: f64 = 50.0;
+
+const DEFAULT_WIDTH: f64 = 800.0;
+const DEFAULT_PANEL_HEIGHT: f64 = 160.0;
+
+const SERIES_COLOR: &str = "#1f77b4";
+const STABLE_COLOR: &str = "#d9f2d9";
+const SUSPECT_COLOR: &str = "#ff9900";
+const BAD_COLOR: &str = "#d62728";
+const GRID_COLOR: &str = "#e0e0e0";
+
+/// Minutes between time axis ticks, the first giving at most `MAX_TICKS`.
+const TICK_MINUTES: &[i64] = &[1, 2, 5, 10, 15, 30, 60, 120, 180, 360, 720, 1440];
+const MAX_TICKS: i64 = 8;
+
+#[derive(Deserialize)]
+pub struct ChartQuery {
+    /// Comma separated parameters, one panel each. Defaults to the parameters
+    /// with stabilization criteria.
+    #[serde(default)]
+    params: Option<String>,
+    #[serde(default)]
+    width: Option<f64>,
+    /// Height of each panel
+    #[serde(default)]
+    height: Option<f64>,
+}
+
+/// Tick times of the time axis, on whole local minutes.
+fn time_ticks(tz: Tz, t0: f64, t1: f64) -> Vec<f64> {
+    let minutes = ((t1 - t0) / 60.0).max(1.0);
+    let step = TICK_MINUTES
+        .iter()
+        .find(|step| (minutes / **step as f64) as i64 <= MAX_TICKS)
+        .copied()
+        .unwrap_or(1440)
+        * 60;
+
+    let offset = DateTime::from_timestamp(t0 as i64, 0)
+        .map(|datetime| {
+            tz.offset_from_utc_datetime(&datetime.naive_utc())
+                .fix()
+                .local_minus_utc() as i64
+        })
+        .unwrap_or_default();
+
+    let first = ((t0 as i64 + offset) / step + 1) * step - offset;
+    (0..)
+        .map(|idx| (first + idx * step) as f64)
+        .take_while(|t| *t <= t1)
+        .collect()
+}
+
+/// One parameter over time. Times are seconds since the epoch.
+pub(crate) struct Panel<'a> {
+    pub(crate) parameter: &'a str,
+    /// Padded value range of the axis, `None` without values
+    pub(crate) range: Option<(f64, f64)>,
+    /// Lines through the values, broken at gaps
+    pub(crate) lines: Vec<Vec<(f64, f64)>>,
+    /// Stabilization windows, from the same readings as the final field
+    /// parameters
+    pub(crate) stable: Vec<(f64, f64)>,
+    /// Suspect and bad values
+    pub(crate) flagged: Vec<(f64, f64, QcFlag)>,
+}
+
+impl<'a> Panel<'a> {
+    fn new(parameter: &'a str, records: &[SensorRecord]) -> Self {
+        let mut lines = Vec::new();
+        let mut line = Vec::new();
+        for record in records {
+            match record.parameter(parameter) {
+                Some(value) => line.push((seconds(record.datetime), value)),
+                None if !line.is_empty() => lines.push(std::mem::take(&mut line)),
+                None => {}
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+
+        let values = lines.iter().flatten().map(|(_, value)| *value);
+        let range = match (values.clone().reduce(f64::min), values.reduce(f64::max)) {
+            (Some(min), Some(max)) => {
+                let (min, max) = if max > min {
+                    (min, max)
+                } else {
+                    (min - 0.5, max + 0.5)
+                };
+                let pad = (max - min) * 0.05;
+                Some((min - pad, max + pad))
+            }
+            _ => None,
+        };
+
+        let stable = match STABILITY_CRITERIA
+            .iter()
+            .find(|(name, _)| *name == parameter)
+        {
+            Some((_, criterion)) => {
+                let readings: Vec<(NaiveDateTime, f64)> = records
+                    .iter()
+                    .filter(|record| record.qc_flag_of(parameter) != Some(QcFlag::Bad))
+                    .filter_map(|record| Some((record.datetime, record.parameter(parameter)?)))
+                    .collect();
+                stable_runs(&readings, *criterion)
+                    .into_iter()
+                    .map(|(start, end)| (seconds(readings[start].0), seconds(readings[end].0)))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        let flagged = records
+            .iter()
+            .filter_map(|record| {
+                let flag = record
+                    .qc_flag_of(parameter)
+                    .filter(|flag| matches!(flag, QcFlag::Suspect | QcFlag::Bad))?;
+                Some((seconds(record.datetime), record.parameter(parameter)?, flag))
+            })
+            .collect();
+
+        Self {
+            parameter,
+            range,
+            lines,
+            stable,
+            flagged,
+        }
+    }
+
+    /// Vertical position of a value as a fraction of the range, from the
+    /// bottom.
+    pub(crate) fn position(&self, value: f64) -> f64 {
+        match self.range {
+            Some((min, max)) => (value - min) / (max - min),
+            None => 0.5,
+        }
+    }
+}
+
+/// Parameters of the sensor data of a task over a common time axis, which
+/// includes the sampling time.
+pub(crate) struct Chart<'a> {
+    pub(crate) t0: f64,
+    pub(crate) t1: f64,
+    pub(crate) sampling: Option<f64>,
+    pub(crate) panels: Vec<Panel<'a>>,
+}
+
+impl<'a> Chart<'a> {
+    pub(crate) fn new(
+        records: &[SensorRecord],
+        parameters: &[&'a str],
+        sampling_time: Option<NaiveDateTime>,
+    ) -> Self {
+        let sampling = sampling_time.map(seconds);
+        let times = records
+            .iter()
+            .map(|record| seconds(record.datetime))
+            .chain(sampling);
+        Self {
+            t0: times.clone().reduce(f64::min).unwrap_or_default(),
+            t1: times.reduce(f64::max).unwrap_or_default(),
+            sampling,
+            panels: parameters
+                .iter()
+                .map(|parameter| Panel::new(parameter, records))
+                .collect(),
+        }
+    }
+
+    /// Horizontal position of a time as a fraction of the time axis.
+    pub(crate) fn position(&self, t: f64) -> f64 {
+        let duration = if self.t1 > self.t0 {
+            self.t1 - self.t0
+        } else {
+            1.0
+        };
+        (t - self.t0) / duration
+    }
+
+    /// The chart as SVG, one panel below the other.
+    pub(crate) fn svg(&self, tz: Tz, title: &str, width: f64, panel_height: f64) -> String {
+        let count = self.panels.len() as f64;
+        let height = MARGIN_TOP + count * (panel_height + PANEL_GAP) + LEGEND_HEIGHT;
+        let mut svg = Svg {
+            svg: format!(
+                concat!(
+                    r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}" "#,
+                    r#"font-family="sans-serif" font-size="11">"#,
+                    "\n",
+                    r#"<rect width="{w}" height="{h}" fill="white"/>"#,
+                    "\n"
+                ),
+                w = width,
+                h = height
+            ),
+            tz,
+            left: MARGIN_LEFT,
+            width: width - MARGIN_LEFT - MARGIN_RIGHT,
+            height: panel_height,
+        };
+
+        svg.text(MARGIN_LEFT, 18.0, "start", "font-size=\"14\"", title);
+
+        for (idx, panel) in self.panels.iter().enumerate() {
+            let top = MARGIN_TOP + 14.0 + idx as f64 * (panel_height + PANEL_GAP);
+            svg.panel(self, panel, top, idx + 1 == self.panels.len());
+        }
+
+        if let Some(sampling) = self.sampling {
+            let x = svg.x(self, sampling);
+            let bottom = MARGIN_TOP + 14.0 + count * (panel_height + PANEL_GAP) - PANEL_GAP;
+            svg.svg.push_str(&format!(
+                "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-width=\"1.5\" stroke-dasharray=\"4,3\"/>\n",
+                x,
+                MARGIN_TOP + 14.0,
+                x,
+                bottom,
+                BAD_COLOR
+            ));
+            let text = format!("Sampling {}", local_seconds(tz, sampling, "%H:%M"));
+            svg.text(
+                x,
+                MARGIN_TOP - 6.0,
+                "middle",
+                &format!("fill=\"{}\"", BAD_COLOR),
+                &text,
+            );
+        }
+
+        svg.legend(height - LEGEND_HEIGHT / 2.0 + 10.0);
+        svg.svg.push_str("</svg>\n");
+        svg.svg
+    }
+}
+
+/// Title of the chart of a task.
+pub(crate) fn chart_title(tz: Tz, task_id: i64, records: &[SensorRecord]) -> String {
+    match records.first() {
+        Some(first) => format!(
+            "Task {}, {} ({})",
+            task_id,
+            local_time(tz, first.datetime, "%Y-%m-%d"),
+            tz.name()
+        ),
+        None => format!("Task {}, no sensor data", task_id),
+    }
+}
+
+/// The parameters with stabilization criteria, charted by default.
+pub(crate) fn default_parameters() -> Vec<&'static str> {
+    STABILITY_CRITERIA
+        .iter()
+        .map(|(parameter, _)| *parameter)
+        .collect()
+}
+
+/// Chart of the parameters with stabilization criteria as SVG, in the default
+/// size.
+pub(crate) fn default_chart_svg(
+    tz: Tz,
+    task_id: i64,
+    records: &[SensorRecord],
+    sampling_time: Option<NaiveDateTime>,
+) -> String {
+    Chart::new(records, &default_parameters(), sampling_time).svg(
+        tz,
+        &chart_title(tz, task_id, records),
+        DEFAULT_WIDTH,
+        DEFAULT_PANEL_HEIGHT,
+    )
+}
+
+struct Svg {
+    svg: String,
+    tz: Tz,
+    left: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Svg {
+    fn x(&self, chart: &Chart, t: f64) -> f64 {
+        self.left + chart.position(t) * self.width
+    }
+
+    fn text(&mut self, x: f64, y: f64, anchor: &str, attrs: &str, text: &str) {
+        self.svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{}\" {}>{}</text>\n",
+            x,
+            y,
+            anchor,
+            attrs,
+            escape_xml(text)
+        ));
+    }
+
+    fn panel(&mut self, chart: &Chart, panel: &Panel, top: f64, last: bool) {
+        let height = self.height;
+
+        self.text(
+            self.left,
+            top - 6.0,
+            "start",
+            "font-weight=\"bold\"",
+            label(panel.parameter),
+        );
+
+        for (start, end) in &panel.stable {
+            let (x0, x1) = (self.x(chart, *start), self.x(chart, *end));
+            self.svg.push_str(&format!(
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"/>\n",
+                x0,
+                top,
+                x1 - x0,
+                height,
+                STABLE_COLOR
+            ));
+        }
+
+        let Some((min, max)) = panel.range else {
+            self.frame(top);
+            self.text(
+                self.left + self.width / 2.0,
+                top + height / 2.0,
+                "middle",
+                "fill=\"#888\"",
+                "No data",
+            );
+            return;
+        };
+        let y = |value: f64| top + height - panel.position(value) * height;
+
+        for idx in 0..=4 {
+            let value = min + (max - min) * idx as f64 / 4.0;
+            self.svg.push_str(&format!(
+                "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\"/>\n",
+                self.left,
+                y(value),
+                self.left + self.width,
+                y(value),
+                GRID_COLOR
+            ));
+            self.text(self.left - 6.0, y(value) + 4.0, "end", "", &number(value));
+        }
+
+        for t in time_ticks(self.tz, chart.t0, chart.t1) {
+            self.svg.push_str(&format!(
+                "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\"/>\n",
+                self.x(chart, t),
+                top,
+                self.x(chart, t),
+                top + height,
+                GRID_COLOR
+            ));
+            if last {
+                let text = local_seconds(self.tz, t, "%H:%M");
+                self.text(self.x(chart, t), top + height + 14.0, "middle", "", &text);
+            }
+        }
+
+        let path: Vec<String> = panel
+            .lines
+            .iter()
+            .flat_map(|line| {
+                line.iter().enumerate().map(|(idx, (t, value))| {
+                    format!(
+                        "{}{:.1},{:.1}",
+                        if idx == 0 { "M" } else { "L" },
+                        self.x(chart, *t),
+                        y(*value)
+                    )
+                })
+            })
+            .collect();
+        self.svg.push_str(&format!(
+            "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\"/>\n",
+            path.join(" "),
+            SERIES_COLOR
+        ));
+
+        for (t, value, flag) in &panel.flagged {
+            let color = match flag {
+                QcFlag::Bad => BAD_COLOR,
+                _ => SUSPECT_COLOR,
+            };
+            self.svg.push_str(&format!(
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3.5\" fill=\"{}\"/>\n",
+                self.x(chart, *t),
+                y(*value),
+                color
+            ));
+        }
+
+        self.frame(top);
+    }
+
+    fn frame(&mut self, top: f64) {
+        self.svg.push_str(&format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"none\" stroke=\"#888\"/>\n",
+            self.left, top, self.width, self.height
+        ));
+    }
+
+    fn legend(&mut self, y: f64) {
+        let items = [
+            (
+                format!(
+                    "<rect x=\"0\" y=\"-9\" width=\"14\" height=\"10\" fill=\"{}\"/>",
+                    STABLE_COLOR
+                ),
+                "Stabilized",
+            ),
+            (
+                format!("<circle cx=\"7\" cy=\"-4\" r=\"3.5\" fill=\"{}\"/>", SUSPECT_COLOR),
+                "Suspect",
+            ),
+            (
+                format!("<circle cx=\"7\" cy=\"-4\" r=\"3.5\" fill=\"{}\"/>", BAD_COLOR),
+                "Bad",
+            ),
+            (
+                format!(
+                    "<line x1=\"7\" y1=\"-10\" x2=\"7\" y2=\"2\" stroke=\"{}\" stroke-dasharray=\"4,3\"/>",
+                    BAD_COLOR
+                ),
+                "Sampling",
+            ),
+        ];
+        for (idx, (symbol, text)) in items.iter().enumerate() {
+            let x = self.left + idx as f64 * 110.0;
+            self.svg.push_str(&format!(
+                "<g transform=\"translate({:.1},{:.1})\">{}</g>\n",
+                x, y, symbol
+            ));
+            self.text(x + 20.0, y, "start", "", text);
+        }
+    }
+}
+
+/// Chart of sensor data parameters of a task over time as SVG, one panel per
+/// parameter, with stabilization windows, QC flagged values and the sampling
+/// time.
+pub async fn get_chart(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+    Query(query): Query<ChartQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let mut parameters: Vec<&str> = match &query.params {
+        Some(params) => params
+            .split(',')
+            .map(str::trim)
+            .filter(|param| !param.is_empty())
+            .collect(),
+        None => default_parameters(),
+    };
+    if parameters.is_empty() {
+        return Err(anyhow::anyhow!("No parameters").into());
+    }
+    if let Some(parameter) = parameters.iter().find(|param| !PARAMETERS.contains(*param)) {
+        return Err(anyhow::anyhow!("Invalid parameter: {:?}", parameter).into());
+    }
+    // The height grows with the panels, one per parameter at most
+    let mut seen = HashSet::new();
+    parameters.retain(|param| seen.insert(*param));
+    parameters.truncate(PARAMETERS.len());
+    // NaN passes through `clamp`
+    if let Some(size) = [query.width, query.height]
+        .into_iter()
+        .flatten()
+        .find(|size| !size.is_finite())
+    {
+        return Err(anyhow::anyhow!("Invalid size: {}", size).into());
+    }
+
+    let records = fetch_sensor_data(&ctx.db, task_id, DeriveMode::default()).await?;
+    let sampling_time = fetch_task_info(&ctx.db, task_id)
+        .await?
+        .into_iter()
+        .find_map(|info| info.sampling_time);
+
+    let width = query.width.unwrap_or(DEFAULT_WIDTH).clamp(300.0, 4000.0);
+    let panel_height = query
+        .height
+        .unwrap_or(DEFAULT_PANEL_HEIGHT)
+        .clamp(60.0, 1000.0);
+
+    let tz = ctx.settings.time_zone;
+    let svg = Chart::new(&records, &parameters, sampling_time).svg(
+        tz,
+        &chart_title(tz, task_id, &records),
+        width,
+        panel_height,
+    );
+
+    Ok(([(header::CONTENT_TYPE, "image/svg+xml")], svg))
+}
@@ -0,0 +1,304 @@
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::auth::{Role, Session};
+use super::finalize::ensure_unlocked;
+use super::{ApiContext, Error};
+
+/// A shift of the sensor data of a task, for a logger clock which was off.
+#[derive(Serialize)]
+pub struct ClockOffset {
+    id: i64,
+    task_id: i64,
+    /// Added to the logged timestamps
+    seconds: i64,
+    #[serde(with = "super::serde::iso8601")]
+    start_time: NaiveDateTime,
+    #[serde(with = "super::serde::iso8601")]
+    end_time: NaiveDateTime,
+    /// Shifted records
+    records: i64,
+    comment: Option<String>,
+    created_by: i64,
+    #[serde(with = "super::serde::iso8601")]
+    created_at: NaiveDateTime,
+}
+
+async fn fetch_clock_offsets(db: &SqlitePool, task_id: i64) -> Result<Vec<ClockOffset>, Error> {
+    let offsets = sqlx::query_as!(
+        ClockOffset,
+        r#"
+        SELECT
+            id AS "id!",
+            task_id,
+            seconds,
+            start_time,
+            end_time,
+            records,
+            comment,
+            created_by,
+            created_at
+        FROM
+            clock_offset
+        WHERE
+            task_id = $1
+        ORDER BY
+            id
+        "#,
+        task_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(offsets)
+}
+
+pub async fn list_clock_offsets(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<Vec<ClockOffset>>, Error> {
+    Ok(Json(fetch_clock_offsets(&ctx.db, task_id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct NewClockOffset {
+    /// Correct time minus logger time
+    seconds: i64,
+    /// Defaults to the first logged record of the task
+    #[serde(default, with = "super::serde::iso8601_option")]
+    start_time: Option<NaiveDateTime>,
+    /// Defaults to the last logged record of the task
+    #[serde(default, with = "super::serde::iso8601_option")]
+    end_time: Option<NaiveDateTime>,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+/// Shift the sensor data of a task in a time range. Corrections and QC flags
+/// within the range are shifted along.
+pub async fn insert_clock_offset(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(task_id): Path<i64>,
+    Json(offset): Json<NewClockOffset>,
+) -> Result<Json<ClockOffset>, Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    if offset.seconds == 0 {
+        return Err(anyhow::anyhow!("Clock offset is zero").into());
+    }
+
+    let range = sqlx::query!(
+        r#"
+        SELECT
+            MIN(datetime) AS "first: NaiveDateTime",
+            MAX(datetime) AS "last: NaiveDateTime"
+        FROM
+            sensor_data
+        WHERE
+            task_id = $1
+        "#,
+        task_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let start_time = offset
+        .start_time
+        .or(range.first)
+        .ok_or_else(|| anyhow::anyhow!("No sensor data for task {}", task_id))?;
+    let end_time = offset
+        .end_time
+        .or(range.last)
+        .ok_or_else(|| anyhow::anyhow!("No sensor data for task {}", task_id))?;
+
+    if end_time < start_time {
+        return Err(anyhow::anyhow!("Shifted range ends before it starts").into());
+    }
+
+    let shift = Duration::seconds(offset.seconds);
+    let (shifted_start, shifted_end) = (start_time + shift, end_time + shift);
+
+    let overlapping = sqlx::query_scalar!(
+        r#"
+        SELECT
+            COUNT(*) AS "count!: i64"
+        FROM
+            sensor_data
+        WHERE
+            task_id = $1
+            AND datetime >= $2 AND datetime <= $3
+            AND NOT (datetime >= $4 AND datetime <= $5)
+        "#,
+        task_id,
+        shifted_start,
+        shifted_end,
+        start_time,
+        end_time
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    if overlapping > 0 {
+        return Err(anyhow::anyhow!(
+            "Shifted records would overlap {} other records",
+            overlapping
+        )
+        .into());
+    }
+
+    let mut tx = ctx.db.begin().await?;
+
+    // Shift the last records first when moving forward, so that records never
+    // pass each other
+    let mut datetimes = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT
+            datetime AS "datetime!: NaiveDateTime"
+        FROM
+            sensor_data
+        WHERE
+            task_id = $1 AND datetime >= $2 AND datetime <= $3
+        ORDER BY
+            datetime
+        "#,
+        task_id,
+        start_time,
+        end_time
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if offset.seconds > 0 {
+        datetimes.reverse();
+    }
+
+    let mut records = 0;
+    for datetime in datetimes {
+        let shifted = datetime + shift;
+        let result = sqlx::query!(
+            "UPDATE sensor_data SET datetime = $1 WHERE task_id = $2 AND datetime = $3",
+            shifted,
+            task_id,
+            datetime
+        )
+        .execute(&mut *tx)
+        .await?;
+        records += result.rows_affected() as i64;
+    }
+
+    let corrections = sqlx::query!(
+        r#"
+        SELECT
+            id AS "id!",
+            start_time,
+            end_time
+        FROM
+            sensor_correction
+        WHERE
+            task_id = $1 AND start_time >= $2 AND end_time <= $3
+        "#,
+        task_id,
+        start_time,
+        end_time
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for correction in corrections {
+        let (start, end) = (correction.start_time + shift, correction.end_time + shift);
+        sqlx::query!(
+            "UPDATE sensor_correction SET start_time = $1, end_time = $2 WHERE id = $3",
+            start,
+            end,
+            correction.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let flags = sqlx::query!(
+        r#"
+        SELECT
+            id AS "id!",
+            start_time,
+            end_time
+        FROM
+            sensor_qc_flag
+        WHERE
+            task_id = $1 AND start_time >= $2 AND end_time <= $3
+        "#,
+        task_id,
+        start_time,
+        end_time
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for flag in flags {
+        let (start, end) = (flag.start_time + shift, flag.end_time + shift);
+        sqlx::query!(
+            "UPDATE sensor_qc_flag SET start_time = $1, end_time = $2 WHERE id = $3",
+            start,
+            end,
+            flag.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let now = Utc::now().naive_utc();
+
+    let clock_offset = sqlx::query_as!(
+        ClockOffset,
+        r#"
+        INSERT INTO clock_offset (
+            task_id,
+            seconds,
+            start_time,
+            end_time,
+            records,
+            comment,
+            created_by,
+            created_at
+        )
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING
+            id AS "id!",
+            task_id,
+            seconds,
+            start_time,
+            end_time,
+            records,
+            comment,
+            created_by,
+            created_at
+        "#,
+        task_id,
+        offset.seconds,
+        start_time,
+        end_time,
+        records,
+        offset.comment,
+        session.people_id,
+        now
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    tracing::info!(
+        "Sensor data of task {} shifted by {} s by people {}",
+        task_id,
+        offset.seconds,
+        session.people_id
+    );
+
+    Ok(Json(clock_offset))
+}
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use serde::Deserialize;
+
+/// Tags produced by the rich text editor (tiptap `StarterKit`).
+const ALLOWED_TAGS: &[&str] = &[
+    "p",
+    "br",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "strong",
+    "b",
+    "em",
+    "i",
+    "s",
+    "code",
+    "pre",
+    "blockquote",
+    "ul",
+    "ol",
+    "li",
+    "hr",
+];
+
+/// Line width for Markdown and plain text, large enough to never wrap a paragraph.
+const TEXT_WIDTH: usize = 10_000;
+
+static SANITIZER: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| {
+    let mut builder = ammonia::Builder::empty();
+    builder.tags(ALLOWED_TAGS.iter().copied().collect::<HashSet<_>>());
+    builder
+});
+
+/// Strip everything from comment HTML which is not on the allow-list.
+pub fn sanitize(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommentFormat {
+    #[default]
+    Html,
+    Markdown,
+    Text,
+}
+
+impl CommentFormat {
+    /// Render stored comment HTML. Stored comments are sanitized again, since
+    /// older rows were written before sanitizing on insert.
+    pub fn render(self, html: &str) -> String {
+        let html = sanitize(html);
+        let rendered = match self {
+            CommentFormat::Html => return html,
+            CommentFormat::Markdown => html2text::from_read(html.as_bytes(), TEXT_WIDTH),
+            CommentFormat::Text => html2text::from_read_with_decorator(
+                html.as_bytes(),
+                TEXT_WIDTH,
+                html2text::render::TrivialDecorator::new(),
+            ),
+        };
+
+        rendered
+            .map(|text| text.trim_end().to_string())
+            .unwrap_or(html)
+    }
+
+    pub fn render_option(self, html: Option<String>) -> Option<String> {
+        html.map(|html| self.render(&html))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CommentFormatQuery {
+    #[serde(default)]
+    pub format: CommentFormat,
+}
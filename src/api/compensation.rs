@@ -0,0 +1,502 @@
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::auth::{Role, Session};
+use super::derived::{density, DeriveMode};
+use super::finalize::ensure_unlocked;
+use super::sensor_data::fetch_sensor_data;
+use super::water_level::{fetch_water_level_readings, GROUND_SURFACE, TOP_OF_CASING};
+use super::{ApiContext, Error};
+
+const PASCAL_PER_PSI: f64 = 6_894.757;
+
+/// Standard gravity, m/s².
+const GRAVITY: f64 = 9.806_65;
+
+#[derive(Serialize)]
+pub struct BaroSeries {
+    id: i64,
+    name: String,
+    comment: Option<String>,
+    uploaded_by: i64,
+    #[serde(with = "super::serde::iso8601")]
+    uploaded_at: NaiveDateTime,
+    readings: i64,
+    #[serde(with = "super::serde::iso8601_option")]
+    first: Option<NaiveDateTime>,
+    #[serde(with = "super::serde::iso8601_option")]
+    last: Option<NaiveDateTime>,
+}
+
+pub async fn list_baro_series(ctx: Extension<ApiContext>) -> Result<Json<Vec<BaroSeries>>, Error> {
+    let series = sqlx::query_as!(
+        BaroSeries,
+        r#"
+        SELECT
+            baro_series.id AS "id!",
+            baro_series.name AS "name!",
+            baro_series.comment,
+            baro_series.uploaded_by AS "uploaded_by!",
+            baro_series.uploaded_at AS "uploaded_at!",
+            COUNT(baro_reading.series_id) AS "readings!: i64",
+            MIN(baro_reading.datetime) AS "first: NaiveDateTime",
+            MAX(baro_reading.datetime) AS "last: NaiveDateTime"
+        FROM
+            baro_series
+        LEFT JOIN baro_reading
+            ON baro_reading.series_id = baro_series.id
+        GROUP BY
+            baro_series.id
+        ORDER BY
+            baro_series.id DESC
+        "#
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(Json(series))
+}
+
+#[derive(Deserialize)]
+pub struct BaroReading {
+    #[serde(with = "super::serde::iso8601")]
+    datetime: NaiveDateTime,
+    /// PSI
+    pressure: f64,
+}
+
+#[derive(Deserialize)]
+pub struct NewBaroSeries {
+    name: String,
+    #[serde(default)]
+    comment: Option<String>,
+    readings: Vec<BaroReading>,
+}
+
+pub async fn insert_baro_series(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Json(series): Json<NewBaroSeries>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::FieldTech)?;
+
+    let now = Utc::now().naive_utc();
+
+    let mut tx = ctx.db.begin().await?;
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO
+            baro_series (name, comment, uploaded_by, uploaded_at)
+        VALUES
+            ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+        series.name,
+        series.comment,
+        session.people_id,
+        now
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .id
+    .ok_or_else(|| anyhow::anyhow!("Failed to insert barometric series"))?;
+
+    for reading in series.readings {
+        sqlx::query!(
+            r#"
+            INSERT OR REPLACE INTO
+                baro_reading (series_id, datetime, pressure)
+            VALUES
+                ($1, $2, $3)
+            "#,
+            id,
+            reading.datetime,
+            reading.pressure
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(id))
+}
+
+pub async fn delete_baro_series(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(series_id): Path<i64>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    sqlx::query!("DELETE FROM baro_series WHERE id = $1", series_id)
+        .execute(&ctx.db)
+        .await?;
+
+    Ok(())
+}
+
+/// Barometric pressure at a point in time, linearly interpolated between the
+/// readings of a series. None outside the period the series covers.
+fn interpolate(readings: &[(NaiveDateTime, f64)], datetime: NaiveDateTime) -> Option<f64> {
+    let idx = readings.partition_point(|(t, _)| *t < datetime);
+    let (t1, p1) = *readings.get(idx)?;
+    if t1 == datetime {
+        return Some(p1);
+    }
+
+    let (t0, p0) = *readings.get(idx.checked_sub(1)?)?;
+    let fraction = (datetime - t0).num_milliseconds() as f64 / (t1 - t0).num_milliseconds() as f64;
+    Some(p0 + (p1 - p0) * fraction)
+}
+
+/// Height in metres of a water column with a density in g/cm³, from its
+/// pressure in psi.
+fn water_column(pressure: f64, density: f64) -> f64 {
+    pressure * PASCAL_PER_PSI / (density * 1000.0 * GRAVITY)
+}
+
+async fn fetch_baro_readings(
+    db: &SqlitePool,
+    series_id: i64,
+) -> Result<Vec<(NaiveDateTime, f64)>, Error> {
+    let readings = sqlx::query!(
+        r#"
+        SELECT
+            datetime,
+            pressure
+        FROM
+            baro_reading
+        WHERE
+            series_id = $1
+        ORDER BY
+            datetime
+        "#,
+        series_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(readings
+        .into_iter()
+        .map(|reading| (reading.datetime, reading.pressure))
+        .collect())
+}
+
+#[derive(Serialize)]
+pub struct PressureCompensation {
+    id: i64,
+    task_id: i64,
+    vented: bool,
+    baro_series_id: Option<i64>,
+    reference_depth_to_water: Option<f64>,
+    reference_point: Option<String>,
+    #[serde(with = "super::serde::iso8601_option")]
+    reference_time: Option<NaiveDateTime>,
+    compensated: i64,
+    skipped: i64,
+    created_by: i64,
+    #[serde(with = "super::serde::iso8601")]
+    created_at: NaiveDateTime,
+}
+
+pub async fn list_compensations(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<Vec<PressureCompensation>>, Error> {
+    let compensations = sqlx::query_as!(
+        PressureCompensation,
+        r#"
+        SELECT
+            id AS "id!",
+            task_id,
+            vented,
+            baro_series_id,
+            reference_depth_to_water,
+            reference_point,
+            reference_time,
+            compensated,
+            skipped,
+            created_by,
+            created_at
+        FROM
+            pressure_compensation
+        WHERE
+            task_id = $1
+        ORDER BY
+            id DESC
+        "#,
+        task_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(Json(compensations))
+}
+
+#[derive(Deserialize)]
+pub struct Compensate {
+    /// Vented loggers already report gauge pressure
+    #[serde(default)]
+    vented: bool,
+    /// Barometric logger series, instead of the `pres_baro` of each record
+    #[serde(default)]
+    baro_series_id: Option<i64>,
+    /// Manually measured depth to water, defaults to the first water level
+    /// reading of the task
+    #[serde(default)]
+    reference_depth_to_water: Option<f64>,
+    /// Reference point of `reference_depth_to_water`, `TOC` or `GS`, defaults
+    /// to `TOC`
+    #[serde(default)]
+    reference_point: Option<String>,
+    /// Time of the reference measurement, defaults to the start of the log
+    #[serde(default, with = "super::serde::iso8601_option")]
+    reference_time: Option<NaiveDateTime>,
+}
+
+/// Convert the logged pressure to the height of the water column above the
+/// sensor, and with a reference water level to depth to water and elevation.
+pub async fn compensate(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(task_id): Path<i64>,
+    Json(request): Json<Compensate>,
+) -> Result<Json<PressureCompensation>, Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    if let Some(reference_point) = request.reference_point.as_deref() {
+        if reference_point != TOP_OF_CASING && reference_point != GROUND_SURFACE {
+            return Err(anyhow::anyhow!(
+                "Invalid reference point: {:?}, must be {} or {}",
+                reference_point,
+                TOP_OF_CASING,
+                GROUND_SURFACE
+            )
+            .into());
+        }
+    }
+
+    let (reference_depth_to_water, reference_point, reference_time) =
+        match request.reference_depth_to_water {
+            Some(depth_to_water) => (
+                Some(depth_to_water),
+                Some(
+                    request
+                        .reference_point
+                        .unwrap_or_else(|| TOP_OF_CASING.to_string()),
+                ),
+                request.reference_time,
+            ),
+            None => fetch_water_level_readings(&ctx.db, task_id)
+                .await?
+                .into_iter()
+                .next()
+                .map(|reading| {
+                    (
+                        Some(reading.depth_to_water),
+                        Some(reading.reference_point),
+                        Some(reading.measured_at),
+                    )
+                })
+                .unwrap_or((None, None, None)),
+        };
+
+    let well = sqlx::query!(
+        r#"
+        SELECT
+            well.ground_elevation,
+            well.casing_top_elevation
+        FROM
+            task
+        JOIN well
+            ON well.id = task.well_id
+        WHERE
+            task.id = $1
+        "#,
+        task_id
+    )
+    .fetch_optional(&ctx.db)
+    .await?
+    .ok_or(Error::TaskNotFound(task_id))?;
+
+    // Depth to water is below the reference point, so its elevation depends on
+    // where the reference was measured from
+    let reference_elevation = match reference_point.as_deref() {
+        Some(TOP_OF_CASING) => well.casing_top_elevation,
+        Some(GROUND_SURFACE) => well.ground_elevation,
+        Some(_) => None,
+    };
+
+    let records = fetch_sensor_data(&ctx.db, task_id, DeriveMode::Fill).await?;
+    let baro = match request.baro_series_id {
+        Some(series_id) => Some(fetch_baro_readings(&ctx.db, series_id).await?),
+        None => None,
+    };
+
+    let water_columns: Vec<Option<f64>> = records
+        .iter()
+        .map(|record| {
+            let pressure = record.pres?;
+            let gauge = if request.vented {
+                pressure
+            } else {
+                let baro_pressure = match &baro {
+                    Some(readings) => interpolate(readings, record.datetime)?,
+                    None => record.pres_baro?,
+                };
+                pressure - baro_pressure
+            };
+            let rho = record
+                .wtr_d
+                .unwrap_or_else(|| density(0.0, record.temp_internal));
+            Some(water_column(gauge, rho))
+        })
+        .collect();
+
+    // The water column at the reference time, from the nearest compensated record
+    let reference_column = reference_depth_to_water.and_then(|_| {
+        records
+            .iter()
+            .zip(water_columns.iter())
+            .filter_map(|(record, column)| Some((record.datetime, (*column)?)))
+            .min_by_key(|(datetime, _)| match reference_time {
+                Some(reference_time) => (*datetime - reference_time).num_seconds().abs(),
+                None => 0,
+            })
+            .map(|(_, column)| column)
+    });
+
+    let mut tx = ctx.db.begin().await?;
+    let mut compensated = 0;
+
+    for (record, water_column) in records.iter().zip(water_columns) {
+        let depth_to_water = match (reference_depth_to_water, reference_column, water_column) {
+            (Some(depth_to_water), Some(reference_column), Some(column)) => {
+                Some(depth_to_water + reference_column - column)
+            }
+            _ => None,
+        };
+        let water_elevation = reference_elevation
+            .zip(depth_to_water)
+            .map(|(elevation, depth_to_water)| elevation - depth_to_water);
+
+        if water_column.is_some() {
+            compensated += 1;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE
+                sensor_data
+            SET
+                water_column = $1,
+                depth_to_water = $2,
+                water_elevation = $3
+            WHERE
+                task_id = $4 AND datetime = $5
+            "#,
+            water_column,
+            depth_to_water,
+            water_elevation,
+            task_id,
+            record.datetime
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let skipped = records.len() as i64 - compensated;
+    let now = Utc::now().naive_utc();
+
+    let compensation = sqlx::query_as!(
+        PressureCompensation,
+        r#"
+        INSERT INTO pressure_compensation (
+            task_id,
+            vented,
+            baro_series_id,
+            reference_depth_to_water,
+            reference_point,
+            reference_time,
+            compensated,
+            skipped,
+            created_by,
+            created_at
+        )
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING
+            id AS "id!",
+            task_id,
+            vented,
+            baro_series_id,
+            reference_depth_to_water,
+            reference_point,
+            reference_time,
+            compensated,
+            skipped,
+            created_by,
+            created_at
+        "#,
+        task_id,
+        request.vented,
+        request.baro_series_id,
+        reference_depth_to_water,
+        reference_point,
+        reference_time,
+        compensated,
+        skipped,
+        session.people_id,
+        now
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(compensation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sensor_data::time;
+
+    #[test]
+    fn interpolated_baro_pressure() {
+        let readings = [(time(0), 14.0), (time(15), 14.3), (time(30), 14.6)];
+        // Minute, then the interpolated pressure
+        let cases = [
+            (0, Some(14.0)),
+            (5, Some(14.1)),
+            (15, Some(14.3)),
+            (21, Some(14.42)),
+            (30, Some(14.6)),
+            // Outside of the series
+            (-1, None),
+            (31, None),
+        ];
+        for (minute, expected) in cases {
+            let pressure = interpolate(&readings, time(minute));
+            match (pressure, expected) {
+                (Some(pressure), Some(expected)) => {
+                    assert!((pressure - expected).abs() < 1e-9, "minute {}", minute)
+                }
+                _ => assert_eq!(pressure, expected, "minute {}", minute),
+            }
+        }
+    }
+
+    #[test]
+    fn water_column_of_pressure() {
+        // 1 psi of fresh water is 0.703 m, of sea water 0.686 m
+        assert!((water_column(1.0, 1.0) - 0.703_070).abs() < 1e-6);
+        assert!((water_column(1.0, 1.025) - 0.685_922).abs() < 1e-6);
+        assert!((water_column(10.0, 1.0) - 7.030_696).abs() < 1e-6);
+    }
+}
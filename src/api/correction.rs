@@ -0,0 +1,265 @@
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::auth::{Role, Session};
+use super::finalize::ensure_unlocked;
+use super::sensor_data::{SensorRecord, PARAMETERS};
+use super::{ApiContext, Error};
+
+/// A linear correction of one sensor parameter, going from `start_offset` at
+/// `start_time` to `end_offset` at `end_time`. Points outside the interval
+/// are left as they are.
+#[derive(Serialize)]
+pub struct SensorCorrection {
+    id: i64,
+    task_id: i64,
+    parameter: String,
+    start_offset: f64,
+    end_offset: f64,
+    #[serde(with = "super::serde::iso8601")]
+    start_time: NaiveDateTime,
+    #[serde(with = "super::serde::iso8601")]
+    end_time: NaiveDateTime,
+    comment: Option<String>,
+    created_by: i64,
+    #[serde(with = "super::serde::iso8601")]
+    created_at: NaiveDateTime,
+    reverted_by: Option<i64>,
+    #[serde(with = "super::serde::iso8601_option")]
+    reverted_at: Option<NaiveDateTime>,
+}
+
+impl SensorCorrection {
+    /// Offset at a point in time, None outside the interval.
+    fn offset_at(&self, datetime: NaiveDateTime) -> Option<f64> {
+        if datetime < self.start_time || datetime > self.end_time {
+            return None;
+        }
+
+        let span = (self.end_time - self.start_time).num_milliseconds();
+        if span <= 0 {
+            return Some(self.end_offset);
+        }
+
+        let elapsed = (datetime - self.start_time).num_milliseconds();
+        let fraction = elapsed as f64 / span as f64;
+        Some(self.start_offset + (self.end_offset - self.start_offset) * fraction)
+    }
+}
+
+async fn fetch_corrections(db: &SqlitePool, task_id: i64) -> Result<Vec<SensorCorrection>, Error> {
+    let corrections = sqlx::query_as!(
+        SensorCorrection,
+        r#"
+        SELECT
+            id AS "id!",
+            task_id,
+            parameter,
+            start_offset,
+            end_offset,
+            start_time,
+            end_time,
+            comment,
+            created_by,
+            created_at,
+            reverted_by,
+            reverted_at
+        FROM
+            sensor_correction
+        WHERE
+            task_id = $1
+        ORDER BY
+            id
+        "#,
+        task_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(corrections)
+}
+
+/// Apply the corrections of a task which haven't been reverted, in the order
+/// they were made. The logged value of each corrected parameter is kept in `raw`.
+pub(crate) async fn apply_corrections(
+    db: &SqlitePool,
+    task_id: i64,
+    records: &mut [SensorRecord],
+) -> Result<(), Error> {
+    let corrections = fetch_corrections(db, task_id).await?;
+
+    for correction in corrections.iter().filter(|c| c.reverted_at.is_none()) {
+        for record in records.iter_mut() {
+            let Some(offset) = correction.offset_at(record.datetime) else {
+                continue;
+            };
+            let Some(value) = record.parameter_mut(&correction.parameter) else {
+                continue;
+            };
+            let raw = *value;
+            *value += offset;
+            record
+                .raw
+                .entry(correction.parameter.clone())
+                .or_insert(raw);
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn list_corrections(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<Vec<SensorCorrection>>, Error> {
+    Ok(Json(fetch_corrections(&ctx.db, task_id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct NewSensorCorrection {
+    parameter: String,
+    /// Defaults to 0, a pure drift correction
+    #[serde(default)]
+    start_offset: f64,
+    end_offset: f64,
+    /// Defaults to the first logged record of the task
+    #[serde(default, with = "super::serde::iso8601_option")]
+    start_time: Option<NaiveDateTime>,
+    /// Defaults to the last logged record of the task
+    #[serde(default, with = "super::serde::iso8601_option")]
+    end_time: Option<NaiveDateTime>,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+pub async fn insert_correction(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(task_id): Path<i64>,
+    Json(correction): Json<NewSensorCorrection>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    if !PARAMETERS.contains(&correction.parameter.as_str()) {
+        return Err(anyhow::anyhow!("Invalid parameter: {:?}", correction.parameter).into());
+    }
+
+    let range = sqlx::query!(
+        r#"
+        SELECT
+            MIN(datetime) AS "first: NaiveDateTime",
+            MAX(datetime) AS "last: NaiveDateTime"
+        FROM
+            sensor_data
+        WHERE
+            task_id = $1
+        "#,
+        task_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let start_time = correction
+        .start_time
+        .or(range.first)
+        .ok_or_else(|| anyhow::anyhow!("No sensor data for task {}", task_id))?;
+    let end_time = correction
+        .end_time
+        .or(range.last)
+        .ok_or_else(|| anyhow::anyhow!("No sensor data for task {}", task_id))?;
+
+    if end_time < start_time {
+        return Err(anyhow::anyhow!("Correction ends before it starts").into());
+    }
+
+    let now = Utc::now().naive_utc();
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO sensor_correction (
+            task_id,
+            parameter,
+            start_offset,
+            end_offset,
+            start_time,
+            end_time,
+            comment,
+            created_by,
+            created_at
+        )
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id
+        "#,
+        task_id,
+        correction.parameter,
+        correction.start_offset,
+        correction.end_offset,
+        start_time,
+        end_time,
+        correction.comment,
+        session.people_id,
+        now
+    )
+    .fetch_one(&ctx.db)
+    .await?
+    .id
+    .ok_or_else(|| anyhow::anyhow!("Failed to insert correction"))?;
+
+    tracing::info!(
+        "Correction {} of {} for task {} by people {}",
+        id,
+        correction.parameter,
+        task_id,
+        session.people_id
+    );
+
+    Ok(Json(id))
+}
+
+/// Stop applying a correction. The correction is kept for the record.
+pub async fn revert_correction(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path((task_id, correction_id)): Path<(i64, i64)>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    let now = Utc::now().naive_utc();
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE
+            sensor_correction
+        SET
+            reverted_by = $1,
+            reverted_at = $2
+        WHERE
+            id = $3 AND task_id = $4 AND reverted_at IS NULL
+        "#,
+        session.people_id,
+        now,
+        correction_id,
+        task_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(anyhow::anyhow!(
+            "No active correction {} for task {}",
+            correction_id,
+            task_id
+        )
+        .into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,973 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use axum::extract::{Json, Multipart, Path, Query};
+use axum::Extension;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::auth::{Role, Session};
+use super::sensor_data::read_log;
+use super::time_zone::to_utc;
+use super::{ApiContext, Error};
+
+/// Log column names of the Aqua TROLL csv and txt exports, mapped to the
+/// `sensor_data` column names. Same mapping as the web frontend uses.
+const COLUMN_NAMES: &[(&str, &str)] = &[
+    ("Date/Time", "datetime"),
+    ("Temp", "temp_internal"),
+    ("Pres", "pres"),
+    ("Depth", "depth"),
+    ("CNDCT", "cndct"),
+    ("SPCNDCT", "spcndct"),
+    ("SA", "sa"),
+    ("TDS", "tds"),
+    ("pH", "ph"),
+    ("ORP", "orp"),
+    ("DO(con)", "do_con"),
+    ("DO(%sat)", "do_sat"),
+    ("Turbidity", "turbidity"),
+    ("PPO2", "ppo2"),
+    ("Batt Perc(%)", "batt"),
+    ("R", "resis"),
+    ("Date and Time", "datetime"),
+    ("Temperature", "temp_internal"),
+    ("External Voltage", "v"),
+    ("Battery Percentage (%)", "batt"),
+    ("Barometric Pressure", "pres_baro"),
+    ("Pressure", "pres"),
+    ("Dissolved Oxygen (concentration)", "do_con"),
+    ("Partial Pressure Oxygen", "ppo2"),
+    ("pH(mV)", "ph_mv"),
+    ("Dissolved Oxygen (%saturation)", "do_sat"),
+    ("Oxidation Reduction Potential (ORP)", "orp"),
+    ("Actual Conductivity", "cndct"),
+    ("Specific Conductivity", "spcndct"),
+    ("Salinity", "sa"),
+    ("Resistivity", "resis"),
+    ("Water Density", "wtr_d"),
+    ("Total Dissolved Solids", "tds"),
+];
+
+/// Factors of the units a parameter is logged in to the unit `sensor_data`
+/// stores it in. Units are per parameter, a ppt salinity is about PSU while
+/// ppt dissolved solids are a thousand ppm.
+const UNIT_FACTORS: &[(&str, &str, f64)] = &[
+    ("temp_internal", "(C)", 1.0),
+    ("pres", "(PSI)", 1.0),
+    ("pres", "(mmHg)", 1.0 / 51.7149),
+    ("pres_baro", "(PSI)", 1.0),
+    ("pres_baro", "(mmHg)", 1.0 / 51.7149),
+    ("depth", "(m)", 1.0),
+    ("depth", "(ft)", 0.3048),
+    ("cndct", "(µS/cm)", 1.0),
+    ("cndct", "(mS/cm)", 1000.0),
+    ("spcndct", "(µS/cm)", 1.0),
+    ("spcndct", "(mS/cm)", 1000.0),
+    ("sa", "(PSU)", 1.0),
+    ("sa", "(ppt)", 1.0),
+    ("tds", "(ppm)", 1.0),
+    ("tds", "(ppt)", 1000.0),
+    ("ph", "(pH)", 1.0),
+    ("ph_mv", "(mV)", 1.0),
+    ("orp", "(mV)", 1.0),
+    ("do_con", "(mg/L)", 1.0),
+    ("do_sat", "(%Sat)", 1.0),
+    ("turbidity", "(NTU)", 1.0),
+    ("ppo2", "(Torr)", 1.0),
+    ("resis", "(ohm-cm)", 1.0),
+    ("v", "(V)", 1.0),
+    ("wtr_d", "(g/cm3)", 1.0),
+];
+
+/// Datetime formats of the log exports without an offset, in the time zone of the logger.
+const DATETIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%m/%d/%Y %H:%M:%S",
+    "%m/%d/%Y %I:%M:%S %p",
+];
+
+/// A gap is reported when records are further apart than this many logging intervals.
+const GAP_FACTOR: f64 = 1.5;
+
+/// Map a log column like `Pressure (mmHg)` to its parameter and unit factor.
+fn map_column(name: &str) -> Option<(&'static str, f64)> {
+    COLUMN_NAMES.iter().find_map(|(column, parameter)| {
+        let unit = if name == *column {
+            ""
+        } else {
+            name.strip_prefix(column)?.trim()
+        };
+        if unit.is_empty() {
+            Some((*parameter, 1.0))
+        } else if unit.starts_with('(') && unit.ends_with(')') {
+            let factor = UNIT_FACTORS
+                .iter()
+                .find(|(p, u, _)| p == parameter && *u == unit)
+                .map(|(_, _, factor)| *factor)?;
+            Some((*parameter, factor))
+        } else {
+            None
+        }
+    })
+}
+
+fn parse_datetime(value: &str, tz: Tz) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    DateTime::parse_from_rfc3339(value)
+        .map(|datetime| datetime.naive_utc())
+        .ok()
+        .or_else(|| {
+            DATETIME_FORMATS
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+                .map(|datetime| to_utc(tz, datetime))
+        })
+}
+
+fn json_number(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+struct ParsedLog {
+    readings: Vec<(NaiveDateTime, &'static str, f64)>,
+    records: i64,
+    ignored_columns: BTreeSet<String>,
+}
+
+/// Long format readings of a parsed log. The log is read through its serialized
+/// form, which is the same as `/sensor_log/upload` returns.
+fn parse_log(log: serde_json::Value, tz: Tz) -> Result<ParsedLog, Error> {
+    let rows = log
+        .get("log_data")
+        .and_then(|rows| rows.as_array())
+        .ok_or_else(|| anyhow::anyhow!("Log has no data"))?;
+
+    let mut parsed = ParsedLog {
+        readings: Vec::new(),
+        records: 0,
+        ignored_columns: BTreeSet::new(),
+    };
+
+    for row in rows {
+        let Some(row) = row.as_object() else {
+            continue;
+        };
+
+        let mut datetime = None;
+        let mut values = Vec::new();
+        for (name, value) in row {
+            match map_column(name) {
+                Some(("datetime", _)) => {
+                    datetime = value.as_str().and_then(|value| parse_datetime(value, tz))
+                }
+                Some((parameter, factor)) => {
+                    if let Some(value) = json_number(value) {
+                        values.push((parameter, value * factor));
+                    }
+                }
+                None => {
+                    parsed.ignored_columns.insert(name.clone());
+                }
+            }
+        }
+
+        let Some(datetime) = datetime else {
+            continue;
+        };
+        parsed.records += 1;
+        parsed.readings.extend(
+            values
+                .into_iter()
+                .map(|(parameter, value)| (datetime, parameter, value)),
+        );
+    }
+
+    Ok(parsed)
+}
+
+#[derive(Serialize)]
+pub struct Deployment {
+    id: i64,
+    well_id: i64,
+    instrument_serial: Option<String>,
+    equipment_id: Option<i64>,
+    #[serde(with = "super::serde::iso8601")]
+    installed_at: NaiveDateTime,
+    #[serde(with = "super::serde::iso8601_option")]
+    removed_at: Option<NaiveDateTime>,
+    /// Metres below top of casing
+    hang_depth: Option<f64>,
+    /// Seconds between records
+    logging_interval: Option<i64>,
+    /// Time zone of the logger clock, the project time zone when not set
+    time_zone: Option<String>,
+    comment: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeploymentFilter {
+    #[serde(default)]
+    well_id: Option<i64>,
+}
+
+pub async fn list_deployments(
+    ctx: Extension<ApiContext>,
+    Query(filter): Query<DeploymentFilter>,
+) -> Result<Json<Vec<Deployment>>, Error> {
+    let deployments = sqlx::query_as!(
+        Deployment,
+        r#"
+        SELECT
+            id AS "id!",
+            well_id,
+            instrument_serial,
+            equipment_id,
+            installed_at,
+            removed_at,
+            hang_depth,
+            logging_interval,
+            time_zone,
+            comment
+        FROM
+            deployment
+        WHERE
+            $1 IS NULL OR well_id = $1
+        ORDER BY
+            installed_at DESC
+        "#,
+        filter.well_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(Json(deployments))
+}
+
+#[derive(Deserialize)]
+pub struct NewDeployment {
+    well_id: i64,
+    #[serde(default)]
+    instrument_serial: Option<String>,
+    #[serde(default)]
+    equipment_id: Option<i64>,
+    #[serde(with = "super::serde::iso8601")]
+    installed_at: NaiveDateTime,
+    #[serde(default)]
+    hang_depth: Option<f64>,
+    #[serde(default)]
+    logging_interval: Option<i64>,
+    #[serde(default)]
+    time_zone: Option<String>,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+fn validate_time_zone(time_zone: Option<&str>) -> Result<(), Error> {
+    if let Some(time_zone) = time_zone {
+        time_zone
+            .parse::<Tz>()
+            .map_err(|_| anyhow::anyhow!("Invalid time zone: {:?}", time_zone))?;
+    }
+    Ok(())
+}
+
+pub async fn insert_deployment(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Json(deployment): Json<NewDeployment>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::FieldTech)?;
+
+    validate_time_zone(deployment.time_zone.as_deref())?;
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO deployment (
+            well_id,
+            instrument_serial,
+            equipment_id,
+            installed_at,
+            hang_depth,
+            logging_interval,
+            time_zone,
+            comment
+        )
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id
+        "#,
+        deployment.well_id,
+        deployment.instrument_serial,
+        deployment.equipment_id,
+        deployment.installed_at,
+        deployment.hang_depth,
+        deployment.logging_interval,
+        deployment.time_zone,
+        deployment.comment
+    )
+    .fetch_one(&ctx.db)
+    .await?
+    .id
+    .ok_or_else(|| anyhow::anyhow!("Failed to insert deployment"))?;
+
+    Ok(Json(id))
+}
+
+pub async fn update_deployment(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(deployment_id): Path<i64>,
+    Json(update): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    for (key, value) in update {
+        match key.as_str() {
+            "instrument_serial" => {
+                let value = value.as_str();
+                sqlx::query!(
+                    "UPDATE deployment SET instrument_serial = $1 WHERE id = $2",
+                    value,
+                    deployment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "equipment_id" => {
+                let value: Option<i64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE deployment SET equipment_id = $1 WHERE id = $2",
+                    value,
+                    deployment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "installed_at" => {
+                let value = super::serde::iso8601::deserialize(value)?;
+                sqlx::query!(
+                    "UPDATE deployment SET installed_at = $1 WHERE id = $2",
+                    value,
+                    deployment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "removed_at" => {
+                let value = super::serde::iso8601_option::deserialize(value)?;
+                sqlx::query!(
+                    "UPDATE deployment SET removed_at = $1 WHERE id = $2",
+                    value,
+                    deployment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "hang_depth" => {
+                let value: Option<f64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE deployment SET hang_depth = $1 WHERE id = $2",
+                    value,
+                    deployment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "logging_interval" => {
+                let value: Option<i64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE deployment SET logging_interval = $1 WHERE id = $2",
+                    value,
+                    deployment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "time_zone" => {
+                let value = value.as_str();
+                validate_time_zone(value)?;
+                sqlx::query!(
+                    "UPDATE deployment SET time_zone = $1 WHERE id = $2",
+                    value,
+                    deployment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "comment" => {
+                let value = value.as_str();
+                sqlx::query!(
+                    "UPDATE deployment SET comment = $1 WHERE id = $2",
+                    value,
+                    deployment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn delete_deployment(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(deployment_id): Path<i64>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    sqlx::query!("DELETE FROM deployment WHERE id = $1", deployment_id)
+        .execute(&ctx.db)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct Gap {
+    #[serde(with = "super::serde::iso8601")]
+    pub(crate) from: NaiveDateTime,
+    #[serde(with = "super::serde::iso8601")]
+    pub(crate) to: NaiveDateTime,
+    /// Seconds without records
+    seconds: i64,
+}
+
+/// Gaps in the series of a deployment.
+async fn find_gaps(db: &SqlitePool, deployment_id: i64) -> Result<Vec<Gap>, Error> {
+    let logging_interval = sqlx::query_scalar!(
+        "SELECT logging_interval FROM deployment WHERE id = $1",
+        deployment_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    let datetimes = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT
+            datetime AS "datetime!: NaiveDateTime"
+        FROM
+            deployment_reading
+        WHERE
+            deployment_id = $1
+        ORDER BY
+            datetime
+        "#,
+        deployment_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(detect_gaps(&datetimes, logging_interval))
+}
+
+/// Gaps in a sorted series of distinct timestamps, where records are further
+/// apart than expected. Without a logging interval the median interval is used.
+pub(crate) fn detect_gaps(datetimes: &[NaiveDateTime], logging_interval: Option<i64>) -> Vec<Gap> {
+    let intervals: Vec<i64> = datetimes
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]).num_seconds())
+        .collect();
+
+    let expected = match logging_interval {
+        Some(interval) => interval,
+        None => {
+            let mut sorted = intervals.clone();
+            sorted.sort_unstable();
+            match sorted.get(sorted.len() / 2) {
+                Some(median) => *median,
+                None => return Vec::new(),
+            }
+        }
+    };
+
+    datetimes
+        .windows(2)
+        .zip(intervals)
+        .filter(|(_, seconds)| *seconds as f64 > expected as f64 * GAP_FACTOR)
+        .map(|(pair, seconds)| Gap {
+            from: pair[0],
+            to: pair[1],
+            seconds,
+        })
+        .collect()
+}
+
+pub async fn get_gaps(
+    ctx: Extension<ApiContext>,
+    Path(deployment_id): Path<i64>,
+) -> Result<Json<Vec<Gap>>, Error> {
+    Ok(Json(find_gaps(&ctx.db, deployment_id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct ReadingQuery {
+    #[serde(default)]
+    parameter: Option<String>,
+    #[serde(default, with = "super::serde::iso8601_option")]
+    from: Option<NaiveDateTime>,
+    #[serde(default, with = "super::serde::iso8601_option")]
+    to: Option<NaiveDateTime>,
+    /// Downsample to at most this many points per parameter
+    #[serde(default)]
+    points: Option<i64>,
+}
+
+/// A reading, or the aggregate of the readings in an interval of a downsampled
+/// overview starting at `datetime`.
+#[derive(Serialize)]
+pub struct ReadingPoint {
+    #[serde(with = "super::serde::iso8601")]
+    datetime: NaiveDateTime,
+    value: f64,
+    min: f64,
+    max: f64,
+    count: i64,
+}
+
+/// Readings of a deployment in a time range, per parameter. With `points` the
+/// range is split into equal intervals which are averaged, for overviews of
+/// series which are too long to plot.
+pub async fn get_readings(
+    ctx: Extension<ApiContext>,
+    Path(deployment_id): Path<i64>,
+    Query(query): Query<ReadingQuery>,
+) -> Result<Json<BTreeMap<String, Vec<ReadingPoint>>>, Error> {
+    let range = sqlx::query!(
+        r#"
+        SELECT
+            MIN(datetime) AS "first: NaiveDateTime",
+            MAX(datetime) AS "last: NaiveDateTime"
+        FROM
+            deployment_reading
+        WHERE
+            deployment_id = $1
+            AND ($2 IS NULL OR parameter = $2)
+        "#,
+        deployment_id,
+        query.parameter
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let mut readings: BTreeMap<String, Vec<ReadingPoint>> = BTreeMap::new();
+
+    let (Some(from), Some(to)) = (query.from.or(range.first), query.to.or(range.last)) else {
+        return Ok(Json(readings));
+    };
+
+    match query.points.filter(|points| *points > 0) {
+        None => {
+            let rows = sqlx::query!(
+                r#"
+                SELECT
+                    parameter,
+                    datetime,
+                    value
+                FROM
+                    deployment_reading
+                WHERE
+                    deployment_id = $1
+                    AND ($2 IS NULL OR parameter = $2)
+                    AND datetime >= $3 AND datetime <= $4
+                ORDER BY
+                    parameter, datetime
+                "#,
+                deployment_id,
+                query.parameter,
+                from,
+                to
+            )
+            .fetch_all(&ctx.db)
+            .await?;
+
+            for row in rows {
+                readings
+                    .entry(row.parameter)
+                    .or_default()
+                    .push(ReadingPoint {
+                        datetime: row.datetime,
+                        value: row.value,
+                        min: row.value,
+                        max: row.value,
+                        count: 1,
+                    });
+            }
+        }
+        Some(points) => {
+            let interval =
+                ((to - from).num_milliseconds() as f64 / 1000.0 / points as f64).max(1.0);
+
+            let rows = sqlx::query!(
+                r#"
+                SELECT
+                    parameter,
+                    MIN(
+                        CAST((julianday(datetime) - julianday($3)) * 86400 / $5 AS INTEGER),
+                        $6 - 1
+                    ) AS "bucket!: i64",
+                    AVG(value) AS "value!: f64",
+                    MIN(value) AS "min!: f64",
+                    MAX(value) AS "max!: f64",
+                    COUNT(*) AS "count!: i64"
+                FROM
+                    deployment_reading
+                WHERE
+                    deployment_id = $1
+                    AND ($2 IS NULL OR parameter = $2)
+                    AND datetime >= $3 AND datetime <= $4
+                GROUP BY
+                    parameter, 2
+                ORDER BY
+                    parameter, 2
+                "#,
+                deployment_id,
+                query.parameter,
+                from,
+                to,
+                interval,
+                points
+            )
+            .fetch_all(&ctx.db)
+            .await?;
+
+            for row in rows {
+                let offset = (row.bucket as f64 * interval * 1000.0) as i64;
+                readings
+                    .entry(row.parameter)
+                    .or_default()
+                    .push(ReadingPoint {
+                        datetime: from + chrono::Duration::milliseconds(offset),
+                        value: row.value,
+                        min: row.min,
+                        max: row.max,
+                        count: row.count,
+                    });
+            }
+        }
+    }
+
+    Ok(Json(readings))
+}
+
+#[derive(Serialize)]
+pub struct Download {
+    id: i64,
+    deployment_id: i64,
+    file_name: String,
+    #[serde(with = "super::serde::iso8601_option")]
+    first_record: Option<NaiveDateTime>,
+    #[serde(with = "super::serde::iso8601_option")]
+    last_record: Option<NaiveDateTime>,
+    /// Records in the file
+    records: i64,
+    /// Readings which weren't in the series yet
+    added: i64,
+    downloaded_by: i64,
+    #[serde(with = "super::serde::iso8601")]
+    downloaded_at: NaiveDateTime,
+}
+
+pub async fn list_downloads(
+    ctx: Extension<ApiContext>,
+    Path(deployment_id): Path<i64>,
+) -> Result<Json<Vec<Download>>, Error> {
+    let downloads = sqlx::query_as!(
+        Download,
+        r#"
+        SELECT
+            id AS "id!",
+            deployment_id,
+            file_name,
+            first_record,
+            last_record,
+            records,
+            added,
+            downloaded_by,
+            downloaded_at
+        FROM
+            deployment_download
+        WHERE
+            deployment_id = $1
+        ORDER BY
+            id
+        "#,
+        deployment_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(Json(downloads))
+}
+
+#[derive(Serialize)]
+pub struct DownloadReport {
+    download: Download,
+    ignored_columns: BTreeSet<String>,
+    /// Gaps of the whole series after appending the download
+    gaps: Vec<Gap>,
+}
+
+/// Append a log file downloaded from the logger to the series of a deployment.
+/// Readings already in the series, from overlapping downloads, are kept as they are.
+pub async fn append_download(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(deployment_id): Path<i64>,
+    mut multipart: Multipart,
+) -> Result<Json<DownloadReport>, Error> {
+    session.require(Role::FieldTech)?;
+
+    let field = multipart
+        .next_field()
+        .await
+        .map_err(|e| anyhow::anyhow!("Invalid multipart data: {:?}", e))?
+        .ok_or_else(|| anyhow::anyhow!("No log file"))?;
+    let file_name = field.file_name().unwrap_or("").to_string();
+    let data = field
+        .bytes()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to read log file: {:?}", e))?;
+
+    let time_zone = sqlx::query_scalar!(
+        "SELECT time_zone FROM deployment WHERE id = $1",
+        deployment_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+    let tz = match time_zone {
+        Some(time_zone) => time_zone
+            .parse::<Tz>()
+            .map_err(|_| anyhow::anyhow!("Invalid time zone: {:?}", time_zone))?,
+        None => ctx.settings.time_zone,
+    };
+
+    let parsed = parse_log(serde_json::to_value(read_log(&file_name, &data)?)?, tz)?;
+
+    let first_record = parsed
+        .readings
+        .iter()
+        .map(|(datetime, _, _)| *datetime)
+        .min();
+    let last_record = parsed
+        .readings
+        .iter()
+        .map(|(datetime, _, _)| *datetime)
+        .max();
+
+    let mut tx = ctx.db.begin().await?;
+    let mut added = 0;
+
+    for (datetime, parameter, value) in &parsed.readings {
+        let result = sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO
+                deployment_reading (deployment_id, parameter, datetime, value)
+            VALUES
+                ($1, $2, $3, $4)
+            "#,
+            deployment_id,
+            parameter,
+            datetime,
+            value
+        )
+        .execute(&mut *tx)
+        .await?;
+        added += result.rows_affected() as i64;
+    }
+
+    let now = Utc::now().naive_utc();
+
+    let download = sqlx::query_as!(
+        Download,
+        r#"
+        INSERT INTO deployment_download (
+            deployment_id,
+            file_name,
+            first_record,
+            last_record,
+            records,
+            added,
+            downloaded_by,
+            downloaded_at
+        )
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING
+            id AS "id!",
+            deployment_id,
+            file_name,
+            first_record,
+            last_record,
+            records,
+            added,
+            downloaded_by,
+            downloaded_at
+        "#,
+        deployment_id,
+        file_name,
+        first_record,
+        last_record,
+        parsed.records,
+        added,
+        session.people_id,
+        now
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(DownloadReport {
+        download,
+        ignored_columns: parsed.ignored_columns,
+        gaps: find_gaps(&ctx.db, deployment_id).await?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sensor_data::time;
+
+    /// Header row of an Aqua TROLL 500 txt export.
+    const HEADER: &[&str] = &[
+        "Date and Time",
+        "Actual Conductivity (µS/cm)",
+        "Specific Conductivity (mS/cm)",
+        "Salinity (ppt)",
+        "Resistivity (ohm-cm)",
+        "Water Density (g/cm3)",
+        "Total Dissolved Solids (ppt)",
+        "pH (pH)",
+        "pH(mV) (mV)",
+        "Oxidation Reduction Potential (ORP) (mV)",
+        "Dissolved Oxygen (concentration) (mg/L)",
+        "Dissolved Oxygen (%saturation) (%Sat)",
+        "Partial Pressure Oxygen (Torr)",
+        "Temperature (C)",
+        "External Voltage (V)",
+        "Battery Percentage (%)",
+        "Barometric Pressure (mmHg)",
+        "Pressure (PSI)",
+        "Depth (ft)",
+        "Marked",
+    ];
+
+    #[test]
+    fn map_header() {
+        let mapped: Vec<Option<(&str, f64)>> = HEADER.iter().map(|name| map_column(name)).collect();
+        assert_eq!(
+            mapped,
+            vec![
+                Some(("datetime", 1.0)),
+                Some(("cndct", 1.0)),
+                Some(("spcndct", 1000.0)),
+                Some(("sa", 1.0)),
+                Some(("resis", 1.0)),
+                Some(("wtr_d", 1.0)),
+                Some(("tds", 1000.0)),
+                Some(("ph", 1.0)),
+                Some(("ph_mv", 1.0)),
+                Some(("orp", 1.0)),
+                Some(("do_con", 1.0)),
+                Some(("do_sat", 1.0)),
+                Some(("ppo2", 1.0)),
+                Some(("temp_internal", 1.0)),
+                Some(("v", 1.0)),
+                Some(("batt", 1.0)),
+                Some(("pres_baro", 1.0 / 51.7149)),
+                Some(("pres", 1.0)),
+                Some(("depth", 0.3048)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn map_csv_columns() {
+        assert_eq!(map_column("Date/Time"), Some(("datetime", 1.0)));
+        assert_eq!(map_column("Pres (PSI)"), Some(("pres", 1.0)));
+        assert_eq!(map_column("SA (PSU)"), Some(("sa", 1.0)));
+        assert_eq!(map_column("Batt Perc(%)"), Some(("batt", 1.0)));
+        // A unit which doesn't belong to the parameter
+        assert_eq!(map_column("pH (mg/L)"), None);
+        assert_eq!(map_column("Temperature (F)"), None);
+    }
+
+    #[test]
+    fn parse_txt_log() {
+        let row = |datetime: &str, salinity: &str| {
+            let mut row: serde_json::Map<String, serde_json::Value> = HEADER
+                .iter()
+                .map(|name| (name.to_string(), serde_json::json!("1.5")))
+                .collect();
+            row.insert("Date and Time".to_string(), serde_json::json!(datetime));
+            row.insert("Salinity (ppt)".to_string(), serde_json::json!(salinity));
+            serde_json::Value::Object(row)
+        };
+        let log = serde_json::json!({
+            "log_data": [
+                row("2026-05-01 10:00:00", "0.25"),
+                row("not a time", "0.5"),
+                row("2026-05-01 10:05:00", "0.3"),
+            ]
+        });
+
+        let parsed = parse_log(log, chrono_tz::Europe::Berlin).unwrap();
+        assert_eq!(parsed.records, 2);
+        assert_eq!(
+            parsed.ignored_columns,
+            BTreeSet::from(["Marked".to_string()])
+        );
+
+        let reading = |datetime: NaiveDateTime, parameter: &str| {
+            parsed
+                .readings
+                .iter()
+                .find(|reading| reading.0 == datetime && reading.1 == parameter)
+                .map(|reading| reading.2)
+        };
+        // Logged in Berlin summer time
+        let (first, second) = (time(0), time(5));
+        assert_eq!(reading(first, "sa"), Some(0.25));
+        assert_eq!(reading(second, "sa"), Some(0.3));
+        assert_eq!(reading(first, "tds"), Some(1500.0));
+        assert_eq!(reading(first, "depth"), Some(1.5 * 0.3048));
+        assert_eq!(parsed.readings.len(), 2 * (HEADER.len() - 2));
+    }
+
+    #[test]
+    fn gaps_by_logging_interval() {
+        let datetimes = [time(0), time(5), time(10), time(30), time(35), time(42)];
+        let gaps: Vec<(NaiveDateTime, NaiveDateTime, i64)> = detect_gaps(&datetimes, Some(300))
+            .into_iter()
+            .map(|gap| (gap.from, gap.to, gap.seconds))
+            .collect();
+        assert_eq!(gaps, vec![(time(10), time(30), 1200)]);
+    }
+
+    #[test]
+    fn gaps_by_median_interval() {
+        let datetimes = [time(0), time(1), time(2), time(3), time(10), time(11)];
+        let gaps: Vec<(NaiveDateTime, NaiveDateTime, i64)> = detect_gaps(&datetimes, None)
+            .into_iter()
+            .map(|gap| (gap.from, gap.to, gap.seconds))
+            .collect();
+        assert_eq!(gaps, vec![(time(3), time(10), 420)]);
+        assert!(detect_gaps(&[time(0)], None).is_empty());
+    }
+}
@@ -0,0 +1,242 @@
+//! Parameters derived from the measured ones with standard equations.
+//!
+//! Units follow the sensor log import: temperature in °C, conductivity in µS/cm,
+//! pressure in PSI, salinity in PSU, TDS in ppm, density in g/cm³ and dissolved
+//! oxygen in mg/L and % saturation. The water temperature is `temp_internal`.
+
+use serde::Deserialize;
+
+use super::sensor_data::SensorRecord;
+
+/// Linear temperature compensation coefficient for specific conductance, 1/°C
+/// (Standard Methods 2510 B, ISO 7888).
+const CONDUCTIVITY_ALPHA: f64 = 0.0191;
+
+/// TDS per specific conductance, the default of the Aqua TROLL sondes.
+const TDS_FACTOR: f64 = 0.65;
+
+/// Conductivity of standard seawater (S = 35, t = 15 °C, p = 0), µS/cm.
+const SEAWATER_CONDUCTIVITY: f64 = 42_914.0;
+
+const PSI_PER_ATM: f64 = 14.695_95;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeriveMode {
+    /// Only the values reported by the logger
+    #[default]
+    Logged,
+    /// Compute derived values the logger didn't report
+    Fill,
+    /// Compute all derived values, replacing the reported ones
+    Recompute,
+}
+
+/// Temperatures in ITS-90 to IPTS-68, which PSS-78 and EOS-80 are defined in.
+fn t68(t: f64) -> f64 {
+    1.00024 * t
+}
+
+/// Specific conductance at 25 °C, µS/cm.
+pub fn specific_conductance(conductivity: f64, t: f64) -> f64 {
+    conductivity / (1.0 + CONDUCTIVITY_ALPHA * (t - 25.0))
+}
+
+/// Practical salinity (PSS-78, UNESCO 1981) at atmospheric pressure. Below a
+/// salinity of 2 the equation is extrapolated, which is accurate enough for
+/// the freshwater range.
+pub fn salinity(conductivity: f64, t: f64) -> f64 {
+    const A: [f64; 6] = [0.0080, -0.1692, 25.3851, 14.0941, -7.0261, 2.7081];
+    const B: [f64; 6] = [0.0005, -0.0056, -0.0066, -0.0375, 0.0636, -0.0144];
+    const C: [f64; 5] = [
+        0.676_609_7,
+        2.005_64e-2,
+        1.104_259e-4,
+        -6.9698e-7,
+        1.0031e-9,
+    ];
+    const K: f64 = 0.0162;
+
+    let t = t68(t);
+    let r = conductivity / SEAWATER_CONDUCTIVITY;
+    let rt_ratio = C.iter().rev().fold(0.0, |acc, c| acc * t + c);
+    let rt = (r / rt_ratio).max(0.0).sqrt();
+
+    let (s, ds) = A
+        .iter()
+        .zip(B.iter())
+        .enumerate()
+        .fold((0.0, 0.0), |(s, ds), (i, (a, b))| {
+            let term = rt.powi(i as i32);
+            (s + a * term, ds + b * term)
+        });
+
+    (s + (t - 15.0) / (1.0 + K * (t - 15.0)) * ds).max(0.0)
+}
+
+/// Total dissolved solids from specific conductance, ppm.
+pub fn total_dissolved_solids(specific_conductance: f64) -> f64 {
+    TDS_FACTOR * specific_conductance
+}
+
+/// Density at atmospheric pressure (EOS-80 one atmosphere equation), g/cm³.
+pub fn density(salinity: f64, t: f64) -> f64 {
+    let t = t68(t);
+    let pure = 999.842_594 + 6.793_952e-2 * t - 9.095_290e-3 * t.powi(2) + 1.001_685e-4 * t.powi(3)
+        - 1.120_083e-6 * t.powi(4)
+        + 6.536_332e-9 * t.powi(5);
+    let a = 0.824_493 - 4.0899e-3 * t + 7.6438e-5 * t.powi(2) - 8.2467e-7 * t.powi(3)
+        + 5.3875e-9 * t.powi(4);
+    let b = -5.724_66e-3 + 1.0227e-4 * t - 1.6546e-6 * t.powi(2);
+    let c = 4.8314e-4;
+
+    (pure + a * salinity + b * salinity.powf(1.5) + c * salinity.powi(2)) / 1000.0
+}
+
+/// Oxygen solubility in mg/L (Benson & Krause 1980, 1984), with the salinity
+/// and barometric pressure corrections of USGS Office of Water Quality Technical
+/// Memorandum 2011.03.
+pub fn oxygen_solubility(t: f64, salinity: f64, pressure_atm: f64) -> f64 {
+    let tk = t + 273.15;
+    let c0 = (-139.344_11 + 1.575_701e5 / tk - 6.642_308e7 / tk.powi(2)
+        + 1.243_800e10 / tk.powi(3)
+        - 8.621_949e11 / tk.powi(4))
+    .exp();
+    let salinity_factor = (-salinity * (0.017_674 - 10.754 / tk + 2140.7 / tk.powi(2))).exp();
+
+    let vapor_pressure = (11.8571 - 3840.70 / tk - 216_961.0 / tk.powi(2)).exp();
+    let theta = 0.000_975 - 1.426e-5 * t + 6.436e-8 * t.powi(2);
+    let pressure_factor = (pressure_atm - vapor_pressure) * (1.0 - theta * pressure_atm)
+        / ((1.0 - vapor_pressure) * (1.0 - theta));
+
+    c0 * salinity_factor * pressure_factor
+}
+
+/// Dissolved oxygen saturation, %.
+pub fn oxygen_saturation(concentration: f64, t: f64, salinity: f64, pressure_atm: f64) -> f64 {
+    100.0 * concentration / oxygen_solubility(t, salinity, pressure_atm)
+}
+
+/// Set a derived value and mark it as computed. A replaced logged value is kept
+/// in `raw`, unless a correction already put the logged value there.
+fn set(record: &mut SensorRecord, parameter: &'static str, value: f64) {
+    if let Some(current) = record.parameter_mut(parameter) {
+        let logged = *current;
+        *current = value;
+        record.raw.entry(parameter.to_string()).or_insert(logged);
+    } else {
+        match parameter {
+            "sa" => record.sa = Some(value),
+            "wtr_d" => record.wtr_d = Some(value),
+            "tds" => record.tds = Some(value),
+            _ => return,
+        }
+    }
+    record.computed.insert(parameter);
+}
+
+pub fn derive(records: &mut [SensorRecord], mode: DeriveMode) {
+    if mode == DeriveMode::Logged {
+        return;
+    }
+    let recompute = mode == DeriveMode::Recompute;
+
+    for record in records.iter_mut() {
+        let t = record.temp_internal;
+
+        if recompute {
+            set(record, "spcndct", specific_conductance(record.cndct, t));
+        }
+        if recompute || record.sa.is_none() {
+            set(record, "sa", salinity(record.cndct, t));
+        }
+        if recompute || record.tds.is_none() {
+            set(record, "tds", total_dissolved_solids(record.spcndct));
+        }
+
+        let s = record.sa.unwrap_or_default();
+        if recompute || record.wtr_d.is_none() {
+            set(record, "wtr_d", density(s, t));
+        }
+        if recompute {
+            let pressure_atm = record
+                .pres_baro
+                .map(|pressure| pressure / PSI_PER_ATM)
+                .unwrap_or(1.0);
+            set(
+                record,
+                "do_sat",
+                oxygen_saturation(record.do_con, t, s, pressure_atm),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ITS-90 temperature of an IPTS-68 one, the scale of the check values.
+    fn t90(t68: f64) -> f64 {
+        t68 / 1.00024
+    }
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn specific_conductance_reference() {
+        for (conductivity, t, expected) in [
+            (1000.0, 25.0, 1000.0),
+            (1000.0, 20.0, 1105.583),
+            (1000.0, 10.0, 1401.542),
+            (500.0, 30.0, 456.413),
+        ] {
+            assert_close(specific_conductance(conductivity, t), expected, 1e-3);
+        }
+    }
+
+    #[test]
+    fn salinity_reference() {
+        // Standard seawater defines S = 35 at 15 °C (IPTS-68)
+        assert_close(salinity(SEAWATER_CONDUCTIVITY, t90(15.0)), 35.0, 1e-4);
+        // TEOS-10 check value of gsw_SP_from_C, at 10 dbar which changes the
+        // salinity by less than 0.005
+        assert_close(salinity(34_548.7, 28.7856), 20.009_870, 5e-3);
+    }
+
+    #[test]
+    fn density_reference() {
+        // EOS-80 check values (UNESCO 1981) at one atmosphere, IPTS-68
+        for (s, t, expected) in [
+            (0.0, 5.0, 0.999_966_75),
+            (35.0, 5.0, 1.027_675_47),
+            (35.0, 25.0, 1.023_343_06),
+        ] {
+            assert_close(density(s, t90(t)), expected, 1e-7);
+        }
+    }
+
+    #[test]
+    fn oxygen_solubility_reference() {
+        // USGS DOTABLES values for freshwater at 760 mm Hg
+        assert_close(oxygen_solubility(0.0, 0.0, 1.0), 14.621, 1e-3);
+        assert_close(oxygen_solubility(20.0, 0.0, 1.0), 9.092, 1e-3);
+        // 700 mm Hg, the pressure correction takes out the water vapour first,
+        // so the result is a little below the plain pressure ratio
+        assert_close(oxygen_solubility(10.0, 0.0, 700.0 / 760.0), 10.387, 1e-3);
+    }
+
+    #[test]
+    fn oxygen_saturation_reference() {
+        assert_close(oxygen_saturation(9.092, 20.0, 0.0, 1.0), 100.0, 1e-2);
+        assert_close(oxygen_saturation(8.0, 20.0, 0.0, 1.0), 87.985, 1e-3);
+    }
+}
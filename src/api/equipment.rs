@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+
+use axum::extract::{Json, Path, Query};
+use axum::Extension;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::auth::{Role, Session};
+use super::finalize::ensure_unlocked;
+use super::task_status::TaskStatus;
+use super::{ApiContext, Error};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum EquipmentType {
+    Sonde,
+    Probe,
+    FlowCell,
+    WaterLevelMeter,
+    Tubing,
+    Pump,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum EquipmentStatus {
+    InService,
+    Repair,
+    Retired,
+}
+
+#[derive(Serialize)]
+pub struct Equipment {
+    id: i64,
+    equipment_type: EquipmentType,
+    name: String,
+    serial: Option<String>,
+    status: EquipmentStatus,
+    service_due: Option<NaiveDate>,
+    calibration_due: Option<NaiveDate>,
+    comment: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EquipmentFilter {
+    #[serde(default)]
+    equipment_type: Option<EquipmentType>,
+    #[serde(default)]
+    status: Option<EquipmentStatus>,
+}
+
+pub async fn list_equipment(
+    ctx: Extension<ApiContext>,
+    Query(filter): Query<EquipmentFilter>,
+) -> Result<Json<Vec<Equipment>>, Error> {
+    let equipment = sqlx::query_as!(
+        Equipment,
+        r#"
+        SELECT
+            id AS "id!",
+            equipment_type AS "equipment_type: EquipmentType",
+            name,
+            serial,
+            status AS "status: EquipmentStatus",
+            service_due,
+            calibration_due,
+            comment
+        FROM
+            equipment
+        WHERE
+            ($1 IS NULL OR equipment_type = $1)
+            AND ($2 IS NULL OR status = $2)
+        ORDER BY
+            equipment_type, name
+        "#,
+        filter.equipment_type,
+        filter.status
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(Json(equipment))
+}
+
+#[derive(Deserialize)]
+pub struct AsOfQuery {
+    /// Defaults to today
+    #[serde(default)]
+    as_of: Option<NaiveDate>,
+}
+
+/// Equipment in service which is past its service or calibration due date.
+pub async fn list_overdue_equipment(
+    ctx: Extension<ApiContext>,
+    Query(query): Query<AsOfQuery>,
+) -> Result<Json<Vec<Equipment>>, Error> {
+    let as_of = query.as_of.unwrap_or_else(|| Utc::now().date_naive());
+
+    let equipment = sqlx::query_as!(
+        Equipment,
+        r#"
+        SELECT
+            id AS "id!",
+            equipment_type AS "equipment_type: EquipmentType",
+            name,
+            serial,
+            status AS "status: EquipmentStatus",
+            service_due,
+            calibration_due,
+            comment
+        FROM
+            equipment
+        WHERE
+            status = 'in_service'
+            AND (service_due < $1 OR calibration_due < $1)
+        ORDER BY
+            MIN(COALESCE(service_due, calibration_due), COALESCE(calibration_due, service_due))
+        "#,
+        as_of
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(Json(equipment))
+}
+
+#[derive(Deserialize)]
+pub struct NewEquipment {
+    equipment_type: EquipmentType,
+    name: String,
+    #[serde(default)]
+    serial: Option<String>,
+    #[serde(default)]
+    service_due: Option<NaiveDate>,
+    #[serde(default)]
+    calibration_due: Option<NaiveDate>,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+pub async fn insert_equipment(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Json(equipment): Json<NewEquipment>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::DataManager)?;
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO
+            equipment (equipment_type, name, serial, service_due, calibration_due, comment)
+        VALUES
+            ($1, $2, $3, $4, $5, $6)
+        RETURNING id
+        "#,
+        equipment.equipment_type,
+        equipment.name,
+        equipment.serial,
+        equipment.service_due,
+        equipment.calibration_due,
+        equipment.comment
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    Ok(Json(id.id))
+}
+
+pub async fn update_equipment(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(equipment_id): Path<i64>,
+    Json(update): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    for (key, value) in update {
+        match key.as_str() {
+            "equipment_type" => {
+                let value: EquipmentType = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE equipment SET equipment_type = $1 WHERE id = $2",
+                    value,
+                    equipment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "name" => {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid value for name: {:?}", value))?;
+                sqlx::query!(
+                    "UPDATE equipment SET name = $1 WHERE id = $2",
+                    value,
+                    equipment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "serial" => {
+                let value = value.as_str();
+                sqlx::query!(
+                    "UPDATE equipment SET serial = $1 WHERE id = $2",
+                    value,
+                    equipment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "status" => {
+                let value: EquipmentStatus = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE equipment SET status = $1 WHERE id = $2",
+                    value,
+                    equipment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "service_due" => {
+                let value: Option<NaiveDate> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE equipment SET service_due = $1 WHERE id = $2",
+                    value,
+                    equipment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "calibration_due" => {
+                let value: Option<NaiveDate> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE equipment SET calibration_due = $1 WHERE id = $2",
+                    value,
+                    equipment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "comment" => {
+                let value = value.as_str();
+                sqlx::query!(
+                    "UPDATE equipment SET comment = $1 WHERE id = $2",
+                    value,
+                    equipment_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Equipment is normally retired rather than deleted, deleting it also removes
+/// its usage history.
+pub async fn delete_equipment(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(equipment_id): Path<i64>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    sqlx::query!("DELETE FROM equipment WHERE id = $1", equipment_id)
+        .execute(&ctx.db)
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct EquipmentUsage {
+    task_id: i64,
+    well_id: i64,
+    well_name: String,
+    status: TaskStatus,
+    #[serde(with = "super::serde::iso8601_option")]
+    sampling_time: Option<NaiveDateTime>,
+}
+
+/// Tasks the equipment was used for, most recent first.
+pub async fn get_equipment_usage(
+    ctx: Extension<ApiContext>,
+    Path(equipment_id): Path<i64>,
+) -> Result<Json<Vec<EquipmentUsage>>, Error> {
+    let usage = sqlx::query_as!(
+        EquipmentUsage,
+        r#"
+        SELECT
+            task.id AS "task_id!",
+            well.id AS "well_id!",
+            well.name AS well_name,
+            task.status AS "status: TaskStatus",
+            MAX(task_info.sampling_time) AS "sampling_time: NaiveDateTime"
+        FROM
+            task_equipment
+        JOIN task
+            ON task.id = task_equipment.task_id
+        JOIN well
+            ON well.id = task.well_id
+        LEFT JOIN task_info
+            ON task_info.task_id = task.id
+        WHERE
+            task_equipment.equipment_id = $1
+        GROUP BY
+            task.id
+        ORDER BY
+            MAX(task_info.sampling_time) DESC, task.id DESC
+        "#,
+        equipment_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(Json(usage))
+}
+
+pub async fn get_task_equipment(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<Vec<Equipment>>, Error> {
+    let equipment = sqlx::query_as!(
+        Equipment,
+        r#"
+        SELECT
+            equipment.id AS "id!",
+            equipment.equipment_type AS "equipment_type: EquipmentType",
+            equipment.name,
+            equipment.serial,
+            equipment.status AS "status: EquipmentStatus",
+            equipment.service_due,
+            equipment.calibration_due,
+            equipment.comment
+        FROM
+            task_equipment
+        JOIN equipment
+            ON equipment.id = task_equipment.equipment_id
+        WHERE
+            task_equipment.task_id = $1
+        ORDER BY
+            equipment.equipment_type, equipment.name
+        "#,
+        task_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(Json(equipment))
+}
+
+#[derive(Deserialize)]
+pub struct EquipmentId {
+    id: i64,
+}
+
+pub async fn add_task_equipment(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(task_id): Path<i64>,
+    Json(equipment_id): Json<EquipmentId>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    let status = sqlx::query_scalar!(
+        r#"SELECT status AS "status: EquipmentStatus" FROM equipment WHERE id = $1"#,
+        equipment_id.id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    if status != EquipmentStatus::InService {
+        return Err(anyhow::anyhow!("Equipment {} is not in service", equipment_id.id).into());
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            task_equipment (task_id, equipment_id)
+        VALUES
+            ($1, $2)
+        "#,
+        task_id,
+        equipment_id.id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn delete_task_equipment(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path((task_id, equipment_id)): Path<(i64, i64)>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM task_equipment
+        WHERE task_id = $1 AND equipment_id = $2
+        "#,
+        task_id,
+        equipment_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(())
+}
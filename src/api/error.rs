@@ -11,6 +11,20 @@ pub enum Error {
     Anyhow(#[from] anyhow::Error),
     #[error("failed to parse log data")]
     AquaTrollLog(#[from] aqua_troll_log_reader::AquaTrollLogError),
+    #[error("authentication required")]
+    Unauthorized,
+    #[error("permission denied")]
+    Forbidden,
+    #[error("task {0} is finalized, it must be reopened before editing")]
+    Locked(i64),
+    #[error("task {0} is not finalized")]
+    NotFinalized(i64),
+    #[error("task {0} not found")]
+    TaskNotFound(i64),
+    #[error("task info {1} of task {0} not found")]
+    TaskInfoNotFound(i64, i64),
+    #[error("campaign {0} not found")]
+    CampaignNotFound(i64),
 }
 
 impl IntoResponse for Error {
@@ -32,6 +46,14 @@ impl IntoResponse for Error {
                 tracing::error!("failed to parse log data: {:?}", e);
                 (StatusCode::BAD_REQUEST, e.to_string()).into_response()
             }
+            Error::Unauthorized => (StatusCode::UNAUTHORIZED, self.to_string()).into_response(),
+            Error::Forbidden => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
+            Error::Locked(_) | Error::NotFinalized(_) => {
+                (StatusCode::CONFLICT, self.to_string()).into_response()
+            }
+            Error::TaskNotFound(_) | Error::TaskInfoNotFound(..) | Error::CampaignNotFound(_) => {
+                (StatusCode::NOT_FOUND, self.to_string()).into_response()
+            }
         }
     }
 }
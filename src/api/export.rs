@@ -0,0 +1,183 @@
+use axum::extract::{Json, Path, Query};
+use axum::Extension;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::attachment::{fetch_attachments, Attachment};
+use super::comment::{CommentFormat, CommentFormatQuery};
+use super::derived::DeriveMode;
+use super::sensor_data::{fetch_sensor_data, SensorRecord};
+use super::task::{fetch_sample_set, SampleSet};
+use super::task_info::{fetch_task_info, TaskInfo};
+use super::task_status::TaskStatus;
+use super::{ApiContext, Error};
+
+#[derive(Serialize)]
+pub struct TaskInfoExport {
+    #[serde(flatten)]
+    pub(crate) info: TaskInfo,
+    pub(crate) minuted_by: Vec<String>,
+    pub(crate) sampled_by: Vec<String>,
+    attachments: Vec<Attachment>,
+}
+
+/// A self-contained record of a task and everything logged for it.
+#[derive(Serialize)]
+pub struct TaskExport {
+    id: i64,
+    done: Option<bool>,
+    pub(crate) serial: Option<String>,
+    pub(crate) well_id: i64,
+    well_name: String,
+    pub(crate) depth: String,
+    campaign_id: Option<i64>,
+    pub(crate) campaign_name: Option<String>,
+    pub(crate) status: TaskStatus,
+    sample_set: Vec<SampleSet>,
+    pub(crate) task_info: Vec<TaskInfoExport>,
+    pub(crate) sensor_data: Vec<SensorRecord>,
+}
+
+pub(crate) async fn fetch_task_export(
+    db: &SqlitePool,
+    task_id: i64,
+    format: CommentFormat,
+) -> Result<TaskExport, Error> {
+    let task = sqlx::query!(
+        r#"
+        SELECT
+            task.id,
+            task.done,
+            task.serial,
+            task.well_id,
+            well.name AS well_name,
+            task.depth,
+            task.campaign_id,
+            campaign.name AS "campaign_name?",
+            task.status AS "status: TaskStatus"
+        FROM
+            task
+        JOIN well
+            ON well.id = task.well_id
+        LEFT JOIN campaign
+            ON campaign.id = task.campaign_id
+        WHERE
+            task.id = $1
+        "#,
+        task_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    let mut task_info = Vec::new();
+    for mut info in fetch_task_info(db, task_id).await? {
+        info.comment = format.render_option(info.comment.take());
+
+        let minuted_by = sqlx::query_scalar!(
+            r#"
+            SELECT
+                people.name
+            FROM
+                task_minuted_by
+            JOIN people
+                ON people.id = task_minuted_by.people_id
+            WHERE
+                task_minuted_by.task_info_id = $1
+            ORDER BY
+                people.name
+            "#,
+            info.id
+        )
+        .fetch_all(db)
+        .await?;
+
+        let sampled_by = sqlx::query_scalar!(
+            r#"
+            SELECT
+                people.name
+            FROM
+                task_sampled_by
+            JOIN people
+                ON people.id = task_sampled_by.people_id
+            WHERE
+                task_sampled_by.task_info_id = $1
+            ORDER BY
+                people.name
+            "#,
+            info.id
+        )
+        .fetch_all(db)
+        .await?;
+
+        let attachments = fetch_attachments(db, info.id).await?;
+
+        task_info.push(TaskInfoExport {
+            info,
+            minuted_by,
+            sampled_by,
+            attachments,
+        });
+    }
+
+    Ok(TaskExport {
+        id: task.id,
+        done: task.done,
+        serial: task.serial,
+        well_id: task.well_id,
+        well_name: task.well_name,
+        depth: task.depth,
+        campaign_id: task.campaign_id,
+        campaign_name: task.campaign_name,
+        status: task.status,
+        sample_set: fetch_sample_set(db, task_id).await?,
+        task_info,
+        sensor_data: fetch_sensor_data(db, task_id, DeriveMode::default()).await?,
+    })
+}
+
+pub async fn export_task(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+    Query(query): Query<CommentFormatQuery>,
+) -> Result<Json<TaskExport>, Error> {
+    Ok(Json(
+        fetch_task_export(&ctx.db, task_id, query.format).await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct ExportFilter {
+    #[serde(default)]
+    campaign_id: Option<i64>,
+    #[serde(default)]
+    format: CommentFormat,
+}
+
+/// Export all tasks, or the tasks of a single campaign.
+pub async fn export_tasks(
+    ctx: Extension<ApiContext>,
+    Query(filter): Query<ExportFilter>,
+) -> Result<Json<Vec<TaskExport>>, Error> {
+    let task_ids = sqlx::query_scalar!(
+        r#"
+        SELECT
+            id
+        FROM
+            task
+        WHERE
+            $1 IS NULL OR campaign_id = $1
+        ORDER BY
+            id
+        "#,
+        filter.campaign_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    let mut tasks = Vec::with_capacity(task_ids.len());
+    for task_id in task_ids {
+        tasks.push(fetch_task_export(&ctx.db, task_id, filter.format).await?);
+    }
+
+    Ok(Json(tasks))
+}
@@ -0,0 +1,420 @@
+//! Final field parameters of a task for lab reports: the last reading of the
+//! last stable window before sampling, or the mean over the last minutes before
+//! it when a parameter wasn't stable in those minutes. Values flagged bad are
+//! left out.
+
+use axum::extract::{Json, Path, Query};
+use axum::Extension;
+use chrono::{Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::derived::DeriveMode;
+use super::qc::QcFlag;
+use super::sensor_data::{fetch_sensor_data, SensorRecord};
+use super::task_info::fetch_task_info;
+use super::{ApiContext, Error};
+
+/// How much the readings of a window may spread around their mean for a
+/// parameter to count as stable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Criterion {
+    Absolute(f64),
+    /// A fraction of the mean
+    Relative(f64),
+}
+
+impl Criterion {
+    /// Whether the minimum and maximum are both within the margin of the mean.
+    fn passes(self, values: &[f64]) -> bool {
+        let Some(min) = values.iter().copied().reduce(f64::min) else {
+            return false;
+        };
+        let max = values.iter().copied().fold(min, f64::max);
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        match self {
+            Criterion::Absolute(margin) => max - mean < margin && mean - min < margin,
+            Criterion::Relative(margin) => {
+                (max - mean) / mean < margin && (mean - min) / mean < margin
+            }
+        }
+    }
+}
+
+/// Stabilization criteria, the same as the sensor data chart of the web UI
+/// (`web/src/sensor-data/test_function.ts`).
+pub(crate) const STABILITY_CRITERIA: &[(&str, Criterion)] = &[
+    ("temp_internal", Criterion::Absolute(0.2)),
+    ("temp_sensor", Criterion::Absolute(0.2)),
+    ("cndct", Criterion::Relative(0.03)),
+    ("spcndct", Criterion::Relative(0.03)),
+    ("ph", Criterion::Absolute(0.1)),
+    ("orp", Criterion::Absolute(50.0)),
+    ("do_con", Criterion::Absolute(0.3)),
+    ("do_sat", Criterion::Relative(0.1)),
+];
+
+/// Width of the rolling window the criteria are tested on.
+pub(crate) const STABILITY_WINDOW_MINUTES: i64 = 5;
+
+/// Minutes before sampling a stable window must end in, and averaged when none
+/// does.
+const WINDOW_MINUTES: i64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldParameterMethod {
+    /// Last reading of the last stable window, ending within the window before
+    /// sampling
+    Stabilized,
+    /// Mean over the window before sampling
+    Mean,
+}
+
+impl FieldParameterMethod {
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            FieldParameterMethod::Stabilized => "Stabilized",
+            FieldParameterMethod::Mean => "Mean",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FieldParameter {
+    pub(crate) parameter: &'static str,
+    pub(crate) value: f64,
+    pub(crate) method: FieldParameterMethod,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+    pub(crate) mean: f64,
+    /// Readings in the window
+    pub(crate) records: usize,
+    #[serde(with = "super::serde::iso8601")]
+    pub(crate) window_start: NaiveDateTime,
+    #[serde(with = "super::serde::iso8601")]
+    pub(crate) window_end: NaiveDateTime,
+}
+
+#[derive(Serialize)]
+pub struct FieldParameters {
+    task_id: i64,
+    /// Sampling time of the task info, or the last reading without one
+    #[serde(with = "super::serde::iso8601_option")]
+    pub(crate) sampling_time: Option<NaiveDateTime>,
+    window_minutes: i64,
+    pub(crate) parameters: Vec<FieldParameter>,
+}
+
+/// Rolling windows over the readings as index ranges with both ends included.
+/// A window starts at each reading and takes the readings up to, but without,
+/// the first one at least `width` later. Windows which aren't closed by such a
+/// reading are left out.
+fn rolling_windows(readings: &[(NaiveDateTime, f64)], width: Duration) -> Vec<(usize, usize)> {
+    let mut windows = Vec::new();
+    let mut start = 0;
+    for end in 0..readings.len() {
+        while readings[end].0 - readings[start].0 >= width {
+            windows.push((start, end - 1));
+            start += 1;
+        }
+    }
+    windows
+}
+
+/// Windows of the readings in which the parameter was stable.
+fn stable_windows(readings: &[(NaiveDateTime, f64)], criterion: Criterion) -> Vec<(usize, usize)> {
+    let values: Vec<f64> = readings.iter().map(|(_, value)| *value).collect();
+    rolling_windows(readings, Duration::minutes(STABILITY_WINDOW_MINUTES))
+        .into_iter()
+        .filter(|(start, end)| criterion.passes(&values[*start..=*end]))
+        .collect()
+}
+
+/// Runs of stable readings, as index ranges into `readings` with both ends
+/// included. Overlapping or adjacent stable windows are merged.
+pub(crate) fn stable_runs(
+    readings: &[(NaiveDateTime, f64)],
+    criterion: Criterion,
+) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in stable_windows(readings, criterion) {
+        match runs.last_mut() {
+            Some(run) if start <= run.1 + 1 => run.1 = run.1.max(end),
+            _ => runs.push((start, end)),
+        }
+    }
+    runs
+}
+
+fn summarize(
+    parameter: &'static str,
+    readings: &[(NaiveDateTime, f64)],
+    method: FieldParameterMethod,
+    window_start: NaiveDateTime,
+    window_end: NaiveDateTime,
+) -> Option<FieldParameter> {
+    let values = readings.iter().map(|(_, value)| *value);
+    let min = values.clone().reduce(f64::min)?;
+    let max = values.clone().reduce(f64::max)?;
+    let mean = values.sum::<f64>() / readings.len() as f64;
+    let value = match method {
+        FieldParameterMethod::Stabilized => readings.last()?.1,
+        FieldParameterMethod::Mean => mean,
+    };
+
+    Some(FieldParameter {
+        parameter,
+        value,
+        method,
+        min,
+        max,
+        mean,
+        records: readings.len(),
+        window_start,
+        window_end,
+    })
+}
+
+fn field_parameter(
+    records: &[SensorRecord],
+    parameter: &'static str,
+    criterion: Criterion,
+    sampling_time: NaiveDateTime,
+    minutes: i64,
+) -> Option<FieldParameter> {
+    let readings: Vec<(NaiveDateTime, f64)> = records
+        .iter()
+        .filter(|record| record.datetime <= sampling_time)
+        .filter(|record| record.qc_flag_of(parameter) != Some(QcFlag::Bad))
+        .filter_map(|record| Some((record.datetime, record.parameter(parameter)?)))
+        .collect();
+
+    let window_start = sampling_time - Duration::minutes(minutes);
+
+    // A parameter which settled early and drifted since is not stable at sampling
+    if let Some(&(start, end)) = stable_windows(&readings, criterion)
+        .last()
+        .filter(|(_, end)| readings[*end].0 >= window_start)
+    {
+        let window = &readings[start..=end];
+        return summarize(
+            parameter,
+            window,
+            FieldParameterMethod::Stabilized,
+            window[0].0,
+            window[window.len() - 1].0,
+        );
+    }
+
+    let window: Vec<(NaiveDateTime, f64)> = readings
+        .into_iter()
+        .filter(|(datetime, _)| *datetime >= window_start)
+        .collect();
+
+    summarize(
+        parameter,
+        &window,
+        FieldParameterMethod::Mean,
+        window_start,
+        sampling_time,
+    )
+}
+
+pub(crate) async fn compute_field_parameters(
+    db: &SqlitePool,
+    task_id: i64,
+    minutes: Option<i64>,
+) -> Result<FieldParameters, Error> {
+    let minutes = minutes.unwrap_or(WINDOW_MINUTES);
+    let records = fetch_sensor_data(db, task_id, DeriveMode::default()).await?;
+
+    let sampling_time = fetch_task_info(db, task_id)
+        .await?
+        .into_iter()
+        .find_map(|info| info.sampling_time)
+        .or_else(|| records.last().map(|record| record.datetime));
+
+    let parameters = match sampling_time {
+        Some(sampling_time) => STABILITY_CRITERIA
+            .iter()
+            .filter_map(|(parameter, criterion)| {
+                field_parameter(&records, parameter, *criterion, sampling_time, minutes)
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    Ok(FieldParameters {
+        task_id,
+        sampling_time,
+        window_minutes: minutes,
+        parameters,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct FieldParametersQuery {
+    /// Minutes before sampling averaged for parameters which never stabilized
+    #[serde(default)]
+    minutes: Option<i64>,
+}
+
+pub async fn get_field_parameters(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+    Query(query): Query<FieldParametersQuery>,
+) -> Result<Json<FieldParameters>, Error> {
+    Ok(Json(
+        compute_field_parameters(&ctx.db, task_id, query.minutes).await?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sensor_data::time;
+
+    /// One reading a minute.
+    fn readings(values: &[f64]) -> Vec<(NaiveDateTime, f64)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(minute, value)| (time(minute as i64), *value))
+            .collect()
+    }
+
+    /// Drifting for six readings, then settled.
+    const PH: &[f64] = &[
+        7.0, 7.5, 8.0, 8.5, 9.0, 9.5, 9.51, 9.49, 9.5, 9.52, 9.5, 9.49, 9.5, 9.51, 9.5, 9.5, 9.49,
+        9.5, 9.51, 9.5,
+    ];
+
+    #[test]
+    fn rolling_windows_by_time() {
+        let readings: Vec<(NaiveDateTime, f64)> = [0, 1, 2, 10, 11]
+            .into_iter()
+            .map(|minute| (time(minute), 0.0))
+            .collect();
+        assert_eq!(
+            rolling_windows(&readings, Duration::minutes(5)),
+            vec![(0, 2), (1, 2), (2, 2)]
+        );
+        assert_eq!(rolling_windows(&[], Duration::minutes(5)), vec![]);
+    }
+
+    #[test]
+    fn criteria() {
+        assert!(Criterion::Absolute(0.1).passes(&[7.0, 7.05, 7.1]));
+        assert!(!Criterion::Absolute(0.1).passes(&[7.0, 7.2]));
+        assert!(Criterion::Relative(0.03).passes(&[1000.0, 1020.0]));
+        assert!(!Criterion::Relative(0.03).passes(&[1000.0, 1100.0]));
+        assert!(!Criterion::Absolute(0.1).passes(&[]));
+    }
+
+    #[test]
+    fn stable_runs_merge_windows() {
+        assert_eq!(
+            stable_runs(&readings(PH), Criterion::Absolute(0.1)),
+            vec![(5, 18)]
+        );
+    }
+
+    #[test]
+    fn stable_runs_split_by_spike() {
+        let mut values = PH.to_vec();
+        values[12] = 12.0;
+        assert_eq!(
+            stable_runs(&readings(&values), Criterion::Absolute(0.1)),
+            vec![(5, 11), (13, 18)]
+        );
+    }
+
+    #[test]
+    fn stable_runs_never_stable() {
+        let values: Vec<f64> = (0..20).map(|idx| idx as f64).collect();
+        assert_eq!(
+            stable_runs(&readings(&values), Criterion::Absolute(0.1)),
+            vec![]
+        );
+    }
+
+    fn records(values: &[f64]) -> Vec<SensorRecord> {
+        readings(values)
+            .into_iter()
+            .map(|(datetime, ph)| SensorRecord {
+                ph,
+                ..SensorRecord::at(datetime)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn field_parameter_stabilized() {
+        let parameter = field_parameter(
+            &records(PH),
+            "ph",
+            Criterion::Absolute(0.1),
+            time(15),
+            WINDOW_MINUTES,
+        )
+        .unwrap();
+        // The last window closed by a reading before sampling is 10 to 14
+        assert_eq!(parameter.method, FieldParameterMethod::Stabilized);
+        assert_eq!(parameter.value, 9.5);
+        assert_eq!(parameter.records, 5);
+        assert_eq!(
+            (parameter.window_start, parameter.window_end),
+            (time(10), time(14))
+        );
+        assert_eq!((parameter.min, parameter.max), (9.49, 9.51));
+    }
+
+    #[test]
+    fn field_parameter_mean() {
+        let values: Vec<f64> = (0..20).map(|idx| idx as f64).collect();
+        let parameter = field_parameter(
+            &records(&values),
+            "ph",
+            Criterion::Absolute(0.1),
+            time(19),
+            4,
+        )
+        .unwrap();
+        assert_eq!(parameter.method, FieldParameterMethod::Mean);
+        assert_eq!(parameter.value, 17.0);
+        assert_eq!(parameter.records, 5);
+        assert_eq!(
+            (parameter.window_start, parameter.window_end),
+            (time(15), time(19))
+        );
+    }
+
+    #[test]
+    fn field_parameter_drifted_after_stable() {
+        // Settled for ten readings, then drifting until sampling
+        let values: Vec<f64> = (0..30)
+            .map(|idx| 9.5 + 0.5 * (idx as f64 - 9.0).max(0.0))
+            .collect();
+        let parameter = field_parameter(
+            &records(&values),
+            "ph",
+            Criterion::Absolute(0.1),
+            time(29),
+            WINDOW_MINUTES,
+        )
+        .unwrap();
+        assert_eq!(parameter.method, FieldParameterMethod::Mean);
+        assert_eq!(parameter.value, 17.0);
+        assert_eq!(parameter.records, 11);
+        assert_eq!(
+            (parameter.window_start, parameter.window_end),
+            (time(19), time(29))
+        );
+    }
+
+    #[test]
+    fn field_parameter_without_readings() {
+        assert!(field_parameter(&[], "ph", Criterion::Absolute(0.1), time(0), 10).is_none());
+    }
+}
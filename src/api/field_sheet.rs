@@ -0,0 +1,498 @@
+//! Printable field sheet of a task, rendered to PDF on the server. Text is set
+//! in the standard PDF fonts, which cover Latin-1 only, other characters are
+//! printed as `?`.
+
+use axum::extract::Path;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::Extension;
+use chrono::{NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+
+use super::chart::{Chart, Panel};
+use super::comment::CommentFormat;
+use super::export::fetch_task_export;
+use super::field_parameters::{compute_field_parameters, STABILITY_CRITERIA};
+use super::format::{label, local_time};
+use super::qc::QcFlag;
+use super::sensor_data::SensorRecord;
+use super::task::fetch_samples;
+use super::well::fetch_well;
+use super::{ApiContext, Error};
+
+/// A4 in points.
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 48.0;
+
+const FONT_SIZE: f32 = 9.0;
+const LEADING: f32 = 13.0;
+
+const REGULAR: Name = Name(b"F1");
+const BOLD: Name = Name(b"F2");
+
+/// Parameters drawn in the purge chart.
+const CHART_PARAMETERS: &[&str] = &[
+    "temp_internal",
+    "ph",
+    "spcndct",
+    "orp",
+    "do_con",
+    "turbidity",
+];
+
+/// Text in the WinAnsi encoding of the standard fonts.
+fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c as u32 {
+            0x20..=0x7e | 0xa0..=0xff => c as u8,
+            _ => b'?',
+        })
+        .collect()
+}
+
+fn or_dash(value: Option<String>) -> String {
+    value
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn number(value: Option<f64>) -> String {
+    or_dash(value.map(super::format::number))
+}
+
+/// A panel of the purge chart, placed with its lower left corner at `x`, `y`.
+struct PurgeChart<'a> {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    chart: &'a Chart<'a>,
+    panel: &'a Panel<'a>,
+}
+
+/// Pages laid out from the top, a new page is started when the next block
+/// doesn't fit.
+struct Sheet {
+    pages: Vec<Content>,
+    content: Content,
+    y: f32,
+}
+
+impl Sheet {
+    fn new() -> Self {
+        Self {
+            pages: Vec::new(),
+            content: Content::new(),
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    /// Start a new page unless `height` fits on the current one.
+    fn ensure(&mut self, height: f32) {
+        if self.y - height < MARGIN {
+            let content = std::mem::replace(&mut self.content, Content::new());
+            self.pages.push(content);
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+    }
+
+    fn text(&mut self, x: f32, y: f32, font: Name, size: f32, text: &str) {
+        self.content
+            .begin_text()
+            .set_font(font, size)
+            .next_line(x, y)
+            .show(Str(&encode(text)))
+            .end_text();
+    }
+
+    fn line(&mut self, x1: f32, y1: f32, x2: f32, y2: f32) {
+        self.content.move_to(x1, y1).line_to(x2, y2).stroke();
+    }
+
+    fn title(&mut self, title: &str, subtitle: &str) {
+        self.y -= 16.0;
+        self.text(MARGIN, self.y, BOLD, 16.0, title);
+        self.y -= LEADING;
+        self.text(MARGIN, self.y, REGULAR, FONT_SIZE, subtitle);
+        self.y -= LEADING;
+    }
+
+    fn heading(&mut self, heading: &str) {
+        self.ensure(3.0 * LEADING);
+        self.y -= LEADING;
+        self.text(MARGIN, self.y, BOLD, 11.0, heading);
+        self.y -= 4.0;
+        self.content.set_line_width(0.5);
+        self.line(MARGIN, self.y, PAGE_WIDTH - MARGIN, self.y);
+        self.y -= LEADING;
+    }
+
+    /// Labelled values in two columns.
+    fn fields(&mut self, fields: &[(&str, String)]) {
+        let column = (PAGE_WIDTH - 2.0 * MARGIN) / 2.0;
+        for pair in fields.chunks(2) {
+            self.ensure(LEADING);
+            for (idx, (label, value)) in pair.iter().enumerate() {
+                let x = MARGIN + idx as f32 * column;
+                self.text(x, self.y, BOLD, FONT_SIZE, label);
+                self.text(x + 110.0, self.y, REGULAR, FONT_SIZE, value);
+            }
+            self.y -= LEADING;
+        }
+    }
+
+    /// A table row, cells start at the given offsets from the margin.
+    fn row(&mut self, columns: &[f32], cells: &[String], font: Name) {
+        self.ensure(LEADING);
+        for (x, cell) in columns.iter().zip(cells) {
+            self.text(MARGIN + x, self.y, font, FONT_SIZE, cell);
+        }
+        self.y -= LEADING;
+    }
+
+    /// Lines of free text, wrapped at a number of characters.
+    fn paragraph(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut rest = line;
+            loop {
+                let split = rest
+                    .char_indices()
+                    .nth(100)
+                    .map(|(idx, _)| rest[..idx].rfind(' ').unwrap_or(idx))
+                    .unwrap_or(rest.len());
+                self.ensure(LEADING);
+                self.text(MARGIN, self.y, REGULAR, FONT_SIZE, &rest[..split]);
+                self.y -= LEADING;
+                rest = rest[split..].trim_start();
+                if rest.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// A line chart of one parameter over the purge, with the stabilization
+    /// windows, QC flagged values and the sampling time marked.
+    fn chart(&mut self, purge_chart: &PurgeChart) {
+        let PurgeChart {
+            x,
+            y,
+            width,
+            height,
+            chart,
+            panel,
+        } = *purge_chart;
+        let px = |t: f64| x + chart.position(t) as f32 * width;
+        let py = |value: f64| y + panel.position(value) as f32 * height;
+
+        self.text(x, y + height + 4.0, BOLD, 8.0, label(panel.parameter));
+
+        self.content.set_fill_rgb(0.85, 0.95, 0.85);
+        for (start, end) in &panel.stable {
+            self.content
+                .rect(px(*start), y, px(*end) - px(*start), height)
+                .fill_nonzero();
+        }
+        self.content.set_fill_rgb(0.0, 0.0, 0.0);
+
+        self.content
+            .set_line_width(0.5)
+            .set_stroke_rgb(0.6, 0.6, 0.6);
+        self.content.rect(x, y, width, height).stroke();
+
+        if let Some(sampling) = chart.sampling {
+            self.content
+                .set_stroke_rgb(0.8, 0.1, 0.1)
+                .set_dash_pattern([2.0, 2.0], 0.0);
+            self.line(px(sampling), y, px(sampling), y + height);
+            self.content.set_dash_pattern(std::iter::empty(), 0.0);
+        }
+
+        let Some((min, max)) = panel.range else {
+            self.content.set_stroke_rgb(0.0, 0.0, 0.0);
+            self.text(x + 4.0, y + height / 2.0, REGULAR, 8.0, "No data");
+            return;
+        };
+
+        self.content
+            .set_line_width(1.0)
+            .set_stroke_rgb(0.1, 0.3, 0.7);
+        for line in &panel.lines {
+            for (idx, (t, value)) in line.iter().enumerate() {
+                if idx == 0 {
+                    self.content.move_to(px(*t), py(*value));
+                } else {
+                    self.content.line_to(px(*t), py(*value));
+                }
+            }
+        }
+        self.content.stroke();
+
+        for (t, value, flag) in &panel.flagged {
+            match flag {
+                QcFlag::Bad => self.content.set_fill_rgb(0.84, 0.15, 0.16),
+                _ => self.content.set_fill_rgb(1.0, 0.6, 0.0),
+            };
+            self.content
+                .rect(px(*t) - 1.5, py(*value) - 1.5, 3.0, 3.0)
+                .fill_nonzero();
+        }
+
+        self.content
+            .set_fill_rgb(0.0, 0.0, 0.0)
+            .set_stroke_rgb(0.0, 0.0, 0.0)
+            .set_line_width(0.5);
+        self.text(x + 2.0, y + height - 8.0, REGULAR, 7.0, &number(Some(max)));
+        self.text(x + 2.0, y + 2.0, REGULAR, 7.0, &number(Some(min)));
+    }
+
+    fn signature(&mut self, label: &str) {
+        self.ensure(3.0 * LEADING);
+        self.y -= 2.0 * LEADING;
+        self.line(MARGIN, self.y, MARGIN + 250.0, self.y);
+        self.line(MARGIN + 300.0, self.y, PAGE_WIDTH - MARGIN, self.y);
+        self.y -= LEADING - 2.0;
+        self.text(MARGIN, self.y, REGULAR, 8.0, label);
+        self.text(MARGIN + 300.0, self.y, REGULAR, 8.0, "Date");
+    }
+
+    fn finish(mut self, title: &str) -> Vec<u8> {
+        self.pages.push(self.content);
+
+        let catalog_id = Ref::new(1);
+        let tree_id = Ref::new(2);
+        let info_id = Ref::new(3);
+        let regular_id = Ref::new(4);
+        let bold_id = Ref::new(5);
+
+        let mut pdf = Pdf::new();
+        pdf.catalog(catalog_id).pages(tree_id);
+        pdf.document_info(info_id).title(pdf_writer::TextStr(title));
+        pdf.type1_font(regular_id)
+            .base_font(Name(b"Helvetica"))
+            .encoding_predefined(Name(b"WinAnsiEncoding"));
+        pdf.type1_font(bold_id)
+            .base_font(Name(b"Helvetica-Bold"))
+            .encoding_predefined(Name(b"WinAnsiEncoding"));
+
+        let count = self.pages.len() as i32;
+        let page_ids: Vec<Ref> = (0..count).map(|idx| Ref::new(6 + 2 * idx)).collect();
+        pdf.pages(tree_id)
+            .kids(page_ids.iter().copied())
+            .count(count);
+
+        for (idx, content) in self.pages.into_iter().enumerate() {
+            let page_id = page_ids[idx];
+            let content_id = Ref::new(page_id.get() + 1);
+
+            let mut content = content;
+            content
+                .begin_text()
+                .set_font(REGULAR, 7.0)
+                .next_line(PAGE_WIDTH - MARGIN - 40.0, MARGIN / 2.0)
+                .show(Str(format!("Page {} / {}", idx + 1, count).as_bytes()))
+                .end_text();
+
+            let mut page = pdf.page(page_id);
+            page.parent(tree_id)
+                .media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+                .contents(content_id);
+            page.resources()
+                .fonts()
+                .pair(REGULAR, regular_id)
+                .pair(BOLD, bold_id);
+            page.finish();
+
+            pdf.stream(content_id, &content.finish());
+        }
+
+        pdf.finish()
+    }
+}
+
+fn date_time(tz: Tz, datetime: Option<NaiveDateTime>) -> String {
+    or_dash(datetime.map(|datetime| local_time(tz, datetime, "%Y-%m-%d %H:%M")))
+}
+
+fn purge_chart(sheet: &mut Sheet, records: &[SensorRecord], sampling_time: Option<NaiveDateTime>) {
+    let chart = Chart::new(records, CHART_PARAMETERS, sampling_time);
+
+    let gap = 16.0;
+    let width = (PAGE_WIDTH - 2.0 * MARGIN - gap) / 2.0;
+    let height = 80.0;
+
+    for pair in chart.panels.chunks(2) {
+        sheet.ensure(height + 2.0 * LEADING);
+        sheet.y -= height + 4.0;
+        for (idx, panel) in pair.iter().enumerate() {
+            sheet.chart(&PurgeChart {
+                x: MARGIN + idx as f32 * (width + gap),
+                y: sheet.y,
+                width,
+                height,
+                chart: &chart,
+                panel,
+            });
+        }
+        sheet.y -= 2.0 * LEADING;
+    }
+}
+
+/// Field sheet of a task as PDF, with the well, the task info entries, the
+/// sample set, the final field parameters and a chart of the purge.
+pub async fn get_field_sheet(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<impl IntoResponse, Error> {
+    let tz = ctx.settings.time_zone;
+    let task = fetch_task_export(&ctx.db, task_id, CommentFormat::Text).await?;
+    let well = fetch_well(&ctx.db, task.well_id).await?;
+    let field_parameters = compute_field_parameters(&ctx.db, task_id, None).await?;
+
+    let sample_set = fetch_samples(&ctx.db, task_id).await?;
+
+    let serial = task.serial.clone().unwrap_or_else(|| task_id.to_string());
+    let title = format!("Field sheet {}", serial);
+
+    let mut sheet = Sheet::new();
+    sheet.title(
+        &title,
+        &format!(
+            "Printed {}, times in {}",
+            date_time(tz, Some(Utc::now().naive_utc())),
+            tz.name()
+        ),
+    );
+
+    sheet.heading("Well");
+    sheet.fields(&[
+        ("Name", well.name.clone()),
+        ("Type", or_dash(well.type_.clone())),
+        ("Aquifer", or_dash(well.aquifer.clone())),
+        (
+            "Coordinates",
+            match (well.x, well.y) {
+                (Some(x), Some(y)) => format!("{}, {} ({})", x, y, well.crs),
+                _ => "-".to_string(),
+            },
+        ),
+        ("Casing diameter (m)", number(well.casing_diameter)),
+        ("Total depth (m)", number(well.total_depth)),
+        ("Screen top (m)", number(well.screen_top)),
+        ("Screen bottom (m)", number(well.screen_bottom)),
+    ]);
+
+    sheet.heading("Task");
+    sheet.fields(&[
+        ("Serial", or_dash(task.serial.clone())),
+        ("Depth", task.depth.clone()),
+        ("Campaign", or_dash(task.campaign_name.clone())),
+        ("Status", task.status.label().to_string()),
+    ]);
+
+    // Oldest entry first, as they were written in the field
+    for (idx, entry) in task.task_info.iter().rev().enumerate() {
+        let info = &entry.info;
+        let pump = match info.pump_id {
+            Some(pump_id) => {
+                sqlx::query_scalar!("SELECT name FROM pump WHERE id = $1", pump_id)
+                    .fetch_optional(&ctx.db)
+                    .await?
+            }
+            None => None,
+        };
+
+        sheet.heading(&format!("Field record {}", idx + 1));
+        sheet.fields(&[
+            ("Purging time", date_time(tz, info.purging_time)),
+            ("Sampling time", date_time(tz, info.sampling_time)),
+            ("Water level (m)", number(info.water_level)),
+            ("Calibration", or_dash(info.calibration.clone())),
+            ("Pump", or_dash(pump)),
+            ("Pump depth (m)", number(info.pump_depth)),
+            ("Pump frequency (Hz)", number(info.pump_freq)),
+            ("Pump rate (L/min)", number(info.pump_rate)),
+            ("Hose setup", or_dash(info.hose_setup.clone())),
+            ("Radium sample weight", number(info.sample_wt_radium)),
+            ("Sampled by", or_dash(Some(entry.sampled_by.join(", ")))),
+            ("Minuted by", or_dash(Some(entry.minuted_by.join(", ")))),
+        ]);
+        if let Some(comment) = info.comment.as_deref().filter(|c| !c.is_empty()) {
+            sheet.paragraph(comment);
+        }
+    }
+
+    sheet.heading("Sample set");
+    let columns = [0.0, 300.0];
+    sheet.row(
+        &columns,
+        &["Sample type".to_string(), "Bottles".to_string()],
+        BOLD,
+    );
+    for sample in &sample_set {
+        let name = match &sample.variant {
+            Some(variant) => format!("{} ({})", sample.name, variant),
+            None => sample.name.clone(),
+        };
+        sheet.row(&columns, &[name, sample.qty.to_string()], REGULAR);
+    }
+
+    sheet.heading("Final field parameters");
+    let columns = [0.0, 150.0, 205.0, 260.0, 310.0, 360.0];
+    sheet.row(
+        &columns,
+        &["Parameter", "Value", "Method", "Min", "Max", "Window"].map(str::to_string),
+        BOLD,
+    );
+    for parameter in &field_parameters.parameters {
+        sheet.row(
+            &columns,
+            &[
+                label(parameter.parameter).to_string(),
+                number(Some(parameter.value)),
+                parameter.method.label().to_string(),
+                number(Some(parameter.min)),
+                number(Some(parameter.max)),
+                format!(
+                    "{} - {}, {} readings",
+                    local_time(tz, parameter.window_start, "%H:%M"),
+                    local_time(tz, parameter.window_end, "%H:%M"),
+                    parameter.records
+                ),
+            ],
+            REGULAR,
+        );
+    }
+    if field_parameters.parameters.len() < STABILITY_CRITERIA.len() {
+        sheet.paragraph("Parameters without readings before sampling are left out.");
+    }
+
+    sheet.heading("Purge parameters");
+    purge_chart(
+        &mut sheet,
+        &task.sensor_data,
+        field_parameters.sampling_time,
+    );
+
+    sheet.heading("Signatures");
+    sheet.signature("Field technician");
+    sheet.signature("Reviewed by");
+
+    let pdf = sheet.finish(&title);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "inline; filename=\"field_sheet_{}.pdf\"",
+                    serial.replace('"', "")
+                ),
+            ),
+        ],
+        pdf,
+    ))
+}
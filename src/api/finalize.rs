@@ -0,0 +1,214 @@
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteExecutor;
+
+use super::auth::{Role, Session};
+use super::{ApiContext, Error};
+
+/// Reject changes to a task which has been finalized.
+pub async fn ensure_unlocked<'e>(db: impl SqliteExecutor<'e>, task_id: i64) -> Result<(), Error> {
+    let task = sqlx::query!("SELECT finalized_at FROM task WHERE id = $1", task_id)
+        .fetch_optional(db)
+        .await?;
+
+    match task {
+        Some(task) if task.finalized_at.is_some() => Err(Error::Locked(task_id)),
+        _ => Ok(()),
+    }
+}
+
+/// Same as `ensure_unlocked`, for routes which address a `task_info` entry.
+pub async fn ensure_task_info_unlocked<'e>(
+    db: impl SqliteExecutor<'e>,
+    task_info_id: i64,
+) -> Result<(), Error> {
+    let task = sqlx::query!(
+        r#"
+        SELECT
+            task.id,
+            task.finalized_at
+        FROM
+            task_info
+        JOIN task
+            ON task.id = task_info.task_id
+        WHERE
+            task_info.id = $1
+        "#,
+        task_info_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    match task {
+        Some(task) if task.finalized_at.is_some() => Err(Error::Locked(task.id)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Serialize)]
+pub struct FinalizeLog {
+    id: i64,
+    action: String,
+    people_id: i64,
+    #[serde(with = "super::serde::iso8601")]
+    timestamp: NaiveDateTime,
+    reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Finalization {
+    #[serde(with = "super::serde::iso8601_option")]
+    finalized_at: Option<NaiveDateTime>,
+    finalized_by: Option<i64>,
+    log: Vec<FinalizeLog>,
+}
+
+pub async fn get_finalization(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<Finalization>, Error> {
+    let task = sqlx::query!(
+        "SELECT finalized_at, finalized_by FROM task WHERE id = $1",
+        task_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let log = sqlx::query_as!(
+        FinalizeLog,
+        r#"
+        SELECT
+            id,
+            action,
+            people_id,
+            "timestamp",
+            reason
+        FROM
+            task_finalize_log
+        WHERE
+            task_id = $1
+        ORDER BY
+            id
+        "#,
+        task_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(Finalization {
+        finalized_at: task.finalized_at,
+        finalized_by: task.finalized_by,
+        log,
+    }))
+}
+
+/// Sign off a task. All mutating endpoints of the task are locked afterwards.
+pub async fn finalize_task(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(task_id): Path<i64>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    ensure_unlocked(&mut *tx, task_id).await?;
+
+    let now = Utc::now().naive_utc();
+
+    let updated = sqlx::query!(
+        r#"
+        UPDATE task
+        SET
+            finalized_at = $1,
+            finalized_by = $2
+        WHERE
+            id = $3
+        "#,
+        now,
+        session.people_id,
+        task_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Err(Error::TaskNotFound(task_id));
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            task_finalize_log (task_id, action, people_id, "timestamp")
+        VALUES
+            ($1, 'finalize', $2, $3)
+        "#,
+        task_id,
+        session.people_id,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct Reopen {
+    reason: String,
+}
+
+/// Unlock a finalized task, a reason is required for the record.
+pub async fn reopen_task(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(task_id): Path<i64>,
+    Json(reopen): Json<Reopen>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    if reopen.reason.trim().is_empty() {
+        return Err(anyhow::anyhow!("A reason is required to reopen a task").into());
+    }
+
+    let mut tx = ctx.db.begin().await?;
+
+    let task = sqlx::query!("SELECT finalized_at FROM task WHERE id = $1", task_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::TaskNotFound(task_id))?;
+    if task.finalized_at.is_none() {
+        return Err(Error::NotFinalized(task_id));
+    }
+
+    sqlx::query!(
+        "UPDATE task SET finalized_at = NULL, finalized_by = NULL WHERE id = $1",
+        task_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let now = Utc::now().naive_utc();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            task_finalize_log (task_id, action, people_id, "timestamp", reason)
+        VALUES
+            ($1, 'reopen', $2, $3, $4)
+        "#,
+        task_id,
+        session.people_id,
+        now,
+        reopen.reason
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
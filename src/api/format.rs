@@ -0,0 +1,66 @@
+//! Formatting shared by the rendered documents: the SVG charts, the PDF field
+//! sheet, the KML export and search snippets.
+
+use chrono::{DateTime, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+
+/// Labels of the sensor data parameters, with the units they are stored in.
+const PARAMETER_LABELS: &[(&str, &str)] = &[
+    ("cndct", "Conductivity (µS/cm)"),
+    ("temp_internal", "Temperature (°C)"),
+    ("spcndct", "Specific conductance (µS/cm)"),
+    ("sa", "Salinity (PSU)"),
+    ("resis", "Resistivity (ohm-cm)"),
+    ("wtr_d", "Water density (g/cm³)"),
+    ("tds", "Total dissolved solids (ppm)"),
+    ("turbidity", "Turbidity (NTU)"),
+    ("ph", "pH"),
+    ("ph_mv", "pH (mV)"),
+    ("orp", "ORP (mV)"),
+    ("do_con", "Dissolved oxygen (mg/L)"),
+    ("do_sat", "Dissolved oxygen (% saturation)"),
+    ("ppo2", "Partial pressure oxygen (Torr)"),
+    ("temp_sensor", "Sensor temperature (°C)"),
+    ("v", "External voltage (V)"),
+    ("pres_baro", "Barometric pressure (PSI)"),
+    ("pres", "Pressure (PSI)"),
+    ("depth", "Depth (m)"),
+];
+
+/// Label of a sensor data parameter, the parameter itself if unknown.
+pub(crate) fn label(parameter: &str) -> &str {
+    PARAMETER_LABELS
+        .iter()
+        .find(|(name, _)| *name == parameter)
+        .map_or(parameter, |(_, label)| label)
+}
+
+/// A value rounded to three decimals.
+pub(crate) fn number(value: f64) -> String {
+    format!("{}", (value * 1000.0).round() / 1000.0)
+}
+
+/// Seconds since the epoch of a UTC timestamp, the time axis of the charts.
+pub(crate) fn seconds(datetime: NaiveDateTime) -> f64 {
+    datetime.and_utc().timestamp() as f64
+}
+
+/// A UTC timestamp in the project time zone.
+pub(crate) fn local_time(tz: Tz, datetime: NaiveDateTime, format: &str) -> String {
+    tz.from_utc_datetime(&datetime).format(format).to_string()
+}
+
+/// Seconds since the epoch in the project time zone.
+pub(crate) fn local_seconds(tz: Tz, t: f64, format: &str) -> String {
+    DateTime::from_timestamp(t as i64, 0)
+        .map(|datetime| local_time(tz, datetime.naive_utc(), format))
+        .unwrap_or_default()
+}
+
+pub(crate) fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
@@ -1,11 +1,38 @@
+pub mod annotation;
+pub mod anomaly;
+pub mod attachment;
+pub mod auth;
+pub mod calibration;
+pub mod campaign;
+pub mod chart;
+pub mod clock_offset;
+pub mod comment;
+pub mod compensation;
+pub mod correction;
+pub mod deployment;
+pub mod derived;
+pub mod equipment;
 pub mod error;
+pub mod export;
+pub mod field_parameters;
+pub mod field_sheet;
+pub mod finalize;
+pub mod format;
 pub mod people;
 pub mod pump;
+pub mod purge;
+pub mod qc;
+pub mod report;
 pub mod sample_type;
+pub mod search;
 pub mod sensor_data;
 pub mod serde;
 pub mod task;
 pub mod task_info;
+pub mod task_status;
+pub mod task_summary;
+pub mod time_zone;
+pub mod water_level;
 pub mod well;
 
 use sqlx::sqlite::SqlitePool;
@@ -13,13 +40,25 @@ use sqlx::sqlite::SqlitePool;
 pub use error::Error;
 pub use sensor_data::insitu_log_handler;
 
+/// Server wide settings, set from the command line.
+#[derive(Clone)]
+pub struct Settings {
+    /// Minimum number of well volumes to purge before sampling
+    pub min_purge_volumes: f64,
+    /// Largest allowed water level drawdown while purging, in metres
+    pub max_drawdown: f64,
+    /// Time zone of times without an offset, like those of logger files and photos
+    pub time_zone: chrono_tz::Tz,
+}
+
 #[derive(Clone)]
 pub struct ApiContext {
     db: SqlitePool,
+    settings: Settings,
 }
 
 impl ApiContext {
-    pub fn new(db: SqlitePool) -> Self {
-        Self { db }
+    pub fn new(db: SqlitePool, settings: Settings) -> Self {
+        Self { db, settings }
     }
 }
@@ -1,17 +1,81 @@
-use axum::{Extension, Json};
+use std::collections::HashMap;
+
+use axum::extract::{Json, Path};
+use axum::Extension;
 use serde::{Deserialize, Serialize};
 
+use super::auth::{ensure_secret_for_role, hash_secret, Role, Session};
 use super::{ApiContext, Error};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct People {
     id: i64,
     name: String,
+    role: Role,
 }
 
 pub async fn list_people(ctx: Extension<ApiContext>) -> Result<Json<Vec<People>>, Error> {
-    let people = sqlx::query_as!(People, "SELECT id, name FROM people")
-        .fetch_all(&ctx.db)
-        .await?;
+    let people = sqlx::query_as!(
+        People,
+        r#"SELECT id, name, role AS "role: Role" FROM people"#
+    )
+    .fetch_all(&ctx.db)
+    .await?;
     Ok(Json(people))
 }
+
+pub async fn update_people(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(people_id): Path<i64>,
+    Json(update): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    for (key, value) in update {
+        match key.as_str() {
+            "role" => {
+                let value: Role = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE people SET role = $1 WHERE id = $2",
+                    value,
+                    people_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "secret" => {
+                let value = value.as_str().map(hash_secret).transpose()?;
+                sqlx::query!(
+                    "UPDATE people SET secret_hash = $1 WHERE id = $2",
+                    value,
+                    people_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
+            }
+        }
+    }
+
+    // Checked after all keys are applied, so a role and a secret may be set together
+    let people = sqlx::query!(
+        r#"SELECT role AS "role: Role", secret_hash FROM people WHERE id = $1"#,
+        people_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if let Some(people) = people {
+        if people.secret_hash.is_none() {
+            ensure_secret_for_role(people.role)?;
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use std::f64::consts::PI;
+
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use super::{ApiContext, Error};
+
+/// Litres per cubic metre.
+const LITRES: f64 = 1000.0;
+
+struct PurgeInput {
+    task_id: i64,
+    task_info_id: i64,
+    well_id: i64,
+    casing_diameter: Option<f64>,
+    total_depth: Option<f64>,
+    water_level: Option<f64>,
+    pump_rate: Option<f64>,
+    purging_time: Option<NaiveDateTime>,
+    sampling_time: Option<NaiveDateTime>,
+}
+
+/// Purge volumes of a task_info record. Volumes are in litres, values which
+/// can't be computed from the recorded data are null.
+#[derive(Serialize)]
+pub struct PurgeVolume {
+    task_id: i64,
+    task_info_id: i64,
+    well_id: i64,
+    /// Height of the water column in the casing, in metres
+    water_column: Option<f64>,
+    standing_volume: Option<f64>,
+    purge_minutes: Option<f64>,
+    purged_volume: Option<f64>,
+    well_volumes: Option<f64>,
+    min_well_volumes: f64,
+    /// Whether the minimum number of well volumes was purged before sampling
+    sufficient: Option<bool>,
+}
+
+impl PurgeVolume {
+    fn compute(input: PurgeInput, min_well_volumes: f64) -> Self {
+        let water_column = match (input.total_depth, input.water_level) {
+            (Some(total_depth), Some(water_level)) => Some((total_depth - water_level).max(0.0)),
+            _ => None,
+        };
+
+        let standing_volume = match (input.casing_diameter, water_column) {
+            (Some(diameter), Some(column)) => Some(PI * (diameter / 2.0).powi(2) * column * LITRES),
+            _ => None,
+        };
+
+        let purge_minutes = match (input.purging_time, input.sampling_time) {
+            (Some(purging_time), Some(sampling_time)) if sampling_time < purging_time => {
+                tracing::warn!(
+                    "Task info {} was sampled before purging started",
+                    input.task_info_id
+                );
+                None
+            }
+            (Some(purging_time), Some(sampling_time)) => {
+                Some((sampling_time - purging_time).num_seconds() as f64 / 60.0)
+            }
+            _ => None,
+        };
+
+        let purged_volume = match (input.pump_rate, purge_minutes) {
+            (Some(rate), Some(minutes)) => Some(rate * minutes),
+            _ => None,
+        };
+
+        let well_volumes = match (purged_volume, standing_volume) {
+            (Some(purged), Some(standing)) if standing > 0.0 => Some(purged / standing),
+            _ => None,
+        };
+
+        PurgeVolume {
+            task_id: input.task_id,
+            task_info_id: input.task_info_id,
+            well_id: input.well_id,
+            water_column,
+            standing_volume,
+            purge_minutes,
+            purged_volume,
+            well_volumes,
+            min_well_volumes,
+            sufficient: well_volumes.map(|volumes| volumes >= min_well_volumes),
+        }
+    }
+}
+
+async fn fetch_purge_volumes(
+    db: &SqlitePool,
+    task_id: Option<i64>,
+    min_well_volumes: f64,
+) -> Result<Vec<PurgeVolume>, Error> {
+    let inputs = sqlx::query_as!(
+        PurgeInput,
+        r#"
+        SELECT
+            task.id AS task_id,
+            task_info.id AS task_info_id,
+            well.id AS well_id,
+            well.casing_diameter,
+            well.total_depth,
+            task_info.water_level,
+            task_info.pump_rate,
+            task_info.purging_time,
+            task_info.sampling_time
+        FROM
+            task_info
+        JOIN task
+            ON task.id = task_info.task_id
+        JOIN well
+            ON well.id = task.well_id
+        WHERE
+            $1 IS NULL OR task.id = $1
+        ORDER BY
+            task.id, task_info.id
+        "#,
+        task_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(inputs
+        .into_iter()
+        .map(|input| PurgeVolume::compute(input, min_well_volumes))
+        .collect())
+}
+
+pub async fn get_purge_volumes(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<Vec<PurgeVolume>>, Error> {
+    Ok(Json(
+        fetch_purge_volumes(&ctx.db, Some(task_id), ctx.settings.min_purge_volumes).await?,
+    ))
+}
+
+/// Sampled task_info records which were purged less than the minimum number of well volumes.
+pub async fn list_insufficient_purges(
+    ctx: Extension<ApiContext>,
+) -> Result<Json<Vec<PurgeVolume>>, Error> {
+    let purges = fetch_purge_volumes(&ctx.db, None, ctx.settings.min_purge_volumes).await?;
+
+    Ok(Json(
+        purges
+            .into_iter()
+            .filter(|purge| purge.sufficient == Some(false))
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate};
+
+    use super::*;
+
+    /// A well 0.05 m wide and 20 m deep with the water at 10 m, purged at
+    /// 2 l/min from `purging` to `sampling` minutes.
+    fn input(purging: i64, sampling: i64) -> PurgeInput {
+        let time = |minute| {
+            NaiveDate::from_ymd_opt(2026, 5, 1)
+                .unwrap()
+                .and_hms_opt(8, 0, 0)
+                .unwrap()
+                + Duration::minutes(minute)
+        };
+        PurgeInput {
+            task_id: 1,
+            task_info_id: 1,
+            well_id: 1,
+            casing_diameter: Some(0.05),
+            total_depth: Some(20.0),
+            water_level: Some(10.0),
+            pump_rate: Some(2.0),
+            purging_time: Some(time(purging)),
+            sampling_time: Some(time(sampling)),
+        }
+    }
+
+    fn assert_close(value: Option<f64>, expected: f64) {
+        let value = value.unwrap();
+        assert!((value - expected).abs() < 1e-6, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn volumes() {
+        let purge = PurgeVolume::compute(input(0, 30), 3.0);
+        assert_close(purge.water_column, 10.0);
+        // π × 0.025² m² × 10 m
+        assert_close(purge.standing_volume, 19.634_954);
+        assert_close(purge.purge_minutes, 30.0);
+        assert_close(purge.purged_volume, 60.0);
+        assert_close(purge.well_volumes, 3.055_775);
+        assert_eq!(purge.sufficient, Some(true));
+
+        let purge = PurgeVolume::compute(input(0, 20), 3.0);
+        assert_close(purge.well_volumes, 2.037_183);
+        assert_eq!(purge.sufficient, Some(false));
+    }
+
+    #[test]
+    fn missing_or_invalid_inputs() {
+        let purge = PurgeVolume::compute(
+            PurgeInput {
+                water_level: None,
+                ..input(0, 30)
+            },
+            3.0,
+        );
+        assert_eq!(purge.standing_volume, None);
+        assert_close(purge.purged_volume, 60.0);
+        assert_eq!(purge.sufficient, None);
+
+        // Water below the bottom of the well
+        let purge = PurgeVolume::compute(
+            PurgeInput {
+                water_level: Some(25.0),
+                ..input(0, 30)
+            },
+            3.0,
+        );
+        assert_close(purge.standing_volume, 0.0);
+        assert_eq!(purge.well_volumes, None);
+
+        // Sampled before purging started
+        let purge = PurgeVolume::compute(input(30, 0), 3.0);
+        assert_eq!(purge.purge_minutes, None);
+        assert_eq!(purge.purged_volume, None);
+        assert_eq!(purge.sufficient, None);
+    }
+}
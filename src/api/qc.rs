@@ -0,0 +1,697 @@
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::auth::{Role, Session};
+use super::finalize::ensure_unlocked;
+use super::sensor_data::{SensorRecord, PARAMETERS};
+use super::{ApiContext, Error};
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum QcFlag {
+    Good,
+    Suspect,
+    Bad,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QcFilter {
+    /// All records, with their flags
+    #[default]
+    Annotate,
+    /// Leave out records with a value flagged bad
+    ExcludeBad,
+    /// Leave out records with a value flagged suspect or bad
+    ExcludeSuspect,
+}
+
+impl QcFilter {
+    pub(crate) fn keeps(self, record: &SensorRecord) -> bool {
+        !matches!(
+            (self, record.worst_qc_flag()),
+            (QcFilter::ExcludeBad, Some(QcFlag::Bad))
+                | (
+                    QcFilter::ExcludeSuspect,
+                    Some(QcFlag::Suspect | QcFlag::Bad)
+                )
+        )
+    }
+}
+
+/// A QC flag on a sensor value or record.
+#[derive(Debug, Clone, Serialize)]
+pub struct QcMark {
+    /// Flags are applied in the order of their ids, a later one takes precedence
+    pub(crate) flag_id: i64,
+    pub(crate) flag: QcFlag,
+    reason: Option<String>,
+    /// Suggested by the automatic checks and not accepted yet
+    pub(crate) suggested: bool,
+}
+
+/// A QC flag on the sensor data of a task from `start_time` to `end_time`,
+/// both included. Without a parameter the flag applies to whole records.
+#[derive(Serialize)]
+pub struct SensorQcFlag {
+    id: i64,
+    task_id: i64,
+    parameter: Option<String>,
+    #[serde(with = "super::serde::iso8601")]
+    start_time: NaiveDateTime,
+    #[serde(with = "super::serde::iso8601")]
+    end_time: NaiveDateTime,
+    flag: QcFlag,
+    reason: Option<String>,
+    suggested: bool,
+    created_by: i64,
+    #[serde(with = "super::serde::iso8601")]
+    created_at: NaiveDateTime,
+}
+
+async fn fetch_qc_flags(db: &SqlitePool, task_id: i64) -> Result<Vec<SensorQcFlag>, Error> {
+    let flags = sqlx::query_as!(
+        SensorQcFlag,
+        r#"
+        SELECT
+            id AS "id!",
+            task_id,
+            parameter,
+            start_time,
+            end_time,
+            flag AS "flag: QcFlag",
+            reason,
+            suggested,
+            created_by,
+            created_at
+        FROM
+            sensor_qc_flag
+        WHERE
+            task_id = $1
+            AND NOT rejected
+        ORDER BY
+            id
+        "#,
+        task_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(flags)
+}
+
+/// Mark the records with the QC flags of the task, in the order they were made.
+/// Suggested flags don't replace accepted ones.
+pub(crate) async fn apply_qc_flags(
+    db: &SqlitePool,
+    task_id: i64,
+    records: &mut [SensorRecord],
+) -> Result<(), Error> {
+    let flags = fetch_qc_flags(db, task_id).await?;
+    mark_records(records, flags);
+
+    Ok(())
+}
+
+/// Mark the records with flags ordered by id, see `apply_qc_flags`.
+fn mark_records(records: &mut [SensorRecord], flags: Vec<SensorQcFlag>) {
+    for flag in flags {
+        let mark = QcMark {
+            flag_id: flag.id,
+            flag: flag.flag,
+            reason: flag.reason,
+            suggested: flag.suggested,
+        };
+
+        for record in records
+            .iter_mut()
+            .filter(|record| record.datetime >= flag.start_time && record.datetime <= flag.end_time)
+        {
+            let current = match &flag.parameter {
+                Some(parameter) => record
+                    .qc_flags
+                    .entry(parameter.clone())
+                    .or_insert_with(|| mark.clone()),
+                None => record.qc_flag.get_or_insert_with(|| mark.clone()),
+            };
+            if !mark.suggested || current.suggested {
+                *current = mark.clone();
+            }
+        }
+    }
+}
+
+pub async fn list_qc_flags(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<Vec<SensorQcFlag>>, Error> {
+    Ok(Json(fetch_qc_flags(&ctx.db, task_id).await?))
+}
+
+#[derive(Deserialize)]
+pub struct NewSensorQcFlag {
+    /// Flag whole records when not set
+    #[serde(default)]
+    pub(crate) parameter: Option<String>,
+    /// Defaults to the first logged record of the task
+    #[serde(default, with = "super::serde::iso8601_option")]
+    pub(crate) start_time: Option<NaiveDateTime>,
+    /// Defaults to the last logged record of the task
+    #[serde(default, with = "super::serde::iso8601_option")]
+    pub(crate) end_time: Option<NaiveDateTime>,
+    pub(crate) flag: QcFlag,
+    #[serde(default)]
+    pub(crate) reason: Option<String>,
+}
+
+pub async fn insert_qc_flag(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(task_id): Path<i64>,
+    Json(qc_flag): Json<NewSensorQcFlag>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    if let Some(parameter) = &qc_flag.parameter {
+        if !PARAMETERS.contains(&parameter.as_str()) {
+            return Err(anyhow::anyhow!("Invalid parameter: {:?}", parameter).into());
+        }
+    }
+
+    let range = sqlx::query!(
+        r#"
+        SELECT
+            MIN(datetime) AS "first: NaiveDateTime",
+            MAX(datetime) AS "last: NaiveDateTime"
+        FROM
+            sensor_data
+        WHERE
+            task_id = $1
+        "#,
+        task_id
+    )
+    .fetch_one(&ctx.db)
+    .await?;
+
+    let start_time = qc_flag
+        .start_time
+        .or(range.first)
+        .ok_or_else(|| anyhow::anyhow!("No sensor data for task {}", task_id))?;
+    let end_time = qc_flag
+        .end_time
+        .or(range.last)
+        .ok_or_else(|| anyhow::anyhow!("No sensor data for task {}", task_id))?;
+
+    if end_time < start_time {
+        return Err(anyhow::anyhow!("Flagged range ends before it starts").into());
+    }
+
+    let now = Utc::now().naive_utc();
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO sensor_qc_flag (
+            task_id,
+            parameter,
+            start_time,
+            end_time,
+            flag,
+            reason,
+            created_by,
+            created_at
+        )
+        VALUES
+            ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id
+        "#,
+        task_id,
+        qc_flag.parameter,
+        start_time,
+        end_time,
+        qc_flag.flag,
+        qc_flag.reason,
+        session.people_id,
+        now
+    )
+    .fetch_one(&ctx.db)
+    .await?
+    .id
+    .ok_or_else(|| anyhow::anyhow!("Failed to insert QC flag"))?;
+
+    tracing::info!(
+        "QC flag {} ({:?}) for task {} by people {}",
+        id,
+        qc_flag.flag,
+        task_id,
+        session.people_id
+    );
+
+    Ok(Json(id))
+}
+
+pub async fn delete_qc_flag(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path((task_id, flag_id)): Path<(i64, i64)>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    // A suggested flag is rejected instead, so it isn't suggested again
+    sqlx::query!(
+        "UPDATE sensor_qc_flag SET rejected = TRUE WHERE id = $1 AND task_id = $2 AND suggested",
+        flag_id,
+        task_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM sensor_qc_flag WHERE id = $1 AND task_id = $2 AND NOT suggested",
+        flag_id,
+        task_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(())
+}
+
+/// A flag of the task overlapping a suggested one, with the same parameter and
+/// reason.
+struct OverlappingFlag {
+    id: i64,
+    start_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+    suggested: bool,
+    rejected: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Suggestion {
+    /// Covered by a flag or rejected before
+    Skip,
+    Insert,
+    /// Extend a suggested flag to the range, merging the other suggested flags
+    /// it overlaps into it
+    Extend {
+        id: i64,
+        start_time: NaiveDateTime,
+        end_time: NaiveDateTime,
+        merged: Vec<i64>,
+    },
+}
+
+/// How to store a suggested flag from `start_time` to `end_time`, given the
+/// overlapping flags ordered by id.
+fn suggestion(
+    overlapping: &[OverlappingFlag],
+    start_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+) -> Suggestion {
+    if overlapping
+        .iter()
+        .any(|flag| flag.rejected || (flag.start_time <= start_time && flag.end_time >= end_time))
+    {
+        return Suggestion::Skip;
+    }
+
+    let mut suggested = overlapping.iter().filter(|flag| flag.suggested);
+    let Some(first) = suggested.next() else {
+        return Suggestion::Insert;
+    };
+
+    let (mut start_time, mut end_time) = (
+        first.start_time.min(start_time),
+        first.end_time.max(end_time),
+    );
+    let mut merged = Vec::new();
+    for flag in suggested {
+        start_time = start_time.min(flag.start_time);
+        end_time = end_time.max(flag.end_time);
+        merged.push(flag.id);
+    }
+
+    Suggestion::Extend {
+        id: first.id,
+        start_time,
+        end_time,
+        merged,
+    }
+}
+
+/// Store a flag suggested by the automatic checks. A suggestion overlapping
+/// one with the same parameter and reason extends it instead, and none is made
+/// where a flag covers the range already or a suggestion was rejected.
+pub(crate) async fn insert_suggested_qc_flag(
+    db: &SqlitePool,
+    task_id: i64,
+    people_id: i64,
+    qc_flag: &NewSensorQcFlag,
+) -> Result<Option<SensorQcFlag>, Error> {
+    let (Some(start_time), Some(end_time)) = (qc_flag.start_time, qc_flag.end_time) else {
+        return Err(anyhow::anyhow!("Suggested QC flag without a range").into());
+    };
+
+    let mut tx = db.begin().await?;
+
+    let overlapping = sqlx::query_as!(
+        OverlappingFlag,
+        r#"
+        SELECT
+            id AS "id!",
+            start_time,
+            end_time,
+            suggested,
+            rejected
+        FROM
+            sensor_qc_flag
+        WHERE
+            task_id = $1
+            AND parameter IS $2
+            AND reason IS $3
+            AND start_time <= $4
+            AND end_time >= $5
+        ORDER BY
+            id
+        "#,
+        task_id,
+        qc_flag.parameter,
+        qc_flag.reason,
+        end_time,
+        start_time
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let flag = match suggestion(&overlapping, start_time, end_time) {
+        Suggestion::Skip => return Ok(None),
+        Suggestion::Insert => {
+            let now = Utc::now().naive_utc();
+
+            sqlx::query_as!(
+                SensorQcFlag,
+                r#"
+                INSERT INTO sensor_qc_flag (
+                    task_id,
+                    parameter,
+                    start_time,
+                    end_time,
+                    flag,
+                    reason,
+                    suggested,
+                    created_by,
+                    created_at
+                )
+                VALUES
+                    ($1, $2, $3, $4, $5, $6, TRUE, $7, $8)
+                RETURNING
+                    id AS "id!",
+                    task_id,
+                    parameter,
+                    start_time,
+                    end_time,
+                    flag AS "flag: QcFlag",
+                    reason,
+                    suggested,
+                    created_by,
+                    created_at
+                "#,
+                task_id,
+                qc_flag.parameter,
+                start_time,
+                end_time,
+                qc_flag.flag,
+                qc_flag.reason,
+                people_id,
+                now
+            )
+            .fetch_one(&mut *tx)
+            .await?
+        }
+        Suggestion::Extend {
+            id,
+            start_time,
+            end_time,
+            merged,
+        } => {
+            for merged_id in merged {
+                sqlx::query!("DELETE FROM sensor_qc_flag WHERE id = $1", merged_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            sqlx::query_as!(
+                SensorQcFlag,
+                r#"
+                UPDATE
+                    sensor_qc_flag
+                SET
+                    start_time = $1,
+                    end_time = $2
+                WHERE
+                    id = $3
+                RETURNING
+                    id AS "id!",
+                    task_id,
+                    parameter,
+                    start_time,
+                    end_time,
+                    flag AS "flag: QcFlag",
+                    reason,
+                    suggested,
+                    created_by,
+                    created_at
+                "#,
+                start_time,
+                end_time,
+                id
+            )
+            .fetch_one(&mut *tx)
+            .await?
+        }
+    };
+
+    tx.commit().await?;
+
+    Ok(Some(flag))
+}
+
+/// Accept a suggested flag, from then on it counts like a manual flag.
+pub async fn accept_qc_flag(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path((task_id, flag_id)): Path<(i64, i64)>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE
+            sensor_qc_flag
+        SET
+            suggested = FALSE
+        WHERE
+            id = $1 AND task_id = $2 AND suggested AND NOT rejected
+        "#,
+        flag_id,
+        task_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(
+            anyhow::anyhow!("No suggested QC flag {} for task {}", flag_id, task_id).into(),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sensor_data::time;
+
+    /// A flag as (parameter, flag, suggested).
+    type Spec<'a> = (Option<&'a str>, QcFlag, bool);
+
+    /// Flags numbered in order, covering the first two minutes.
+    fn flags(flags: &[Spec]) -> Vec<SensorQcFlag> {
+        flags
+            .iter()
+            .enumerate()
+            .map(|(idx, (parameter, flag, suggested))| SensorQcFlag {
+                id: idx as i64 + 1,
+                task_id: 1,
+                parameter: parameter.map(str::to_string),
+                start_time: time(0),
+                end_time: time(1),
+                flag: *flag,
+                reason: None,
+                suggested: *suggested,
+                created_by: 1,
+                created_at: time(0),
+            })
+            .collect()
+    }
+
+    fn marked(specs: &[Spec]) -> SensorRecord {
+        let mut records = vec![SensorRecord::at(time(0))];
+        mark_records(&mut records, flags(specs));
+        records.pop().unwrap()
+    }
+
+    #[test]
+    fn record_and_value_flags() {
+        use QcFlag::*;
+        // Flags, then the flag of ph, of do_con and the worst of the record
+        let cases: &[(&[Spec], [Option<QcFlag>; 3])] = &[
+            (&[], [None, None, None]),
+            (
+                &[(None, Suspect, false)],
+                [Some(Suspect), Some(Suspect), Some(Suspect)],
+            ),
+            (&[(Some("ph"), Bad, false)], [Some(Bad), None, Some(Bad)]),
+            // The later flag takes precedence, whether on the record or the value
+            (
+                &[(None, Bad, false), (Some("ph"), Good, false)],
+                [Some(Good), Some(Bad), Some(Bad)],
+            ),
+            (
+                &[(Some("ph"), Good, false), (None, Bad, false)],
+                [Some(Bad), Some(Bad), Some(Bad)],
+            ),
+            (
+                &[(Some("ph"), Bad, false), (None, Good, false)],
+                [Some(Good), Some(Good), Some(Good)],
+            ),
+            (
+                &[(Some("ph"), Suspect, false), (Some("ph"), Good, false)],
+                [Some(Good), None, Some(Good)],
+            ),
+        ];
+        for (specs, [ph, do_con, worst]) in cases {
+            let record = marked(specs);
+            assert_eq!(record.qc_flag_of("ph"), *ph, "{:?}", specs);
+            assert_eq!(record.qc_flag_of("do_con"), *do_con, "{:?}", specs);
+            assert_eq!(record.worst_qc_flag(), *worst, "{:?}", specs);
+        }
+    }
+
+    #[test]
+    fn suggested_and_accepted_flags() {
+        use QcFlag::*;
+        // Flags, then the flag of ph and whether the mark on ph is suggested
+        let cases: &[(&[Spec], Option<QcFlag>, Option<bool>)] = &[
+            // Suggested flags are shown, but don't count
+            (&[(Some("ph"), Bad, true)], None, Some(true)),
+            // and don't replace an accepted flag
+            (
+                &[(Some("ph"), Suspect, false), (Some("ph"), Bad, true)],
+                Some(Suspect),
+                Some(false),
+            ),
+            // An accepted flag replaces a suggested one
+            (
+                &[(Some("ph"), Bad, true), (Some("ph"), Good, false)],
+                Some(Good),
+                Some(false),
+            ),
+            // A suggested record flag doesn't hide an accepted value flag
+            (
+                &[(Some("ph"), Good, false), (None, Bad, true)],
+                Some(Good),
+                Some(false),
+            ),
+            (&[(None, Bad, true)], None, None),
+        ];
+        for (specs, ph, suggested) in cases {
+            let record = marked(specs);
+            assert_eq!(record.qc_flag_of("ph"), *ph, "{:?}", specs);
+            assert_eq!(
+                record.qc_flags.get("ph").map(|mark| mark.suggested),
+                *suggested,
+                "{:?}",
+                specs
+            );
+            assert_eq!(record.worst_qc_flag(), *ph, "{:?}", specs);
+        }
+    }
+
+    #[test]
+    fn flags_cover_their_range() {
+        let mut records: Vec<SensorRecord> = (0..4)
+            .map(|minute| SensorRecord::at(time(minute)))
+            .collect();
+        mark_records(&mut records, flags(&[(Some("ph"), QcFlag::Bad, false)]));
+        let flagged: Vec<Option<QcFlag>> = records
+            .iter()
+            .map(|record| record.qc_flag_of("ph"))
+            .collect();
+        assert_eq!(
+            flagged,
+            vec![Some(QcFlag::Bad), Some(QcFlag::Bad), None, None]
+        );
+    }
+
+    #[test]
+    fn suggestions_of_overlapping_flags() {
+        // Flags as (minutes, suggested, rejected), overlapping the suggestion
+        // from minute 2 to 4, then how it is stored
+        let flag = |id, (start, end), suggested, rejected| OverlappingFlag {
+            id,
+            start_time: time(start),
+            end_time: time(end),
+            suggested,
+            rejected,
+        };
+        let cases = [
+            (vec![], Suggestion::Insert),
+            // Covered by an accepted or suggested flag
+            (vec![flag(1, (0, 5), false, false)], Suggestion::Skip),
+            (vec![flag(1, (2, 4), true, false)], Suggestion::Skip),
+            // Rejected before
+            (vec![flag(1, (3, 6), true, true)], Suggestion::Skip),
+            // An accepted flag isn't extended
+            (vec![flag(1, (3, 6), false, false)], Suggestion::Insert),
+            (
+                vec![flag(1, (3, 6), true, false)],
+                Suggestion::Extend {
+                    id: 1,
+                    start_time: time(2),
+                    end_time: time(6),
+                    merged: vec![],
+                },
+            ),
+            (
+                vec![
+                    flag(1, (0, 2), true, false),
+                    flag(2, (1, 3), false, false),
+                    flag(3, (4, 6), true, false),
+                ],
+                Suggestion::Extend {
+                    id: 1,
+                    start_time: time(0),
+                    end_time: time(6),
+                    merged: vec![3],
+                },
+            ),
+        ];
+        for (overlapping, expected) in cases {
+            assert_eq!(suggestion(&overlapping, time(2), time(4)), expected);
+        }
+    }
+}
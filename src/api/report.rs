@@ -0,0 +1,420 @@
+//! Reports rendered from templates stored in the database, so their layout can
+//! be changed without a rebuild. Templates use the MiniJinja (Jinja2) syntax
+//! and are rendered with a [`ReportContext`].
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use axum::extract::{Json, Path};
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::Extension;
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use minijinja::value::Value;
+use minijinja::{AutoEscape, Environment, ErrorKind};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use super::auth::{Role, Session};
+use super::chart::default_chart_svg;
+use super::comment::CommentFormat;
+use super::export::{fetch_task_export, TaskExport};
+use super::field_parameters::{compute_field_parameters, FieldParameters};
+use super::sensor_data::{SensorRecord, PARAMETERS};
+use super::task::{fetch_samples, Sample};
+use super::well::{fetch_well, Well};
+use super::{ApiContext, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum ReportFormat {
+    Html,
+    Markdown,
+    Csv,
+}
+
+impl ReportFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ReportFormat::Html => "text/html; charset=utf-8",
+            ReportFormat::Markdown => "text/markdown; charset=utf-8",
+            ReportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ReportFormat::Html => "html",
+            ReportFormat::Markdown => "md",
+            ReportFormat::Csv => "csv",
+        }
+    }
+
+    /// Comments are stored as HTML and rendered to match the report.
+    fn comment_format(self) -> CommentFormat {
+        match self {
+            ReportFormat::Html => CommentFormat::Html,
+            ReportFormat::Markdown => CommentFormat::Markdown,
+            ReportFormat::Csv => CommentFormat::Text,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ReportTemplate {
+    name: String,
+    format: ReportFormat,
+    description: Option<String>,
+    body: String,
+    updated_by: Option<i64>,
+    #[serde(with = "super::serde::iso8601")]
+    updated_at: NaiveDateTime,
+}
+
+async fn fetch_report_template(db: &SqlitePool, name: &str) -> Result<ReportTemplate, Error> {
+    let template = sqlx::query_as!(
+        ReportTemplate,
+        r#"
+        SELECT
+            name AS "name!",
+            format AS "format: ReportFormat",
+            description,
+            body,
+            updated_by,
+            updated_at
+        FROM
+            report_template
+        WHERE
+            name = $1
+        "#,
+        name
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("No report template {:?}", name))?;
+
+    Ok(template)
+}
+
+pub async fn list_report_templates(
+    ctx: Extension<ApiContext>,
+) -> Result<Json<Vec<ReportTemplate>>, Error> {
+    let templates = sqlx::query_as!(
+        ReportTemplate,
+        r#"
+        SELECT
+            name AS "name!",
+            format AS "format: ReportFormat",
+            description,
+            body,
+            updated_by,
+            updated_at
+        FROM
+            report_template
+        ORDER BY
+            name
+        "#
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(templates))
+}
+
+/// Template environment of a report format, with the filters
+/// - `localtime(format="%Y-%m-%d %H:%M")`: a timestamp in the project time zone
+/// - `csv`: a value quoted as a CSV field where needed
+fn environment(format: ReportFormat, tz: Tz) -> Environment<'static> {
+    let mut env = Environment::new();
+
+    env.set_auto_escape_callback(move |_| match format {
+        ReportFormat::Html => AutoEscape::Html,
+        ReportFormat::Markdown | ReportFormat::Csv => AutoEscape::None,
+    });
+
+    env.add_filter(
+        "localtime",
+        move |value: Option<String>, format: Option<String>| -> Result<String, minijinja::Error> {
+            let Some(value) = value else {
+                return Ok(String::new());
+            };
+            let datetime = super::serde::iso8601::parse(&value).map_err(|e| {
+                minijinja::Error::new(ErrorKind::InvalidOperation, format!("{}: {:?}", e, value))
+            })?;
+            let format = format.as_deref().unwrap_or("%Y-%m-%d %H:%M");
+            let mut local = String::new();
+            write!(local, "{}", tz.from_utc_datetime(&datetime).format(format)).map_err(|_| {
+                minijinja::Error::new(ErrorKind::InvalidOperation, "invalid time format")
+            })?;
+            Ok(local)
+        },
+    );
+
+    env.add_filter("csv", |value: Value| -> String {
+        if value.is_none() || value.is_undefined() {
+            return String::new();
+        }
+        let value = value.to_string();
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value
+        }
+    });
+
+    env
+}
+
+fn validate_template(format: ReportFormat, body: &str) -> Result<(), Error> {
+    environment(format, Tz::UTC)
+        .template_from_str(body)
+        .map_err(|e| anyhow::anyhow!("Invalid template: {}", e))?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct NewReportTemplate {
+    name: String,
+    format: ReportFormat,
+    #[serde(default)]
+    description: Option<String>,
+    body: String,
+}
+
+pub async fn insert_report_template(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Json(template): Json<NewReportTemplate>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    let valid_name = !template.name.is_empty()
+        && template
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid_name {
+        return Err(anyhow::anyhow!("Invalid template name: {:?}", template.name).into());
+    }
+
+    validate_template(template.format, &template.body)?;
+
+    let now = Utc::now().naive_utc();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            report_template (name, format, description, body, updated_by, updated_at)
+        VALUES
+            ($1, $2, $3, $4, $5, $6)
+        "#,
+        template.name,
+        template.format,
+        template.description,
+        template.body,
+        session.people_id,
+        now
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn update_report_template(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(name): Path<String>,
+    Json(update): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    for (key, value) in update {
+        match key.as_str() {
+            "format" => {
+                let value: ReportFormat = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE report_template SET format = $1 WHERE name = $2",
+                    value,
+                    name
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "description" => {
+                let value: Option<String> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE report_template SET description = $1 WHERE name = $2",
+                    value,
+                    name
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "body" => {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid value for body: {:?}", value))?;
+                sqlx::query!(
+                    "UPDATE report_template SET body = $1 WHERE name = $2",
+                    value,
+                    name
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
+            }
+        }
+    }
+
+    let template = sqlx::query!(
+        r#"SELECT format AS "format: ReportFormat", body FROM report_template WHERE name = $1"#,
+        name
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| anyhow::anyhow!("No report template {:?}", name))?;
+    validate_template(template.format, &template.body)?;
+
+    let now = Utc::now().naive_utc();
+    sqlx::query!(
+        "UPDATE report_template SET updated_by = $1, updated_at = $2 WHERE name = $3",
+        session.people_id,
+        now,
+        name
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn delete_report_template(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(name): Path<String>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    sqlx::query!("DELETE FROM report_template WHERE name = $1", name)
+        .execute(&ctx.db)
+        .await?;
+
+    Ok(())
+}
+
+/// Statistics of a parameter over the sensor data of a task.
+#[derive(Serialize)]
+pub struct ParameterStatistics {
+    parameter: &'static str,
+    count: usize,
+    min: f64,
+    max: f64,
+    mean: f64,
+    /// First and last logged values
+    first: f64,
+    last: f64,
+}
+
+fn statistics(records: &[SensorRecord]) -> Vec<ParameterStatistics> {
+    PARAMETERS
+        .iter()
+        .filter_map(|parameter| {
+            let values: Vec<f64> = records
+                .iter()
+                .filter_map(|record| record.parameter(parameter))
+                .collect();
+            Some(ParameterStatistics {
+                parameter,
+                count: values.len(),
+                min: values.iter().copied().reduce(f64::min)?,
+                max: values.iter().copied().reduce(f64::max)?,
+                mean: values.iter().sum::<f64>() / values.len() as f64,
+                first: *values.first()?,
+                last: *values.last()?,
+            })
+        })
+        .collect()
+}
+
+/// Data a report template is rendered with. Timestamps are ISO 8601 strings in
+/// UTC, use the `localtime` filter to print them in the project time zone.
+#[derive(Serialize)]
+pub struct ReportContext {
+    /// The task as in the task export, with its task info entries and their
+    /// `sampled_by` and `minuted_by` names and the sensor data
+    task: TaskExport,
+    well: Well,
+    /// The sample set with the names of the sample types
+    samples: Vec<Sample>,
+    /// Per parameter statistics over the whole sensor data
+    statistics: Vec<ParameterStatistics>,
+    /// Final field parameters at the time of sampling
+    field_parameters: FieldParameters,
+    /// SVG chart of the parameters with stabilization criteria, as
+    /// `GET /api/task/{task_id}/chart.svg`
+    chart: String,
+    time_zone: &'static str,
+    #[serde(with = "super::serde::iso8601")]
+    generated_at: NaiveDateTime,
+}
+
+/// Render a report template for a task.
+pub async fn get_report(
+    ctx: Extension<ApiContext>,
+    Path((task_id, name)): Path<(i64, String)>,
+) -> Result<impl IntoResponse, Error> {
+    let template = fetch_report_template(&ctx.db, &name).await?;
+    let tz = ctx.settings.time_zone;
+
+    let task = fetch_task_export(&ctx.db, task_id, template.format.comment_format()).await?;
+    let field_parameters = compute_field_parameters(&ctx.db, task_id, None).await?;
+    let context = ReportContext {
+        well: fetch_well(&ctx.db, task.well_id).await?,
+        samples: fetch_samples(&ctx.db, task_id).await?,
+        statistics: statistics(&task.sensor_data),
+        chart: default_chart_svg(
+            tz,
+            task_id,
+            &task.sensor_data,
+            field_parameters.sampling_time,
+        ),
+        field_parameters,
+        time_zone: tz.name(),
+        generated_at: Utc::now().naive_utc(),
+        task,
+    };
+
+    let report = environment(template.format, tz)
+        .render_str(&template.body, &context)
+        .map_err(|e| anyhow::anyhow!("Failed to render report {:?}: {}", name, e))?;
+
+    let file_name = format!(
+        "{}_{}.{}",
+        context.task.serial.as_deref().unwrap_or("task"),
+        name,
+        template.format.extension()
+    );
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                template.format.content_type().to_string(),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"{}\"", file_name.replace('"', "")),
+            ),
+        ],
+        report,
+    ))
+}
@@ -0,0 +1,164 @@
+use axum::extract::{Json, Query};
+use axum::Extension;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+use super::comment::CommentFormat;
+use super::format::escape_xml;
+use super::{ApiContext, Error};
+
+const DEFAULT_LIMIT: i64 = 50;
+
+/// Most hits returned, larger limits are clamped.
+const MAX_LIMIT: i64 = 500;
+
+/// Marks around the hits in snippets, replaced after escaping the text.
+const MARK_START: char = '\u{2}';
+const MARK_END: char = '\u{3}';
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    #[serde(default)]
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct SearchHit {
+    /// Table the note comes from: `task_info`, `well`, `pump` or `sample_type`
+    source: String,
+    field: String,
+    source_id: i64,
+    task_id: Option<i64>,
+    task_info_id: Option<i64>,
+    /// Matched text, HTML escaped, with the hits wrapped in `<mark>`
+    snippet: String,
+    rank: f64,
+}
+
+/// Turn free text into an FTS5 query, every term is quoted so that user input
+/// never reaches the FTS5 query syntax.
+fn match_expr(q: &str) -> Option<String> {
+    let terms: Vec<String> = q
+        .split_whitespace()
+        .map(|term| term.replace('"', ""))
+        .filter(|term| !term.is_empty())
+        .map(|term| format!("\"{}\"", term))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+/// Index the notes queued by the triggers by their text rendering, so that
+/// markup never matches and snippets never cut through a tag. Called in the
+/// transaction of every write to a note, and once on start for notes written
+/// outside the API.
+pub async fn index_pending_notes(conn: &mut SqliteConnection) -> Result<(), Error> {
+    let pending = sqlx::query!(
+        r#"
+        SELECT
+            rowid AS "rowid!: i64",
+            body,
+            source,
+            field,
+            source_id,
+            task_id
+        FROM
+            note_search_pending
+        "#
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    for note in pending {
+        let body = CommentFormat::Text
+            .render(&note.body)
+            .replace([MARK_START, MARK_END], "");
+        sqlx::query!(
+            r#"
+            INSERT INTO
+                note_search (body, source, field, source_id, task_id)
+            VALUES
+                ($1, $2, $3, $4, $5)
+            "#,
+            body,
+            note.source,
+            note.field,
+            note.source_id,
+            note.task_id
+        )
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM note_search_pending WHERE rowid = $1",
+            note.rowid
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Escape a snippet for HTML and turn the hit marks into `<mark>` tags.
+fn snippet_html(snippet: &str) -> String {
+    escape_xml(snippet)
+        .replace(MARK_START, "<mark>")
+        .replace(MARK_END, "</mark>")
+}
+
+pub async fn search_notes(
+    ctx: Extension<ApiContext>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<SearchHit>>, Error> {
+    let Some(expr) = match_expr(&query.q) else {
+        return Ok(Json(Vec::new()));
+    };
+    let limit = match query.limit {
+        Some(limit) if limit < 1 => {
+            return Err(anyhow::anyhow!("Invalid limit: {}", limit).into());
+        }
+        limit => limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT),
+    };
+
+    let hits = sqlx::query!(
+        r#"
+        SELECT
+            source AS "source!: String",
+            field AS "field!: String",
+            source_id AS "source_id!: i64",
+            task_id AS "task_id: i64",
+            snippet(note_search, 0, char(2), char(3), '…', 16) AS "snippet!: String",
+            rank AS "rank!: f64"
+        FROM
+            note_search
+        WHERE
+            note_search MATCH $1
+        ORDER BY
+            rank
+        LIMIT $2
+        "#,
+        expr,
+        limit
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(
+        hits.into_iter()
+            .map(|hit| SearchHit {
+                task_info_id: (hit.source == "task_info").then_some(hit.source_id),
+                source: hit.source,
+                field: hit.field,
+                source_id: hit.source_id,
+                task_id: hit.task_id,
+                snippet: snippet_html(&hit.snippet),
+                rank: hit.rank,
+            })
+            .collect(),
+    ))
+}
@@ -1,27 +1,46 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Cursor;
 
-use axum::extract::{Extension, Multipart, Path};
-use axum::Json;
-use chrono::NaiveDateTime;
 use aqua_troll_log_reader::{AquaTrollLogError, AquaTrollLogReader};
+use axum::extract::{Extension, Multipart, Path, Query};
+use axum::Json;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
 
+use super::anomaly::{check_ingest, IngestReport};
+use super::auth::{Role, Session};
+use super::correction::apply_corrections;
+use super::derived::{derive, DeriveMode};
+use super::finalize::ensure_unlocked;
+use super::qc::{apply_qc_flags, QcFilter, QcFlag, QcMark};
+use super::water_level::fetch_water_level_readings;
 use super::{ApiContext, Error};
 
-pub async fn insitu_log_handler(mut multipart: Multipart) -> Result<Json<AquaTrollLogReader>, Error> {
+/// Parse an Aqua TROLL log, the format is chosen by the file extension.
+pub(crate) fn read_log(file_name: &str, data: &[u8]) -> Result<AquaTrollLogReader, Error> {
+    let ext = file_name.rsplit('.').next().unwrap_or("");
+
+    let mut reader = Cursor::new(data);
+
+    let log = match ext {
+        "csv" => AquaTrollLogReader::from_csv(&mut reader)?,
+        "txt" => AquaTrollLogReader::from_txt(&mut reader)?,
+        "zip" => AquaTrollLogReader::from_zipped_html(&mut reader)?,
+        _ => return Err(AquaTrollLogError::InvalidData)?,
+    };
+
+    Ok(log)
+}
+
+pub async fn insitu_log_handler(
+    mut multipart: Multipart,
+) -> Result<Json<AquaTrollLogReader>, Error> {
     let log = if let Some(field) = multipart.next_field().await.unwrap() {
         let file_name = field.file_name().unwrap_or("").to_string();
         let data = field.bytes().await.unwrap();
-        let ext = file_name.split('.').last().unwrap_or("");
-
-        let mut reader = Cursor::new(data);
 
-        match ext {
-            "csv" => AquaTrollLogReader::from_csv(&mut reader)?,
-            "txt" => AquaTrollLogReader::from_txt(&mut reader)?,
-            "zip" => AquaTrollLogReader::from_zipped_html(&mut reader)?,
-            _ => return Err(AquaTrollLogError::InvalidData)?,
-        }
+        read_log(&file_name, &data)?
     } else {
         return Err(AquaTrollLogError::InvalidData)?;
     };
@@ -32,7 +51,7 @@ pub async fn insitu_log_handler(mut multipart: Multipart) -> Result<Json<AquaTro
 pub async fn get_latest_timestamp(
     ctx: Extension<ApiContext>,
     Path(task_id): Path<i64>,
-) -> Result<Json<Option<NaiveDateTime>>, Error> {
+) -> Result<Json<Option<DateTime<Utc>>>, Error> {
     let record = sqlx::query!(
         r#"
         SELECT datetime
@@ -46,13 +65,176 @@ pub async fn get_latest_timestamp(
     .fetch_optional(&ctx.db)
     .await?;
 
-    Ok(Json(record.map(|r| r.datetime)))
+    Ok(Json(record.map(|r| r.datetime.and_utc())))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SensorRecord {
-    task_id: i64,
+    pub(crate) task_id: i64,
     #[serde(with = "super::serde::iso8601")]
+    pub(crate) datetime: NaiveDateTime,
+    pub(crate) cndct: f64,
+    pub(crate) temp_internal: f64,
+    pub(crate) spcndct: f64,
+    pub(crate) sa: Option<f64>,
+    pub(crate) resis: Option<f64>,
+    pub(crate) wtr_d: Option<f64>,
+    pub(crate) tds: Option<f64>,
+    pub(crate) turbidity: Option<f64>,
+    pub(crate) ph: f64,
+    pub(crate) ph_mv: Option<f64>,
+    pub(crate) orp: f64,
+    pub(crate) do_con: f64,
+    pub(crate) do_sat: f64,
+    pub(crate) ppo2: Option<f64>,
+    pub(crate) temp_sensor: Option<f64>,
+    pub(crate) v: Option<f64>,
+    pub(crate) batt: Option<i64>,
+    pub(crate) pres_baro: Option<f64>,
+    pub(crate) pres: Option<f64>,
+    pub(crate) depth: Option<f64>,
+    /// Barometric compensation results, set by the compensation endpoint
+    #[serde(default, skip_deserializing)]
+    pub(crate) water_column: Option<f64>,
+    #[serde(default, skip_deserializing)]
+    pub(crate) depth_to_water: Option<f64>,
+    #[serde(default, skip_deserializing)]
+    pub(crate) water_elevation: Option<f64>,
+    /// Latest manual water level reading at this time, not stored with the record
+    #[serde(default, skip_deserializing)]
+    pub(crate) water_level: Option<f64>,
+    /// Logged values of the parameters which were corrected or recomputed
+    #[serde(
+        default,
+        skip_deserializing,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub(crate) raw: BTreeMap<String, f64>,
+    /// Parameters which were computed rather than measured
+    #[serde(
+        default,
+        skip_deserializing,
+        skip_serializing_if = "BTreeSet::is_empty"
+    )]
+    pub(crate) computed: BTreeSet<&'static str>,
+    /// QC flag of the whole record
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub(crate) qc_flag: Option<QcMark>,
+    /// QC flags of single parameters
+    #[serde(
+        default,
+        skip_deserializing,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub(crate) qc_flags: BTreeMap<String, QcMark>,
+}
+
+/// Parameters which can be corrected.
+pub const PARAMETERS: &[&str] = &[
+    "cndct",
+    "temp_internal",
+    "spcndct",
+    "sa",
+    "resis",
+    "wtr_d",
+    "tds",
+    "turbidity",
+    "ph",
+    "ph_mv",
+    "orp",
+    "do_con",
+    "do_sat",
+    "ppo2",
+    "temp_sensor",
+    "v",
+    "pres_baro",
+    "pres",
+    "depth",
+];
+
+impl SensorRecord {
+    pub(crate) fn parameter(&self, parameter: &str) -> Option<f64> {
+        match parameter {
+            "cndct" => Some(self.cndct),
+            "temp_internal" => Some(self.temp_internal),
+            "spcndct" => Some(self.spcndct),
+            "sa" => self.sa,
+            "resis" => self.resis,
+            "wtr_d" => self.wtr_d,
+            "tds" => self.tds,
+            "turbidity" => self.turbidity,
+            "ph" => Some(self.ph),
+            "ph_mv" => self.ph_mv,
+            "orp" => Some(self.orp),
+            "do_con" => Some(self.do_con),
+            "do_sat" => Some(self.do_sat),
+            "ppo2" => self.ppo2,
+            "temp_sensor" => self.temp_sensor,
+            "v" => self.v,
+            "pres_baro" => self.pres_baro,
+            "pres" => self.pres,
+            "depth" => self.depth,
+            _ => None,
+        }
+    }
+
+    pub(crate) fn parameter_mut(&mut self, parameter: &str) -> Option<&mut f64> {
+        match parameter {
+            "cndct" => Some(&mut self.cndct),
+            "temp_internal" => Some(&mut self.temp_internal),
+            "spcndct" => Some(&mut self.spcndct),
+            "sa" => self.sa.as_mut(),
+            "resis" => self.resis.as_mut(),
+            "wtr_d" => self.wtr_d.as_mut(),
+            "tds" => self.tds.as_mut(),
+            "turbidity" => self.turbidity.as_mut(),
+            "ph" => Some(&mut self.ph),
+            "ph_mv" => self.ph_mv.as_mut(),
+            "orp" => Some(&mut self.orp),
+            "do_con" => Some(&mut self.do_con),
+            "do_sat" => Some(&mut self.do_sat),
+            "ppo2" => self.ppo2.as_mut(),
+            "temp_sensor" => self.temp_sensor.as_mut(),
+            "v" => self.v.as_mut(),
+            "pres_baro" => self.pres_baro.as_mut(),
+            "pres" => self.pres.as_mut(),
+            "depth" => self.depth.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// The worst accepted QC flag of the record or any of its values, each
+    /// value flag resolved as in `qc_flag_of`.
+    pub(crate) fn worst_qc_flag(&self) -> Option<QcFlag> {
+        self.accepted_qc_flag(None)
+            .into_iter()
+            .chain(
+                self.qc_flags
+                    .keys()
+                    .filter_map(|parameter| self.qc_flag_of(parameter)),
+            )
+            .max()
+    }
+
+    /// The accepted QC flag of a value. Flags on the record and on the value
+    /// are taken together, the latest one takes precedence.
+    pub(crate) fn qc_flag_of(&self, parameter: &str) -> Option<QcFlag> {
+        self.accepted_qc_flag(Some(parameter))
+    }
+
+    fn accepted_qc_flag(&self, parameter: Option<&str>) -> Option<QcFlag> {
+        self.qc_flag
+            .iter()
+            .chain(parameter.and_then(|parameter| self.qc_flags.get(parameter)))
+            .filter(|mark| !mark.suggested)
+            .max_by_key(|mark| mark.flag_id)
+            .map(|mark| mark.flag)
+    }
+}
+
+/// A sensor record as stored, before corrections, derived values and flags.
+struct SensorRow {
+    task_id: i64,
     datetime: NaiveDateTime,
     cndct: f64,
     temp_internal: f64,
@@ -74,36 +256,178 @@ pub struct SensorRecord {
     pres_baro: Option<f64>,
     pres: Option<f64>,
     depth: Option<f64>,
+    water_column: Option<f64>,
+    depth_to_water: Option<f64>,
+    water_elevation: Option<f64>,
 }
 
-pub async fn get_sensor_data(
-    ctx: Extension<ApiContext>,
-    Path(task_id): Path<i64>,
-) -> Result<Json<Vec<SensorRecord>>, Error> {
-    let records = sqlx::query_as!(
-        SensorRecord,
+impl From<SensorRow> for SensorRecord {
+    fn from(row: SensorRow) -> Self {
+        SensorRecord {
+            task_id: row.task_id,
+            datetime: row.datetime,
+            cndct: row.cndct,
+            temp_internal: row.temp_internal,
+            spcndct: row.spcndct,
+            sa: row.sa,
+            resis: row.resis,
+            wtr_d: row.wtr_d,
+            tds: row.tds,
+            turbidity: row.turbidity,
+            ph: row.ph,
+            ph_mv: row.ph_mv,
+            orp: row.orp,
+            do_con: row.do_con,
+            do_sat: row.do_sat,
+            ppo2: row.ppo2,
+            temp_sensor: row.temp_sensor,
+            v: row.v,
+            batt: row.batt,
+            pres_baro: row.pres_baro,
+            pres: row.pres,
+            depth: row.depth,
+            water_column: row.water_column,
+            depth_to_water: row.depth_to_water,
+            water_elevation: row.water_elevation,
+            water_level: None,
+            raw: BTreeMap::new(),
+            computed: BTreeSet::new(),
+            qc_flag: None,
+            qc_flags: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl SensorRecord {
+    /// A record with the required values at zero and no optional ones.
+    pub(crate) fn at(datetime: NaiveDateTime) -> Self {
+        SensorRecord::from(SensorRow {
+            task_id: 1,
+            datetime,
+            cndct: 0.0,
+            temp_internal: 0.0,
+            spcndct: 0.0,
+            sa: None,
+            resis: None,
+            wtr_d: None,
+            tds: None,
+            turbidity: None,
+            ph: 0.0,
+            ph_mv: None,
+            orp: 0.0,
+            do_con: 0.0,
+            do_sat: 0.0,
+            ppo2: None,
+            temp_sensor: None,
+            v: None,
+            batt: None,
+            pres_baro: None,
+            pres: None,
+            depth: None,
+            water_column: None,
+            depth_to_water: None,
+            water_elevation: None,
+        })
+    }
+}
+
+/// A time in tests, minutes after the start of a day of sampling.
+#[cfg(test)]
+pub(crate) fn time(minute: i64) -> NaiveDateTime {
+    chrono::NaiveDate::from_ymd_opt(2026, 5, 1)
+        .unwrap()
+        .and_hms_opt(8, 0, 0)
+        .unwrap()
+        + chrono::Duration::minutes(minute)
+}
+
+pub(crate) async fn fetch_sensor_data(
+    db: &SqlitePool,
+    task_id: i64,
+    mode: DeriveMode,
+) -> Result<Vec<SensorRecord>, Error> {
+    let mut records: Vec<SensorRecord> = sqlx::query_as!(
+        SensorRow,
         r#"
         SELECT
             task_id, datetime, cndct, temp_internal, spcndct, sa, resis,
             wtr_d, tds, turbidity, ph, ph_mv, orp, do_con, do_sat,
-            ppo2, temp_sensor, v, batt, pres_baro, pres, depth
+            ppo2, temp_sensor, v, batt, pres_baro, pres, depth,
+            water_column, depth_to_water, water_elevation
         FROM sensor_data
         WHERE task_id = $1
         ORDER BY datetime ASC
         "#,
         task_id
     )
-    .fetch_all(&ctx.db)
-    .await?;
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(SensorRecord::from)
+    .collect();
+
+    apply_corrections(db, task_id, &mut records).await?;
+    derive(&mut records, mode);
+    apply_qc_flags(db, task_id, &mut records).await?;
+
+    with_water_levels(db, task_id, records).await
+}
+
+/// Put the water level readings on the time axis of the sensor data, each record
+/// carries the last reading taken at or before its timestamp.
+async fn with_water_levels(
+    db: &SqlitePool,
+    task_id: i64,
+    mut records: Vec<SensorRecord>,
+) -> Result<Vec<SensorRecord>, Error> {
+    let readings = fetch_water_level_readings(db, task_id).await?;
+    let mut readings = readings.iter().peekable();
+    let mut level = None;
+
+    for record in records.iter_mut() {
+        while let Some(reading) = readings.next_if(|reading| reading.measured_at <= record.datetime)
+        {
+            level = Some(reading.depth_to_water);
+        }
+        record.water_level = level;
+    }
+
+    Ok(records)
+}
 
+#[derive(Deserialize)]
+pub struct SensorDataQuery {
+    #[serde(default)]
+    derive: DeriveMode,
+    #[serde(default)]
+    qc: QcFilter,
+}
+
+pub async fn get_sensor_data(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+    Query(query): Query<SensorDataQuery>,
+) -> Result<Json<Vec<SensorRecord>>, Error> {
+    let mut records = fetch_sensor_data(&ctx.db, task_id, query.derive).await?;
+    records.retain(|record| query.qc.keeps(record));
     Ok(Json(records))
 }
 
 pub async fn insert_sensor_data(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path(task_id): Path<i64>,
     Json(sensor_data): Json<Vec<SensorRecord>>,
-) -> Result<(), Error> {
+) -> Result<Json<Option<IngestReport>>, Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    let inserted = sensor_data.len();
+    let first = sensor_data.iter().map(|record| record.datetime).min();
+    let last = sensor_data.iter().map(|record| record.datetime).max();
+
     let mut tx = ctx.db.begin().await?;
 
     for sensor_record in sensor_data {
@@ -151,13 +475,25 @@ pub async fn insert_sensor_data(
 
     tx.commit().await?;
 
-    Ok(())
+    let report = match (first, last) {
+        (Some(first), Some(last)) => {
+            Some(check_ingest(&ctx.db, task_id, session.people_id, inserted, first, last).await?)
+        }
+        _ => None,
+    };
+
+    Ok(Json(report))
 }
 
 pub async fn clear_sensor_data(
     ctx: Extension<ApiContext>,
-    Path(task_id): Path<u32>,
+    session: Session,
+    Path(task_id): Path<i64>,
 ) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
     sqlx::query!("DELETE FROM sensor_data WHERE task_id = $1", task_id)
         .execute(&ctx.db)
         .await?;
@@ -1,18 +1,22 @@
+/// Timestamps are stored as naive UTC and exchanged as ISO 8601 with an offset.
+/// Input in any offset is converted to UTC.
 pub mod iso8601 {
-    use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+    use chrono::{DateTime, FixedOffset, NaiveDateTime};
     use serde::{self, Deserialize, Deserializer, Serializer};
 
     const FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%z";
 
+    pub(crate) fn parse(s: &str) -> Result<NaiveDateTime, chrono::ParseError> {
+        DateTime::<FixedOffset>::parse_from_str(s, FORMAT)
+            .or_else(|_| DateTime::parse_from_rfc3339(s))
+            .map(|datetime| datetime.naive_utc())
+    }
+
     pub fn serialize<S>(date: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let s = TimeZone::from_local_datetime(&Local, date)
-            .single()
-            .ok_or_else(|| serde::ser::Error::custom("Invalid datetime"))?
-            .format(FORMAT)
-            .to_string();
+        let s = date.and_utc().format(FORMAT).to_string();
         serializer.serialize_str(&s)
     }
 
@@ -21,18 +25,14 @@ pub mod iso8601 {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        Ok(DateTime::parse_from_str(&s, FORMAT)
-            .map_err(serde::de::Error::custom)?
-            .naive_local())
+        parse(&s).map_err(serde::de::Error::custom)
     }
 }
 
 pub mod iso8601_option {
-    use chrono::{DateTime, NaiveDateTime};
+    use chrono::NaiveDateTime;
     use serde::{self, Deserialize, Deserializer, Serializer};
 
-    const FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%z";
-
     pub fn serialize<S>(date: &Option<NaiveDateTime>, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -50,9 +50,7 @@ pub mod iso8601_option {
         let s = Option::<String>::deserialize(deserializer)?;
         if let Some(s) = s {
             Ok(Some(
-                DateTime::parse_from_str(&s, FORMAT)
-                    .map_err(serde::de::Error::custom)?
-                    .naive_local(),
+                super::iso8601::parse(&s).map_err(serde::de::Error::custom)?,
             ))
         } else {
             Ok(None)
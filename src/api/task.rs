@@ -1,34 +1,13 @@
 use axum::extract::{Json, Path};
 use axum::Extension;
-use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
 
+use super::auth::{Role, Session};
+use super::finalize::ensure_unlocked;
+use super::task_status::{record_status, TaskStatus};
 use super::{ApiContext, Error};
 
-#[derive(Deserialize, Serialize)]
-pub struct TaskSummary {
-    id: i64,
-    done: Option<bool>,
-    serial: Option<String>,
-    well_id: Option<i64>,
-    depth: Option<String>,
-    sample_set: Option<String>,
-    sampling_time: Option<NaiveDateTime>,
-    comment: Option<String>,
-}
-
-pub async fn list_task_summaries(
-    ctx: Extension<ApiContext>,
-) -> Result<Json<Vec<TaskSummary>>, Error> {
-    let task_summaries = sqlx::query_as!(
-        TaskSummary,
-        "SELECT id, done, serial, well_id, depth, sample_set, sampling_time, comment FROM task_summary"
-    )
-    .fetch_all(&ctx.db)
-    .await?;
-    Ok(Json(task_summaries))
-}
-
 #[derive(Deserialize)]
 pub struct NewTask {
     well_id: i64,
@@ -37,18 +16,27 @@ pub struct NewTask {
 
 pub async fn insert_task(
     ctx: Extension<ApiContext>,
+    session: Session,
     Json(new_task): Json<NewTask>,
 ) -> Result<Json<i64>, Error> {
+    session.require(Role::FieldTech)?;
+
+    let mut tx = ctx.db.begin().await?;
+
     let id = sqlx::query!(
         "INSERT INTO task (well_id, depth) VALUES ($1, $2) RETURNING id",
         new_task.well_id,
         new_task.depth,
     )
-    .fetch_one(&ctx.db)
+    .fetch_one(&mut *tx)
     .await?;
 
     match id.id {
-        Some(id) => Ok(Json(id)),
+        Some(id) => {
+            record_status(&mut tx, id, TaskStatus::Planned, session.people_id, None).await?;
+            tx.commit().await?;
+            Ok(Json(id))
+        }
         None => {
             tracing::error!("Failed to insert task");
             Err(anyhow::anyhow!("Failed to insert task").into())
@@ -58,8 +46,13 @@ pub async fn insert_task(
 
 pub async fn delete_task(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path(task_id): Path<i64>,
 ) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
     sqlx::query!("DELETE FROM task WHERE id = $1", task_id)
         .execute(&ctx.db)
         .await?;
@@ -68,9 +61,14 @@ pub async fn delete_task(
 
 pub async fn update_task(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path(task_id): Path<i64>,
     Json(update): Json<serde_json::Value>,
 ) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
     tracing::debug!("Update task: {:?}", update);
 
     match update {
@@ -80,12 +78,12 @@ pub async fn update_task(
             for (key, value) in data {
                 match key.as_str() {
                     "done" => {
-                        let value = value.as_bool().ok_or_else(|| {
-                            anyhow::anyhow!("Invalid value for done: {:?}", value)
-                        })?;
-                        sqlx::query!("UPDATE task SET done = $1 WHERE id = $2", value, task_id)
-                            .execute(&mut *tx)
-                            .await?;
+                        // Kept in step with the status, changed with its transitions
+                        return Err(anyhow::anyhow!(
+                            "done follows the status, use POST /api/task/{}/status",
+                            task_id
+                        )
+                        .into());
                     }
                     "serial" => {
                         let value = value.as_str().ok_or_else(|| {
@@ -111,6 +109,32 @@ pub async fn update_task(
                             .execute(&mut *tx)
                             .await?;
                     }
+                    "campaign_id" => {
+                        let value = if value.is_null() {
+                            None
+                        } else {
+                            Some(value.as_i64().ok_or_else(|| {
+                                anyhow::anyhow!("Invalid value for campaign_id: {:?}", value)
+                            })?)
+                        };
+                        sqlx::query!(
+                            "UPDATE task SET campaign_id = $1 WHERE id = $2",
+                            value,
+                            task_id
+                        )
+                        .execute(&mut *tx)
+                        .await?;
+                    }
+                    "instrument_serial" => {
+                        let value = value.as_str();
+                        sqlx::query!(
+                            "UPDATE task SET instrument_serial = $1 WHERE id = $2",
+                            value,
+                            task_id
+                        )
+                        .execute(&mut *tx)
+                        .await?;
+                    }
                     _ => {
                         return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
                     }
@@ -131,26 +155,72 @@ pub struct SampleSet {
     qty: i64,
 }
 
-pub async fn get_sample_set(
-    ctx: Extension<ApiContext>,
-    Path(task_id): Path<i64>,
-) -> Result<Json<Vec<SampleSet>>, Error> {
+pub(crate) async fn fetch_sample_set(
+    db: &SqlitePool,
+    task_id: i64,
+) -> Result<Vec<SampleSet>, Error> {
     let sample_set = sqlx::query_as!(
         SampleSet,
         "SELECT sample_type_id AS id, qty FROM sample_set WHERE task_id = $1 ORDER BY sample_type_id ASC",
         task_id
     )
-    .fetch_all(&ctx.db)
+    .fetch_all(db)
     .await?;
 
-    Ok(Json(sample_set))
+    Ok(sample_set)
+}
+
+/// A sample type of the sample set of a task, by name.
+#[derive(Serialize)]
+pub struct Sample {
+    pub(crate) name: String,
+    pub(crate) variant: Option<String>,
+    /// Number of bottles
+    pub(crate) qty: i64,
+}
+
+pub(crate) async fn fetch_samples(db: &SqlitePool, task_id: i64) -> Result<Vec<Sample>, Error> {
+    let samples = sqlx::query_as!(
+        Sample,
+        r#"
+        SELECT
+            sample_type.name,
+            sample_type.variant,
+            sample_set.qty
+        FROM
+            sample_set
+        JOIN sample_type
+            ON sample_type.id = sample_set.sample_type_id
+        WHERE
+            sample_set.task_id = $1
+        ORDER BY
+            sample_type.id
+        "#,
+        task_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(samples)
+}
+
+pub async fn get_sample_set(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<Vec<SampleSet>>, Error> {
+    Ok(Json(fetch_sample_set(&ctx.db, task_id).await?))
 }
 
 pub async fn update_sample_set(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path(task_id): Path<i64>,
     Json(update_items): Json<Vec<SampleSet>>,
 ) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
     let mut tx = ctx.db.begin().await?;
 
     for update in update_items {
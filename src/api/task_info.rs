@@ -1,15 +1,20 @@
 use std::collections::HashMap;
 
-use axum::extract::{Json, Path};
+use axum::extract::{Json, Path, Query};
 use axum::Extension;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
 
+use super::auth::{Role, Session};
+use super::comment::{sanitize, CommentFormatQuery};
+use super::finalize::{ensure_task_info_unlocked, ensure_unlocked};
+use super::search::index_pending_notes;
 use super::{ApiContext, Error};
 
 pub async fn get_last_timestamp(
     ctx: Extension<ApiContext>,
-) -> Result<Json<Option<NaiveDateTime>>, Error> {
+) -> Result<Json<Option<DateTime<Utc>>>, Error> {
     let last_timestamp = sqlx::query!(
         r#"
         SELECT
@@ -28,33 +33,32 @@ pub async fn get_last_timestamp(
     .await?;
 
     Ok(Json(
-        last_timestamp.map(|row| row.sampling_time).unwrap_or(None),
+        last_timestamp
+            .and_then(|row| row.sampling_time)
+            .map(|sampling_time| sampling_time.and_utc()),
     ))
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct TaskInfo {
-    id: i64,
-    task_id: i64,
-    calibration: Option<String>,
+    pub(crate) id: i64,
+    pub(crate) task_id: i64,
+    pub(crate) calibration: Option<String>,
     #[serde(with = "super::serde::iso8601_option")]
-    purging_time: Option<NaiveDateTime>,
-    water_level: Option<f64>,
-    pump_id: Option<i64>,
-    pump_depth: Option<f64>,
-    pump_freq: Option<f64>,
-    pump_rate: Option<f64>,
-    hose_setup: Option<String>,
+    pub(crate) purging_time: Option<NaiveDateTime>,
+    pub(crate) water_level: Option<f64>,
+    pub(crate) pump_id: Option<i64>,
+    pub(crate) pump_depth: Option<f64>,
+    pub(crate) pump_freq: Option<f64>,
+    pub(crate) pump_rate: Option<f64>,
+    pub(crate) hose_setup: Option<String>,
     #[serde(with = "super::serde::iso8601_option")]
-    sampling_time: Option<NaiveDateTime>,
-    sample_wt_radium: Option<f64>,
-    comment: Option<String>,
+    pub(crate) sampling_time: Option<NaiveDateTime>,
+    pub(crate) sample_wt_radium: Option<f64>,
+    pub(crate) comment: Option<String>,
 }
 
-pub async fn get_task_info(
-    ctx: Extension<ApiContext>,
-    Path(task_id): Path<i64>,
-) -> Result<Json<Vec<TaskInfo>>, Error> {
+pub(crate) async fn fetch_task_info(db: &SqlitePool, task_id: i64) -> Result<Vec<TaskInfo>, Error> {
     let task_info = sqlx::query_as!(
         TaskInfo,
         r#"
@@ -82,9 +86,22 @@ pub async fn get_task_info(
         "#,
         task_id
     )
-    .fetch_all(&ctx.db)
+    .fetch_all(db)
     .await?;
 
+    Ok(task_info)
+}
+
+pub async fn get_task_info(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+    Query(query): Query<CommentFormatQuery>,
+) -> Result<Json<Vec<TaskInfo>>, Error> {
+    let mut task_info = fetch_task_info(&ctx.db, task_id).await?;
+    for info in task_info.iter_mut() {
+        info.comment = query.format.render_option(info.comment.take());
+    }
+
     Ok(Json(task_info))
 }
 
@@ -127,9 +144,14 @@ pub struct PeopleId {
 
 pub async fn add_minuted_by(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path((_, task_info_id)): Path<(i64, i64)>,
     Json(people_id): Json<PeopleId>,
 ) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_task_info_unlocked(&ctx.db, task_info_id).await?;
+
     sqlx::query!(
         r#"
         INSERT INTO
@@ -148,8 +170,13 @@ pub async fn add_minuted_by(
 
 pub async fn delete_minuted_by(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path((_, task_info_id, people_id)): Path<(i64, i64, i64)>,
 ) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_task_info_unlocked(&ctx.db, task_info_id).await?;
+
     sqlx::query!(
         r#"
         DELETE FROM task_minuted_by
@@ -192,9 +219,14 @@ pub async fn get_sampled_by(
 
 pub async fn add_sampled_by(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path((_, task_info_id)): Path<(i64, i64)>,
     Json(people_id): Json<PeopleId>,
 ) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_task_info_unlocked(&ctx.db, task_info_id).await?;
+
     sqlx::query!(
         r#"
         INSERT INTO
@@ -213,8 +245,13 @@ pub async fn add_sampled_by(
 
 pub async fn delete_sampled_by(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path((_, task_info_id, people_id)): Path<(i64, i64, i64)>,
 ) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_task_info_unlocked(&ctx.db, task_info_id).await?;
+
     sqlx::query!(
         r#"
         DELETE FROM task_sampled_by
@@ -258,9 +295,18 @@ pub struct NewTaskInfo {
 
 pub async fn insert_task_info(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path(task_id): Path<i64>,
     Json(task_info): Json<NewTaskInfo>,
 ) -> Result<Json<Option<i64>>, Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    let comment = task_info.comment.as_deref().map(sanitize);
+
+    let mut tx = ctx.db.begin().await?;
+
     let task_info_id = sqlx::query!(
         r#"
         INSERT INTO task_info (
@@ -292,18 +338,27 @@ pub async fn insert_task_info(
         task_info.hose_setup,
         task_info.sampling_time,
         task_info.sample_wt_radium,
-        task_info.comment
+        comment
     )
-    .fetch_one(&ctx.db)
+    .fetch_one(&mut *tx)
     .await?;
 
+    index_pending_notes(&mut tx).await?;
+
+    tx.commit().await?;
+
     Ok(Json(task_info_id.id))
 }
 
 pub async fn delete_task_info(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path((_, task_info_id)): Path<(i64, i64)>,
 ) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_task_info_unlocked(&ctx.db, task_info_id).await?;
+
     sqlx::query!("DELETE FROM task_info WHERE id = $1", task_info_id)
         .execute(&ctx.db)
         .await?;
@@ -313,9 +368,14 @@ pub async fn delete_task_info(
 
 pub async fn update_task_info(
     ctx: Extension<ApiContext>,
+    session: Session,
     Path((_, task_info_id)): Path<(i64, i64)>,
     Json(task_info): Json<HashMap<String, serde_json::Value>>,
 ) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_task_info_unlocked(&ctx.db, task_info_id).await?;
+
     let mut tx = ctx.db.begin().await?;
 
     for (key, val) in task_info {
@@ -331,7 +391,7 @@ pub async fn update_task_info(
                 .await?;
             }
             "purging_time" => {
-                let val = val.as_str();
+                let val = super::serde::iso8601_option::deserialize(val)?;
                 sqlx::query!(
                     "UPDATE task_info SET purging_time = $1 WHERE id = $2",
                     val,
@@ -407,7 +467,7 @@ pub async fn update_task_info(
                 .await?;
             }
             "sampling_time" => {
-                let val = val.as_str();
+                let val = super::serde::iso8601_option::deserialize(val)?;
                 sqlx::query!(
                     "UPDATE task_info SET sampling_time = $1 WHERE id = $2",
                     val,
@@ -427,7 +487,7 @@ pub async fn update_task_info(
                 .await?;
             }
             "comment" => {
-                let val = val.as_str();
+                let val = val.as_str().map(sanitize);
                 sqlx::query!(
                     "UPDATE task_info SET comment = $1 WHERE id = $2",
                     val,
@@ -442,6 +502,8 @@ pub async fn update_task_info(
         }
     }
 
+    index_pending_notes(&mut tx).await?;
+
     tx.commit().await?;
 
     Ok(())
@@ -0,0 +1,186 @@
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+use super::auth::{Role, Session};
+use super::finalize::ensure_unlocked;
+use super::{ApiContext, Error};
+
+/// Lifecycle of a sampling task, in order.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Planned,
+    Purging,
+    Sampled,
+    Shipped,
+    Received,
+    ResultsIn,
+}
+
+impl TaskStatus {
+    /// Name of the status for people, as in printouts.
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            TaskStatus::Planned => "Planned",
+            TaskStatus::Purging => "Purging",
+            TaskStatus::Sampled => "Sampled",
+            TaskStatus::Shipped => "Shipped",
+            TaskStatus::Received => "Received",
+            TaskStatus::ResultsIn => "Results in",
+        }
+    }
+
+    /// A task moves forward one step at a time. Stepping back is a correction
+    /// and is reserved for data managers.
+    fn check_transition(self, to: TaskStatus, role: Role) -> Result<(), Error> {
+        let (from, to_idx) = (self as usize, to as usize);
+        if to_idx == from + 1 || (to_idx + 1 == from && role >= Role::DataManager) {
+            Ok(())
+        } else if to_idx + 1 == from {
+            Err(Error::Forbidden)
+        } else {
+            Err(anyhow::anyhow!("Invalid status transition: {:?} -> {:?}", self, to).into())
+        }
+    }
+}
+
+/// Record a status change of a task, used for both transitions and new tasks.
+pub async fn record_status(
+    conn: &mut SqliteConnection,
+    task_id: i64,
+    status: TaskStatus,
+    people_id: i64,
+    comment: Option<&str>,
+) -> Result<(), Error> {
+    let now = Utc::now().naive_utc();
+    let done = status >= TaskStatus::Sampled;
+
+    sqlx::query!(
+        r#"
+        UPDATE task
+        SET
+            status = $1,
+            status_changed_at = $2,
+            done = $3
+        WHERE
+            id = $4
+        "#,
+        status,
+        now,
+        done,
+        task_id
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO
+            task_status_log (task_id, status, people_id, "timestamp", comment)
+        VALUES
+            ($1, $2, $3, $4, $5)
+        "#,
+        task_id,
+        status,
+        people_id,
+        now,
+        comment
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct StatusLog {
+    id: i64,
+    status: TaskStatus,
+    /// None for the status of tasks from before status tracking
+    people_id: Option<i64>,
+    #[serde(with = "super::serde::iso8601")]
+    timestamp: NaiveDateTime,
+    comment: Option<String>,
+}
+
+pub async fn get_status_log(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<Vec<StatusLog>>, Error> {
+    let log = sqlx::query_as!(
+        StatusLog,
+        r#"
+        SELECT
+            id,
+            status AS "status: TaskStatus",
+            people_id,
+            "timestamp",
+            comment
+        FROM
+            task_status_log
+        WHERE
+            task_id = $1
+        ORDER BY
+            id
+        "#,
+        task_id
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+
+    Ok(Json(log))
+}
+
+#[derive(Deserialize)]
+pub struct StatusTransition {
+    status: TaskStatus,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+/// Move a task to another status. Transitions after sampling (shipping, lab
+/// results) are allowed on finalized tasks, the ones in the field are not.
+pub async fn update_status(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(task_id): Path<i64>,
+    Json(transition): Json<StatusTransition>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    let task = sqlx::query!(
+        r#"SELECT status AS "status: TaskStatus" FROM task WHERE id = $1"#,
+        task_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(Error::TaskNotFound(task_id))?;
+
+    task.status
+        .check_transition(transition.status, session.role)?;
+
+    if task.status.min(transition.status) < TaskStatus::Sampled {
+        ensure_unlocked(&mut *tx, task_id).await?;
+    }
+
+    record_status(
+        &mut tx,
+        task_id,
+        transition.status,
+        session.people_id,
+        transition.comment.as_deref(),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+
+use axum::extract::{Json, Query};
+use axum::Extension;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite};
+
+use super::comment::CommentFormatQuery;
+use super::field_parameters::compute_field_parameters;
+use super::task_status::TaskStatus;
+use super::{ApiContext, Error};
+
+/// Largest page, larger limits are clamped.
+const MAX_LIMIT: i64 = 1000;
+
+/// Largest page with final field parameters, which are computed per task from
+/// its sensor data.
+const FIELD_PARAMETERS_MAX_LIMIT: i64 = 100;
+
+#[derive(Deserialize, Serialize, sqlx::FromRow)]
+pub struct TaskSummary {
+    id: i64,
+    done: Option<bool>,
+    serial: Option<String>,
+    well_id: Option<i64>,
+    depth: Option<String>,
+    campaign_id: Option<i64>,
+    status: Option<TaskStatus>,
+    #[serde(with = "super::serde::iso8601_option")]
+    status_changed_at: Option<NaiveDateTime>,
+    /// Seconds since the last status change
+    status_age: Option<i64>,
+    sample_set: Option<String>,
+    #[serde(with = "super::serde::iso8601_option")]
+    sampling_time: Option<NaiveDateTime>,
+    comment: Option<String>,
+    /// Raw value of a text sort key, used to build the cursor
+    #[serde(skip)]
+    #[sqlx(default)]
+    sort_value: Option<String>,
+    /// Final field parameters as `final_<parameter>`, when requested
+    #[serde(flatten, skip_deserializing)]
+    #[sqlx(skip)]
+    field_parameters: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Id,
+    Serial,
+    WellId,
+    Depth,
+    SamplingTime,
+    StatusChangedAt,
+}
+
+impl SortKey {
+    /// Sort expression, nullable columns are coalesced so that keyset
+    /// pagination can compare against the cursor.
+    fn expr(self) -> &'static str {
+        match self {
+            SortKey::Id => "task_summary.id",
+            SortKey::Serial => "COALESCE(task_summary.serial, '')",
+            SortKey::WellId => "task_summary.well_id",
+            SortKey::Depth => "task_summary.depth",
+            SortKey::SamplingTime => "COALESCE(task_summary.sampling_time, '')",
+            SortKey::StatusChangedAt => "COALESCE(task_summary.status_changed_at, '')",
+        }
+    }
+
+    fn is_numeric(self) -> bool {
+        matches!(self, SortKey::Id | SortKey::WellId)
+    }
+
+    fn cursor_value(self, summary: &TaskSummary) -> serde_json::Value {
+        match self {
+            SortKey::Id => summary.id.into(),
+            SortKey::WellId => summary.well_id.into(),
+            _ => summary.sort_value.clone().unwrap_or_default().into(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Deserialize)]
+pub struct TaskSummaryFilter {
+    #[serde(default, with = "super::serde::iso8601_option")]
+    sampling_from: Option<NaiveDateTime>,
+    #[serde(default, with = "super::serde::iso8601_option")]
+    sampling_to: Option<NaiveDateTime>,
+    #[serde(default)]
+    well_id: Option<i64>,
+    #[serde(default)]
+    well_type: Option<String>,
+    #[serde(default)]
+    done: Option<bool>,
+    #[serde(default)]
+    status: Option<TaskStatus>,
+    #[serde(default)]
+    campaign_id: Option<i64>,
+    /// Prefix of the task serial
+    #[serde(default)]
+    serial: Option<String>,
+    /// Free text search on the comment
+    #[serde(default)]
+    q: Option<String>,
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    order: SortOrder,
+    /// Page size, at least 1 and clamped to 1000
+    #[serde(default)]
+    limit: Option<i64>,
+    /// Opaque cursor from `next_cursor` of the previous page
+    #[serde(default)]
+    cursor: Option<String>,
+    /// Add the final field parameters of each task, requires a `limit` of at
+    /// most 100
+    #[serde(default)]
+    field_parameters: bool,
+}
+
+impl TaskSummaryFilter {
+    fn push_conditions<'a>(&'a self, builder: &mut QueryBuilder<'a, Sqlite>) {
+        if let Some(sampling_from) = self.sampling_from {
+            builder
+                .push(" AND task_summary.sampling_time >= ")
+                .push_bind(sampling_from);
+        }
+        if let Some(sampling_to) = self.sampling_to {
+            builder
+                .push(" AND task_summary.sampling_time <= ")
+                .push_bind(sampling_to);
+        }
+        if let Some(well_id) = self.well_id {
+            builder
+                .push(" AND task_summary.well_id = ")
+                .push_bind(well_id);
+        }
+        if let Some(well_type) = &self.well_type {
+            builder.push(" AND well.type = ").push_bind(well_type);
+        }
+        if let Some(done) = self.done {
+            builder.push(" AND task_summary.done = ").push_bind(done);
+        }
+        if let Some(status) = self.status {
+            builder
+                .push(" AND task_summary.status = ")
+                .push_bind(status);
+        }
+        if let Some(campaign_id) = self.campaign_id {
+            builder
+                .push(" AND task_summary.campaign_id = ")
+                .push_bind(campaign_id);
+        }
+        if let Some(serial) = &self.serial {
+            builder
+                .push(" AND task_summary.serial LIKE ")
+                .push_bind(format!("{}%", escape_like(serial)))
+                .push(" ESCAPE '\\'");
+        }
+        if let Some(q) = &self.q {
+            builder
+                .push(" AND task_summary.comment LIKE ")
+                .push_bind(format!("%{}%", escape_like(q)))
+                .push(" ESCAPE '\\'");
+        }
+    }
+}
+
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn encode_cursor(value: serde_json::Value, id: i64) -> String {
+    hex::encode(serde_json::json!([value, id]).to_string())
+}
+
+fn decode_cursor(cursor: &str) -> Result<(serde_json::Value, i64), Error> {
+    let bytes = hex::decode(cursor).map_err(|_| anyhow::anyhow!("Invalid cursor"))?;
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+#[derive(Serialize)]
+pub struct TaskSummaryPage {
+    items: Vec<TaskSummary>,
+    /// Number of tasks matching the filter, across all pages
+    total: i64,
+    next_cursor: Option<String>,
+}
+
+pub async fn list_task_summaries(
+    ctx: Extension<ApiContext>,
+    Query(filter): Query<TaskSummaryFilter>,
+    Query(format): Query<CommentFormatQuery>,
+) -> Result<Json<TaskSummaryPage>, Error> {
+    const FROM: &str = r#"
+        FROM
+            task_summary
+        LEFT JOIN well
+            ON well.id = task_summary.well_id
+        WHERE
+            1 = 1
+        "#;
+
+    let limit = match filter.limit {
+        Some(limit) if limit < 1 => {
+            return Err(anyhow::anyhow!("Invalid limit: {}", limit).into());
+        }
+        limit => limit.map(|limit| limit.min(MAX_LIMIT)),
+    };
+
+    if filter.field_parameters && limit.is_none_or(|limit| limit > FIELD_PARAMETERS_MAX_LIMIT) {
+        return Err(anyhow::anyhow!(
+            "field_parameters requires a limit of at most {}",
+            FIELD_PARAMETERS_MAX_LIMIT
+        )
+        .into());
+    }
+
+    let mut count = QueryBuilder::<Sqlite>::new("SELECT COUNT(*)");
+    count.push(FROM);
+    filter.push_conditions(&mut count);
+    let total: i64 = count.build_query_scalar().fetch_one(&ctx.db).await?;
+
+    let mut query = QueryBuilder::<Sqlite>::new(
+        r#"
+        SELECT
+            task_summary.id,
+            task_summary.done,
+            task_summary.serial,
+            task_summary.well_id,
+            task_summary.depth,
+            task_summary.campaign_id,
+            task_summary.status,
+            task_summary.status_changed_at,
+            task_summary.status_age,
+            task_summary.sample_set,
+            task_summary.sampling_time,
+            task_summary.comment
+        "#,
+    );
+
+    let expr = filter.sort.expr();
+    if !filter.sort.is_numeric() {
+        // Datetimes are compared as stored, keep the raw text for the cursor
+        query.push(format!(", {} AS sort_value", expr));
+    }
+
+    query.push(FROM);
+    filter.push_conditions(&mut query);
+
+    let (cmp, dir) = match filter.order {
+        SortOrder::Asc => (">", "ASC"),
+        SortOrder::Desc => ("<", "DESC"),
+    };
+
+    if let Some(cursor) = &filter.cursor {
+        let (value, id) = decode_cursor(cursor)?;
+        query.push(format!(" AND ({} {} ", expr, cmp));
+        push_cursor_value(&mut query, &value);
+        query.push(format!(" OR ({} = ", expr));
+        push_cursor_value(&mut query, &value);
+        query
+            .push(format!(" AND task_summary.id {} ", cmp))
+            .push_bind(id)
+            .push("))");
+    }
+
+    query.push(format!(
+        " ORDER BY {} {}, task_summary.id {}",
+        expr, dir, dir
+    ));
+
+    if let Some(limit) = limit {
+        // Fetch one more row to know whether there is a next page
+        query.push(" LIMIT ").push_bind(limit + 1);
+    }
+
+    let mut items: Vec<TaskSummary> = query.build_query_as().fetch_all(&ctx.db).await?;
+
+    let next_cursor = match limit {
+        Some(limit) if items.len() as i64 > limit => {
+            items.truncate(limit as usize);
+            items
+                .last()
+                .map(|last| encode_cursor(filter.sort.cursor_value(last), last.id))
+        }
+        _ => None,
+    };
+
+    for item in items.iter_mut() {
+        item.comment = format.format.render_option(item.comment.take());
+
+        if filter.field_parameters {
+            let summary = compute_field_parameters(&ctx.db, item.id, None).await?;
+            item.field_parameters = summary
+                .parameters
+                .iter()
+                .map(|parameter| (format!("final_{}", parameter.parameter), parameter.value))
+                .collect();
+        }
+    }
+
+    Ok(Json(TaskSummaryPage {
+        items,
+        total,
+        next_cursor,
+    }))
+}
+
+fn push_cursor_value(query: &mut QueryBuilder<'_, Sqlite>, value: &serde_json::Value) {
+    match value {
+        serde_json::Value::Number(n) => query.push_bind(n.as_i64()),
+        serde_json::Value::String(s) => query.push_bind(s.clone()),
+        _ => query.push_bind(None::<i64>),
+    };
+}
@@ -0,0 +1,196 @@
+//! Timestamps are stored in UTC. Times without an offset, from logger files,
+//! photos and databases from before UTC storage, are taken in the project time
+//! zone unless a logger time zone is set.
+
+use chrono::{Duration, LocalResult, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use sqlx::SqlitePool;
+
+use super::Error;
+
+/// Columns which were stored in the local time of the client.
+const LOCAL_COLUMNS: &[(&str, &str)] = &[
+    ("task_info", "purging_time"),
+    ("task_info", "sampling_time"),
+    ("sensor_data", "datetime"),
+    ("attachment", "taken_at"),
+    ("water_level_reading", "measured_at"),
+    ("calibration", "calibrated_at"),
+    ("calibration", "valid_until"),
+    ("sensor_correction", "start_time"),
+    ("sensor_correction", "end_time"),
+    ("sensor_qc_flag", "start_time"),
+    ("sensor_qc_flag", "end_time"),
+    ("baro_reading", "datetime"),
+    ("pressure_compensation", "reference_time"),
+    ("deployment", "installed_at"),
+    ("deployment", "removed_at"),
+];
+
+/// UTC of a wall clock time in a time zone. Times repeated at the end of
+/// daylight saving time resolve to the first, times skipped at its start use
+/// the offset from before the change.
+pub fn to_utc(tz: Tz, datetime: NaiveDateTime) -> NaiveDateTime {
+    match tz.from_local_datetime(&datetime) {
+        LocalResult::Single(local) | LocalResult::Ambiguous(local, _) => local.naive_utc(),
+        LocalResult::None => {
+            let offset = tz
+                .offset_from_utc_datetime(&(datetime - Duration::days(1)))
+                .fix();
+            datetime - Duration::seconds(offset.local_minus_utc() as i64)
+        }
+    }
+}
+
+/// Convert the timestamps stored in local time to UTC, once. The time zone of
+/// the stored times must be given explicitly, `UTC` included, unless there are
+/// none yet. Starting without one leaves the conversion pending and fails.
+pub async fn convert_to_utc(db: &SqlitePool, tz: Option<Tz>) -> Result<(), Error> {
+    let pending = sqlx::query_scalar!(
+        r#"SELECT converted_at IS NULL AS "pending!: bool" FROM utc_conversion WHERE id = 1"#
+    )
+    .fetch_optional(db)
+    .await?
+    .unwrap_or(false);
+
+    if !pending {
+        return Ok(());
+    }
+
+    let mut tx = db.begin().await?;
+
+    if tz.is_none() {
+        for (table, column) in LOCAL_COLUMNS {
+            let stored: i64 = sqlx::query_scalar(&format!(
+                "SELECT COUNT(*) FROM {table} WHERE {column} IS NOT NULL"
+            ))
+            .fetch_one(&mut *tx)
+            .await?;
+
+            if stored > 0 {
+                return Err(anyhow::anyhow!(
+                    "Stored timestamps are in local time and must be converted to UTC once, \
+                     start with --time-zone (use --time-zone UTC if they already are)"
+                )
+                .into());
+            }
+        }
+    }
+
+    if let Some(tz) = tz {
+        for (table, column) in LOCAL_COLUMNS {
+            let rows: Vec<(i64, NaiveDateTime)> = sqlx::query_as(&format!(
+                "SELECT rowid, {column} FROM {table} WHERE {column} IS NOT NULL ORDER BY {column}"
+            ))
+            .fetch_all(&mut *tx)
+            .await?;
+
+            // Convert each timestamp in the direction of its own shift, so that
+            // unique timestamps don't collide with ones not converted yet. The
+            // offset changes with daylight saving time, and zones like
+            // Europe/London only shift part of the year.
+            let (earlier, later): (Vec<_>, Vec<_>) = rows
+                .into_iter()
+                .map(|(rowid, datetime)| (rowid, datetime, to_utc(tz, datetime)))
+                .filter(|(_, datetime, utc)| utc != datetime)
+                .partition(|(_, datetime, utc)| utc < datetime);
+
+            for (rowid, _, utc) in earlier.into_iter().chain(later.into_iter().rev()) {
+                sqlx::query(&format!(
+                    "UPDATE {table} SET {column} = $1 WHERE rowid = $2"
+                ))
+                .bind(utc)
+                .bind(rowid)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+    }
+
+    let time_zone = tz.map(|tz| tz.name().to_string());
+    let now = Utc::now().naive_utc();
+
+    // Tasks from before status tracking are dated to their last sampling time,
+    // which the migration can only read once it is in UTC.
+    sqlx::query!(
+        "UPDATE task SET status_changed_at = COALESCE(
+            (SELECT MAX(sampling_time) FROM task_info WHERE task_info.task_id = task.id),
+            $1
+        )
+        WHERE status_changed_at IS NULL",
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"INSERT INTO task_status_log (task_id, status, people_id, "timestamp", comment)
+        SELECT id, status, NULL, status_changed_at, 'Status from before status tracking'
+        FROM task
+        WHERE NOT EXISTS (SELECT 1 FROM task_status_log WHERE task_status_log.task_id = task.id)"#
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "UPDATE utc_conversion SET time_zone = $1, converted_at = $2 WHERE id = 1",
+        time_zone,
+        now
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    tracing::info!(
+        "Stored timestamps converted to UTC from {}",
+        time_zone.as_deref().unwrap_or("UTC")
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn datetime(day: u32, month: u32, hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, month, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn to_utc_single() {
+        let tz = chrono_tz::Europe::Berlin;
+        assert_eq!(to_utc(tz, datetime(15, 1, 12, 0)), datetime(15, 1, 11, 0));
+        assert_eq!(to_utc(tz, datetime(15, 7, 12, 0)), datetime(15, 7, 10, 0));
+    }
+
+    #[test]
+    fn to_utc_gap() {
+        // 02:00 to 03:00 is skipped on 29 March, the offset before is +01:00
+        let tz = chrono_tz::Europe::Berlin;
+        assert_eq!(to_utc(tz, datetime(29, 3, 2, 30)), datetime(29, 3, 1, 30));
+        assert_eq!(to_utc(tz, datetime(29, 3, 3, 0)), datetime(29, 3, 1, 0));
+    }
+
+    #[test]
+    fn to_utc_ambiguous() {
+        // 02:00 to 03:00 is repeated on 25 October, first at +02:00
+        let tz = chrono_tz::Europe::Berlin;
+        assert_eq!(to_utc(tz, datetime(25, 10, 2, 30)), datetime(25, 10, 0, 30));
+        assert_eq!(to_utc(tz, datetime(25, 10, 3, 0)), datetime(25, 10, 2, 0));
+    }
+
+    #[test]
+    fn to_utc_west() {
+        // New York skips 02:00 to 03:00 on 8 March, the offset before is -05:00
+        let tz = chrono_tz::America::New_York;
+        assert_eq!(to_utc(tz, datetime(8, 3, 2, 30)), datetime(8, 3, 7, 30));
+        assert_eq!(to_utc(tz, datetime(1, 11, 1, 30)), datetime(1, 11, 5, 30));
+    }
+}
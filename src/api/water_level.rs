@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use axum::extract::{Json, Path};
+use axum::Extension;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteConnection, SqlitePool};
+
+use super::auth::{Role, Session};
+use super::finalize::ensure_unlocked;
+use super::{ApiContext, Error};
+
+/// Top of casing, the default reference point of water level readings.
+pub(crate) const TOP_OF_CASING: &str = "TOC";
+
+/// Ground surface.
+pub(crate) const GROUND_SURFACE: &str = "GS";
+
+#[derive(Serialize)]
+pub struct WaterLevelReading {
+    id: i64,
+    task_id: i64,
+    #[serde(with = "super::serde::iso8601")]
+    pub(crate) measured_at: NaiveDateTime,
+    /// Metres below the reference point
+    pub(crate) depth_to_water: f64,
+    pub(crate) reference_point: String,
+    people_id: Option<i64>,
+}
+
+pub(crate) async fn fetch_water_level_readings(
+    db: &SqlitePool,
+    task_id: i64,
+) -> Result<Vec<WaterLevelReading>, Error> {
+    let readings = sqlx::query_as!(
+        WaterLevelReading,
+        r#"
+        SELECT
+            id AS "id!",
+            task_id,
+            measured_at,
+            depth_to_water,
+            reference_point,
+            people_id
+        FROM
+            water_level_reading
+        WHERE
+            task_id = $1
+        ORDER BY
+            measured_at, id
+        "#,
+        task_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(readings)
+}
+
+/// Drawdown compares readings with each other, so all readings of a task must
+/// share one reference point. `reading_id` is left out, for updates.
+async fn ensure_reference_point(
+    conn: &mut SqliteConnection,
+    task_id: i64,
+    reading_id: Option<i64>,
+    reference_point: &str,
+) -> Result<(), Error> {
+    let other = sqlx::query_scalar!(
+        r#"
+        SELECT
+            reference_point
+        FROM
+            water_level_reading
+        WHERE
+            task_id = $1 AND ($2 IS NULL OR id != $2) AND reference_point != $3
+        LIMIT 1
+        "#,
+        task_id,
+        reading_id,
+        reference_point
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    match other {
+        Some(other) => Err(anyhow::anyhow!(
+            "Water level readings of task {} are measured from {}, not {}",
+            task_id,
+            other,
+            reference_point
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+#[derive(Serialize)]
+pub struct DrawdownReading {
+    #[serde(flatten)]
+    reading: WaterLevelReading,
+    /// Drop of the water level since the first reading, in metres. All
+    /// readings of a task share one reference point.
+    drawdown: f64,
+    exceeded: bool,
+}
+
+#[derive(Serialize)]
+pub struct WaterLevelLog {
+    readings: Vec<DrawdownReading>,
+    initial_level: Option<f64>,
+    max_drawdown: Option<f64>,
+    drawdown_limit: f64,
+    /// Set when the drawdown exceeded the limit at any reading
+    warning: bool,
+}
+
+pub async fn get_water_level_log(
+    ctx: Extension<ApiContext>,
+    Path(task_id): Path<i64>,
+) -> Result<Json<WaterLevelLog>, Error> {
+    let readings = fetch_water_level_readings(&ctx.db, task_id).await?;
+    let limit = ctx.settings.max_drawdown;
+    let initial_level = readings.first().map(|reading| reading.depth_to_water);
+
+    let readings: Vec<DrawdownReading> = readings
+        .into_iter()
+        .map(|reading| {
+            let drawdown = reading.depth_to_water - initial_level.unwrap_or_default();
+            DrawdownReading {
+                reading,
+                drawdown,
+                exceeded: drawdown > limit,
+            }
+        })
+        .collect();
+
+    let max_drawdown = readings
+        .iter()
+        .map(|reading| reading.drawdown)
+        .reduce(f64::max);
+
+    Ok(Json(WaterLevelLog {
+        warning: readings.iter().any(|reading| reading.exceeded),
+        readings,
+        initial_level,
+        max_drawdown,
+        drawdown_limit: limit,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct NewWaterLevelReading {
+    #[serde(with = "super::serde::iso8601")]
+    measured_at: NaiveDateTime,
+    depth_to_water: f64,
+    #[serde(default)]
+    reference_point: Option<String>,
+    /// Defaults to the person logged in
+    #[serde(default)]
+    people_id: Option<i64>,
+}
+
+pub async fn insert_water_level_reading(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(task_id): Path<i64>,
+    Json(reading): Json<NewWaterLevelReading>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    let reference_point = reading
+        .reference_point
+        .unwrap_or_else(|| TOP_OF_CASING.to_string());
+    let people_id = reading.people_id.unwrap_or(session.people_id);
+
+    let mut tx = ctx.db.begin().await?;
+
+    ensure_reference_point(&mut tx, task_id, None, &reference_point).await?;
+
+    let id = sqlx::query!(
+        r#"
+        INSERT INTO
+            water_level_reading (task_id, measured_at, depth_to_water, reference_point, people_id)
+        VALUES
+            ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        task_id,
+        reading.measured_at,
+        reading.depth_to_water,
+        reference_point,
+        people_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(id.id.ok_or_else(|| {
+        anyhow::anyhow!("Failed to insert water level reading")
+    })?))
+}
+
+pub async fn update_water_level_reading(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path((task_id, reading_id)): Path<(i64, i64)>,
+    Json(update): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    for (key, value) in update {
+        match key.as_str() {
+            "measured_at" => {
+                let value = super::serde::iso8601::deserialize(value)?;
+                sqlx::query!(
+                    "UPDATE water_level_reading SET measured_at = $1 WHERE id = $2 AND task_id = $3",
+                    value,
+                    reading_id,
+                    task_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "depth_to_water" => {
+                let value: f64 = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE water_level_reading SET depth_to_water = $1 WHERE id = $2 AND task_id = $3",
+                    value,
+                    reading_id,
+                    task_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "reference_point" => {
+                let value = value.as_str().ok_or_else(|| {
+                    anyhow::anyhow!("Invalid value for reference_point: {:?}", value)
+                })?;
+                ensure_reference_point(&mut tx, task_id, Some(reading_id), value).await?;
+                sqlx::query!(
+                    "UPDATE water_level_reading SET reference_point = $1 WHERE id = $2 AND task_id = $3",
+                    value,
+                    reading_id,
+                    task_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "people_id" => {
+                let value: Option<i64> = serde_json::from_value(value)?;
+                sqlx::query!(
+                    "UPDATE water_level_reading SET people_id = $1 WHERE id = $2 AND task_id = $3",
+                    value,
+                    reading_id,
+                    task_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
+            }
+        }
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn delete_water_level_reading(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path((task_id, reading_id)): Path<(i64, i64)>,
+) -> Result<(), Error> {
+    session.require(Role::FieldTech)?;
+
+    ensure_unlocked(&ctx.db, task_id).await?;
+
+    sqlx::query!(
+        "DELETE FROM water_level_reading WHERE id = $1 AND task_id = $2",
+        reading_id,
+        task_id
+    )
+    .execute(&ctx.db)
+    .await?;
+
+    Ok(())
+}
@@ -1,20 +1,407 @@
-use axum::{Extension, Json};
+use std::collections::HashMap;
+
+use axum::extract::{Json, Path};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use axum::response::IntoResponse;
+use axum::Extension;
 use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
 
+use super::auth::{Role, Session};
+use super::format::escape_xml;
+use super::search::index_pending_notes;
 use super::{ApiContext, Error};
 
+/// CRS of coordinates which can be published as GeoJSON and KML without reprojection.
+const WGS84: &str = "EPSG:4326";
+
+/// Comma separated ids of the wells left out of GeoJSON and KML because their
+/// coordinates are in another CRS.
+const UNPROJECTED_WELLS: HeaderName = HeaderName::from_static("x-unprojected-wells");
+
+/// Lengths are in metres, depths are measured below top of casing.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Well {
-    id: i64,
-    name: String,
+    pub(crate) id: i64,
+    pub(crate) name: String,
     #[serde(rename = "type")]
+    pub(crate) type_: Option<String>,
+    pub(crate) comment: Option<String>,
+    pub(crate) x: Option<f64>,
+    pub(crate) y: Option<f64>,
+    pub(crate) crs: String,
+    pub(crate) ground_elevation: Option<f64>,
+    pub(crate) casing_top_elevation: Option<f64>,
+    pub(crate) casing_diameter: Option<f64>,
+    pub(crate) total_depth: Option<f64>,
+    pub(crate) screen_top: Option<f64>,
+    pub(crate) screen_bottom: Option<f64>,
+    pub(crate) aquifer: Option<String>,
+}
+
+impl Well {
+    /// Longitude and latitude, if the well has coordinates in WGS 84.
+    fn lon_lat(&self) -> Option<(f64, f64)> {
+        match (self.x, self.y) {
+            (Some(x), Some(y)) if self.crs == WGS84 => Some((x, y)),
+            _ => None,
+        }
+    }
+}
+
+/// Wells with coordinates in WGS 84 and their longitude and latitude, with the
+/// response headers of a map export. Wells with coordinates in another CRS are
+/// listed in the `X-Unprojected-Wells` header.
+async fn fetch_mapped_wells(
+    ctx: &ApiContext,
+    content_type: &'static str,
+) -> Result<(Vec<(Well, (f64, f64))>, HeaderMap), Error> {
+    let mut wells = Vec::new();
+    let mut unprojected = Vec::new();
+    for well in fetch_wells(ctx).await? {
+        match well.lon_lat() {
+            Some(lon_lat) => wells.push((well, lon_lat)),
+            None if well.x.is_some() && well.y.is_some() => unprojected.push(well.id.to_string()),
+            None => {}
+        }
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    if !unprojected.is_empty() {
+        let value = HeaderValue::from_str(&unprojected.join(",")).map_err(anyhow::Error::from)?;
+        headers.insert(UNPROJECTED_WELLS, value);
+    }
+
+    Ok((wells, headers))
+}
+
+async fn fetch_wells(ctx: &ApiContext) -> Result<Vec<Well>, Error> {
+    let wells = sqlx::query_as!(
+        Well,
+        r#"
+        SELECT
+            id,
+            name,
+            type AS type_,
+            comment,
+            x,
+            y,
+            crs,
+            ground_elevation,
+            casing_top_elevation,
+            casing_diameter,
+            total_depth,
+            screen_top,
+            screen_bottom,
+            aquifer
+        FROM
+            well
+        "#
+    )
+    .fetch_all(&ctx.db)
+    .await?;
+    Ok(wells)
+}
+
+pub(crate) async fn fetch_well(db: &SqlitePool, well_id: i64) -> Result<Well, Error> {
+    let well = sqlx::query_as!(
+        Well,
+        r#"
+        SELECT
+            id,
+            name,
+            type AS type_,
+            comment,
+            x,
+            y,
+            crs,
+            ground_elevation,
+            casing_top_elevation,
+            casing_diameter,
+            total_depth,
+            screen_top,
+            screen_bottom,
+            aquifer
+        FROM
+            well
+        WHERE
+            id = $1
+        "#,
+        well_id
+    )
+    .fetch_one(db)
+    .await?;
+    Ok(well)
+}
+
+pub async fn list_wells(ctx: Extension<ApiContext>) -> Result<Json<Vec<Well>>, Error> {
+    Ok(Json(fetch_wells(&ctx).await?))
+}
+
+#[derive(Deserialize)]
+pub struct NewWell {
+    name: String,
+    #[serde(default, rename = "type")]
     type_: Option<String>,
+    #[serde(default)]
     comment: Option<String>,
 }
 
-pub async fn list_wells(ctx: Extension<ApiContext>) -> Result<Json<Vec<Well>>, Error> {
-    let wells = sqlx::query_as!(Well, "SELECT id, name, type as type_, comment FROM well")
-        .fetch_all(&ctx.db)
-        .await?;
-    Ok(Json(wells))
+pub async fn insert_well(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Json(well): Json<NewWell>,
+) -> Result<Json<i64>, Error> {
+    session.require(Role::DataManager)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    let id = sqlx::query!(
+        "INSERT INTO well (name, type, comment) VALUES ($1, $2, $3) RETURNING id",
+        well.name,
+        well.type_,
+        well.comment
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    index_pending_notes(&mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(id.id))
+}
+
+fn optional_f64(key: &str, value: &serde_json::Value) -> Result<Option<f64>, Error> {
+    if value.is_null() {
+        Ok(None)
+    } else {
+        Ok(Some(value.as_f64().ok_or_else(|| {
+            anyhow::anyhow!("Invalid value for {}: {:?}", key, value)
+        })?))
+    }
+}
+
+pub async fn update_well(
+    ctx: Extension<ApiContext>,
+    session: Session,
+    Path(well_id): Path<i64>,
+    Json(update): Json<HashMap<String, serde_json::Value>>,
+) -> Result<(), Error> {
+    session.require(Role::DataManager)?;
+
+    let mut tx = ctx.db.begin().await?;
+
+    for (key, value) in update {
+        match key.as_str() {
+            "name" => {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid value for name: {:?}", value))?;
+                sqlx::query!("UPDATE well SET name = $1 WHERE id = $2", value, well_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            "type" => {
+                let value = value.as_str();
+                sqlx::query!("UPDATE well SET type = $1 WHERE id = $2", value, well_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            "comment" => {
+                let value = value.as_str();
+                sqlx::query!("UPDATE well SET comment = $1 WHERE id = $2", value, well_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            "x" => {
+                let value = optional_f64(&key, &value)?;
+                sqlx::query!("UPDATE well SET x = $1 WHERE id = $2", value, well_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            "y" => {
+                let value = optional_f64(&key, &value)?;
+                sqlx::query!("UPDATE well SET y = $1 WHERE id = $2", value, well_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            "crs" => {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid value for crs: {:?}", value))?;
+                sqlx::query!("UPDATE well SET crs = $1 WHERE id = $2", value, well_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            "ground_elevation" => {
+                let value = optional_f64(&key, &value)?;
+                sqlx::query!(
+                    "UPDATE well SET ground_elevation = $1 WHERE id = $2",
+                    value,
+                    well_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "casing_top_elevation" => {
+                let value = optional_f64(&key, &value)?;
+                sqlx::query!(
+                    "UPDATE well SET casing_top_elevation = $1 WHERE id = $2",
+                    value,
+                    well_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "casing_diameter" => {
+                let value = optional_f64(&key, &value)?;
+                sqlx::query!(
+                    "UPDATE well SET casing_diameter = $1 WHERE id = $2",
+                    value,
+                    well_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "total_depth" => {
+                let value = optional_f64(&key, &value)?;
+                sqlx::query!(
+                    "UPDATE well SET total_depth = $1 WHERE id = $2",
+                    value,
+                    well_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "screen_top" => {
+                let value = optional_f64(&key, &value)?;
+                sqlx::query!(
+                    "UPDATE well SET screen_top = $1 WHERE id = $2",
+                    value,
+                    well_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "screen_bottom" => {
+                let value = optional_f64(&key, &value)?;
+                sqlx::query!(
+                    "UPDATE well SET screen_bottom = $1 WHERE id = $2",
+                    value,
+                    well_id
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            "aquifer" => {
+                let value = value.as_str();
+                sqlx::query!("UPDATE well SET aquifer = $1 WHERE id = $2", value, well_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            _ => {
+                return Err(anyhow::anyhow!("Invalid column: {:?}", key).into());
+            }
+        }
+    }
+
+    index_pending_notes(&mut tx).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Wells as a GeoJSON FeatureCollection. GeoJSON requires WGS 84, wells with
+/// coordinates in another CRS are left out and listed in the
+/// `X-Unprojected-Wells` header.
+pub async fn get_wells_geojson(ctx: Extension<ApiContext>) -> Result<impl IntoResponse, Error> {
+    let (wells, headers) = fetch_mapped_wells(&ctx, "application/geo+json").await?;
+    let features: Vec<serde_json::Value> = wells
+        .into_iter()
+        .map(|(well, (lon, lat))| {
+            serde_json::json!({
+                "type": "Feature",
+                "id": well.id,
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [lon, lat],
+                },
+                "properties": well,
+            })
+        })
+        .collect();
+
+    let collection = serde_json::json!({
+        "type": "FeatureCollection",
+        "features": features,
+    });
+
+    Ok((headers, serde_json::to_string(&collection)?))
+}
+
+/// Wells as KML placemarks, with the same CRS restriction as GeoJSON.
+pub async fn get_wells_kml(ctx: Extension<ApiContext>) -> Result<impl IntoResponse, Error> {
+    let (wells, headers) = fetch_mapped_wells(&ctx, "application/vnd.google-earth.kml+xml").await?;
+    let mut kml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#,
+        "\n<Document>\n<name>Wells</name>\n",
+    ));
+
+    for (well, (lon, lat)) in wells {
+        let data = [
+            ("type", well.type_.clone()),
+            ("aquifer", well.aquifer.clone()),
+            (
+                "ground_elevation",
+                well.ground_elevation.map(|v| v.to_string()),
+            ),
+            (
+                "casing_top_elevation",
+                well.casing_top_elevation.map(|v| v.to_string()),
+            ),
+            (
+                "casing_diameter",
+                well.casing_diameter.map(|v| v.to_string()),
+            ),
+            ("total_depth", well.total_depth.map(|v| v.to_string())),
+            ("screen_top", well.screen_top.map(|v| v.to_string())),
+            ("screen_bottom", well.screen_bottom.map(|v| v.to_string())),
+        ];
+
+        kml.push_str(&format!(
+            "<Placemark id=\"well-{}\">\n<name>{}</name>\n",
+            well.id,
+            escape_xml(&well.name)
+        ));
+        if let Some(comment) = &well.comment {
+            kml.push_str(&format!(
+                "<description>{}</description>\n",
+                escape_xml(comment)
+            ));
+        }
+        kml.push_str("<ExtendedData>\n");
+        for (name, value) in data {
+            if let Some(value) = value {
+                kml.push_str(&format!(
+                    "<Data name=\"{}\"><value>{}</value></Data>\n",
+                    name,
+                    escape_xml(&value)
+                ));
+            }
+        }
+        kml.push_str("</ExtendedData>\n");
+        kml.push_str(&format!(
+            "<Point><coordinates>{},{}</coordinates></Point>\n</Placemark>\n",
+            lon, lat
+        ));
+    }
+
+    kml.push_str("</Document>\n</kml>\n");
+
+    Ok((headers, kml))
 }
@@ -1,18 +1,19 @@
 pub mod api;
 pub mod frontend;
 
+use std::io::Write;
 use std::net::SocketAddr;
 
 use axum::extract::DefaultBodyLimit;
 use axum::http::{header, HeaderValue, Method};
-use axum::routing::{delete, get, post, put, Router};
+use axum::routing::{delete, get, patch, post, put, Router};
 use axum::Extension;
 use clap::Parser;
 use sqlx::sqlite::SqlitePool;
 use tower_http::cors::CorsLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::api::{error::Error, ApiContext};
+use crate::api::{error::Error, ApiContext, Settings};
 use crate::frontend::{index_handler, static_handler};
 
 #[derive(Parser)]
@@ -32,6 +33,23 @@ struct Args {
     /// Skip open browser on start
     #[clap(long, default_value = "false")]
     no_open: bool,
+
+    /// Minimum number of well volumes to purge before sampling
+    #[clap(long, default_value = "3")]
+    min_purge_volumes: f64,
+
+    /// Largest allowed water level drawdown while purging, in metres
+    #[clap(long, default_value = "0.1")]
+    max_drawdown: f64,
+
+    /// Project time zone, like "Europe/Berlin", for times without an offset. Defaults to UTC
+    #[clap(long)]
+    time_zone: Option<chrono_tz::Tz>,
+
+    /// Make a data manager of the person with this name, added if missing, asking for a PIN or
+    /// password, and exit
+    #[clap(long, value_name = "NAME")]
+    create_admin: Option<String>,
 }
 
 #[tokio::main]
@@ -47,17 +65,227 @@ async fn main() -> Result<(), Error> {
     // Setup database
     let pool = SqlitePool::connect(&format!("sqlite://{}", cli_args.database)).await?;
 
+    if let Some(name) = &cli_args.create_admin {
+        print!("PIN or password for {}: ", name);
+        std::io::stdout()
+            .flush()
+            .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        let mut secret = String::new();
+        std::io::stdin()
+            .read_line(&mut secret)
+            .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+
+        let people_id =
+            api::auth::create_admin(&pool, name, secret.trim_end_matches(['\r', '\n'])).await?;
+        println!("{} (people {}) is a data manager", name, people_id);
+        return Ok(());
+    }
+
+    api::time_zone::convert_to_utc(&pool, cli_args.time_zone).await?;
+    api::search::index_pending_notes(&mut *pool.acquire().await?).await?;
+
     // Server routes
     let app = Router::new()
-        .route("/api/well", get(api::well::list_wells))
+        .route(
+            "/api/well",
+            get(api::well::list_wells).put(api::well::insert_well),
+        )
+        .route("/api/well.geojson", get(api::well::get_wells_geojson))
+        .route("/api/well.kml", get(api::well::get_wells_kml))
+        .route("/api/well/{well_id}", patch(api::well::update_well))
         .route("/api/pump", get(api::pump::list_pumps))
         .route("/api/sample_type", get(api::sample_type::list_sample_types))
         .route("/api/people", get(api::people::list_people))
+        .route("/api/people/{people_id}", patch(api::people::update_people))
+        .route("/api/auth/login", post(api::auth::login))
+        .route("/api/auth/logout", post(api::auth::logout))
+        .route("/api/auth/session", get(api::auth::get_session))
+        .route("/api/auth/secret", put(api::auth::update_secret))
+        .route(
+            "/api/auth/token",
+            get(api::auth::list_api_tokens).put(api::auth::insert_api_token),
+        )
+        .route(
+            "/api/auth/token/{token_id}",
+            delete(api::auth::delete_api_token),
+        )
+        .route(
+            "/api/baro",
+            get(api::compensation::list_baro_series).put(api::compensation::insert_baro_series),
+        )
+        .route(
+            "/api/baro/{series_id}",
+            delete(api::compensation::delete_baro_series),
+        )
+        .route(
+            "/api/deployment",
+            get(api::deployment::list_deployments).put(api::deployment::insert_deployment),
+        )
+        .route(
+            "/api/deployment/{deployment_id}",
+            delete(api::deployment::delete_deployment).patch(api::deployment::update_deployment),
+        )
+        .route(
+            "/api/deployment/{deployment_id}/download",
+            get(api::deployment::list_downloads).post(api::deployment::append_download),
+        )
+        .route(
+            "/api/deployment/{deployment_id}/data",
+            get(api::deployment::get_readings),
+        )
+        .route(
+            "/api/deployment/{deployment_id}/gaps",
+            get(api::deployment::get_gaps),
+        )
+        .route(
+            "/api/calibration",
+            get(api::calibration::list_calibrations).put(api::calibration::insert_calibration),
+        )
+        .route(
+            "/api/calibration/{calibration_id}",
+            delete(api::calibration::delete_calibration)
+                .patch(api::calibration::update_calibration),
+        )
+        .route(
+            "/api/campaign",
+            get(api::campaign::list_campaigns).put(api::campaign::insert_campaign),
+        )
+        .route(
+            "/api/campaign/{campaign_id}",
+            delete(api::campaign::delete_campaign).patch(api::campaign::update_campaign),
+        )
+        .route(
+            "/api/campaign/{campaign_id}/clone",
+            post(api::campaign::clone_campaign),
+        )
+        .route(
+            "/api/equipment",
+            get(api::equipment::list_equipment).put(api::equipment::insert_equipment),
+        )
+        .route(
+            "/api/equipment/overdue",
+            get(api::equipment::list_overdue_equipment),
+        )
+        .route(
+            "/api/equipment/{equipment_id}",
+            delete(api::equipment::delete_equipment).patch(api::equipment::update_equipment),
+        )
+        .route(
+            "/api/equipment/{equipment_id}/usage",
+            get(api::equipment::get_equipment_usage),
+        )
+        .route("/api/export", get(api::export::export_tasks))
+        .route(
+            "/api/purge/insufficient",
+            get(api::purge::list_insufficient_purges),
+        )
+        .route("/api/qc_threshold", get(api::anomaly::list_qc_thresholds))
+        .route(
+            "/api/qc_threshold/{parameter}",
+            patch(api::anomaly::update_qc_threshold),
+        )
+        .route(
+            "/api/report_template",
+            get(api::report::list_report_templates).put(api::report::insert_report_template),
+        )
+        .route(
+            "/api/report_template/{name}",
+            delete(api::report::delete_report_template).patch(api::report::update_report_template),
+        )
+        .route("/api/search", get(api::search::search_notes))
         .route("/api/task", put(api::task::insert_task))
         .route(
             "/api/task/{task_id}",
             delete(api::task::delete_task).patch(api::task::update_task),
         )
+        .route(
+            "/api/task/{task_id}/annotation",
+            get(api::annotation::list_annotations).put(api::annotation::insert_annotation),
+        )
+        .route(
+            "/api/task/{task_id}/annotation/{annotation_id}",
+            delete(api::annotation::delete_annotation).patch(api::annotation::update_annotation),
+        )
+        .route(
+            "/api/task/{task_id}/calibration",
+            get(api::calibration::get_task_calibration),
+        )
+        .route("/api/task/{task_id}/chart.svg", get(api::chart::get_chart))
+        .route(
+            "/api/task/{task_id}/clock_offset",
+            get(api::clock_offset::list_clock_offsets).post(api::clock_offset::insert_clock_offset),
+        )
+        .route(
+            "/api/task/{task_id}/compensation",
+            get(api::compensation::list_compensations).post(api::compensation::compensate),
+        )
+        .route(
+            "/api/task/{task_id}/correction",
+            get(api::correction::list_corrections).post(api::correction::insert_correction),
+        )
+        .route(
+            "/api/task/{task_id}/correction/{correction_id}/revert",
+            post(api::correction::revert_correction),
+        )
+        .route(
+            "/api/task/{task_id}/qc_flag",
+            get(api::qc::list_qc_flags).post(api::qc::insert_qc_flag),
+        )
+        .route(
+            "/api/task/{task_id}/qc_flag/{flag_id}",
+            delete(api::qc::delete_qc_flag),
+        )
+        .route(
+            "/api/task/{task_id}/qc_flag/{flag_id}/accept",
+            post(api::qc::accept_qc_flag),
+        )
+        .route(
+            "/api/task/{task_id}/equipment",
+            get(api::equipment::get_task_equipment).put(api::equipment::add_task_equipment),
+        )
+        .route(
+            "/api/task/{task_id}/equipment/{equipment_id}",
+            delete(api::equipment::delete_task_equipment),
+        )
+        .route("/api/task/{task_id}/export", get(api::export::export_task))
+        .route(
+            "/api/task/{task_id}/field_parameters",
+            get(api::field_parameters::get_field_parameters),
+        )
+        .route(
+            "/api/task/{task_id}/field_sheet.pdf",
+            get(api::field_sheet::get_field_sheet),
+        )
+        .route(
+            "/api/task/{task_id}/finalize",
+            get(api::finalize::get_finalization).post(api::finalize::finalize_task),
+        )
+        .route(
+            "/api/task/{task_id}/reopen",
+            post(api::finalize::reopen_task),
+        )
+        .route(
+            "/api/task/{task_id}/purge",
+            get(api::purge::get_purge_volumes),
+        )
+        .route(
+            "/api/task/{task_id}/report/{template}",
+            get(api::report::get_report),
+        )
+        .route(
+            "/api/task/{task_id}/water_level",
+            get(api::water_level::get_water_level_log)
+                .put(api::water_level::insert_water_level_reading),
+        )
+        .route(
+            "/api/task/{task_id}/water_level/{reading_id}",
+            delete(api::water_level::delete_water_level_reading)
+                .patch(api::water_level::update_water_level_reading),
+        )
+        .route(
+            "/api/task/{task_id}/status",
+            get(api::task_status::get_status_log).post(api::task_status::update_status),
+        )
         .route(
             "/api/task/{task_id}/sample_set",
             get(api::task::get_sample_set).patch(api::task::update_sample_set),
@@ -80,6 +308,18 @@ async fn main() -> Result<(), Error> {
             "/api/task/{task_id}/info/{task_info_id}",
             delete(api::task_info::delete_task_info).patch(api::task_info::update_task_info),
         )
+        .route(
+            "/api/task/{task_id}/info/{task_info_id}/attachment",
+            get(api::attachment::list_attachments).post(api::attachment::insert_attachments),
+        )
+        .route(
+            "/api/task/{task_id}/info/{task_info_id}/attachment/{attachment_id}",
+            get(api::attachment::get_attachment).delete(api::attachment::delete_attachment),
+        )
+        .route(
+            "/api/task/{task_id}/info/{task_info_id}/attachment/{attachment_id}/thumbnail",
+            get(api::attachment::get_attachment_thumbnail),
+        )
         .route(
             "/api/task/{task_id}/info/{task_info_id}/minuted_by",
             get(api::task_info::get_minuted_by).put(api::task_info::add_minuted_by),
@@ -100,14 +340,24 @@ async fn main() -> Result<(), Error> {
             "/api/task/last_timestamp",
             get(api::task_info::get_last_timestamp),
         )
-        .route("/api/task/summary", get(api::task::list_task_summaries))
+        .route(
+            "/api/task/summary",
+            get(api::task_summary::list_task_summaries),
+        )
         .route(
             "/sensor_log/upload",
             post(api::sensor_data::insitu_log_handler),
         )
         .route("/", get(index_handler))
         .route("/{*path}", get(static_handler))
-        .layer(Extension(ApiContext::new(pool)))
+        .layer(Extension(ApiContext::new(
+            pool,
+            Settings {
+                min_purge_volumes: cli_args.min_purge_volumes,
+                max_drawdown: cli_args.max_drawdown,
+                time_zone: cli_args.time_zone.unwrap_or(chrono_tz::Tz::UTC),
+            },
+        )))
         .layer(DefaultBodyLimit::max(100 * 1000 * 1000))
         .layer(
             CorsLayer::new()
@@ -120,7 +370,8 @@ async fn main() -> Result<(), Error> {
                     Method::PUT,
                     Method::DELETE,
                 ])
-                .allow_headers([header::CONTENT_TYPE]),
+                .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+                .allow_credentials(true),
         );
     // Start server
     let addr: SocketAddr = format!("{}:{}", cli_args.bind_address, cli_args.port)
@@ -2,7 +2,10 @@ import axios from 'axios'
 import type { AxiosResponse } from 'axios'
 import { toast } from 'svelte-sonner'
 
-import { apiUrl } from '$lib/shared-variables.svelte'
+import { apiUrl, auth } from '$lib/shared-variables.svelte'
+
+// Send the session cookie, also to the API server during frontend development
+axios.defaults.withCredentials = true
 
 async function handler(
   axiosReturn: Promise<AxiosResponse<any, any>>,
@@ -17,7 +20,12 @@ async function handler(
     })
     .catch((err) => {
       console.error(err)
-      toast.error(err.message)
+      if (err.response?.status === 401) {
+        // The session expired or was never there, show the login view
+        auth.session = null
+      } else {
+        toast.error(err.message)
+      }
       if (onError) {
         return onError(err)
       }
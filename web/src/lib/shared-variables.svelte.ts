@@ -1,4 +1,9 @@
-import { type OptionsData, type TaskSummary, type TaskInfo } from './types.ts'
+import {
+  type OptionsData,
+  type Session,
+  type TaskSummary,
+  type TaskInfo,
+} from './types.ts'
 
 export const sharedOptions: OptionsData = $state({
   well: [],
@@ -7,6 +12,9 @@ export const sharedOptions: OptionsData = $state({
   people: [],
 })
 
+// The logged in person, null until the login succeeds
+export const auth: { session: Session | null } = $state({ session: null })
+
 export const selectedTask: TaskSummary[] = $state([])
 export const selectedTaskInfo: TaskInfo[] = $state([])
 
@@ -20,6 +20,12 @@ export type People = {
   name: string
 }
 
+export type Session = {
+  people_id: number
+  name: string
+  role: 'viewer' | 'field_tech' | 'data_manager'
+}
+
 export type OptionsData = {
   well: Well[]
   pump: Pump[]